@@ -0,0 +1,93 @@
+//! Lexer and parser throughput of the version2 front end, on generated programs.
+
+#![allow(dead_code)]
+
+use std::fmt::Write as _;
+use std::hint::black_box;
+use std::io;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[path = "../src/version2/ast.rs"]
+mod ast;
+#[path = "../src/version2/diagnostics.rs"]
+mod diagnostics;
+#[path = "../src/version2/lexer.rs"]
+mod lexer;
+#[path = "../src/version2/parser.rs"]
+mod parser;
+
+use diagnostics::DiagnosticEmitter;
+use lexer::{Lexer, SourceMap, Token};
+use parser::Parser;
+
+/// Declarations in each generated program.
+const DECLS: usize = 2000;
+
+/// A valid program of `DECLS` structs and functions using them.
+fn program() -> String {
+	let mut src = String::new();
+	for i in 0..DECLS {
+		writeln!(src, "Point{0} = struct {{\n\tx: i32,\n\ty: i32 = {0}\n}}\n", i).unwrap();
+		writeln!(src, "length{0} = (p: Point{0}, scale: i32) -> i32 {{\n\
+			\tsum: i32 = p.x * p.x + p.y * p.y;\n\
+			\tif sum > 100 && scale != 0 {{\n\t\tret sum / scale;\n\t}}\n\
+			\tret sum + {0};\n}}\n", i).unwrap();
+	}
+	src
+}
+
+/// A program with several syntax errors in each of its `DECLS` functions.
+fn error_program() -> String {
+	let mut src = String::new();
+	for i in 0..DECLS {
+		writeln!(src, "broken{} = (a: i32, b: i32) -> i32 {{\n\tx: i32 = a * / b;\n\ty = (a + ;\n\tret x y;\n}}\n", i)
+			.unwrap();
+	}
+	src
+}
+
+fn parse<W: io::Write>(src: &str, emitter: &DiagnosticEmitter<W>) -> Vec<ast::Expr> {
+	let lexer = Lexer::new(src, emitter);
+	Parser::new(lexer, emitter).parse()
+}
+
+fn bench_lex(c: &mut Criterion) {
+	let src = program();
+	let map = SourceMap::new("bench", &src);
+	let mut group = c.benchmark_group("lex");
+	group.throughput(Throughput::Bytes(src.len() as u64));
+	group.bench_function("program", |b| b.iter(|| {
+		let emitter = diagnostics::collecting(&map);
+		black_box(Lexer::new(black_box(&src), &emitter).tokenize_all())
+	}));
+	group.finish();
+}
+
+fn bench_parse(c: &mut Criterion) {
+	let src = program();
+	let map = SourceMap::new("bench", &src);
+	let mut group = c.benchmark_group("lex+parse");
+	group.throughput(Throughput::Bytes(src.len() as u64));
+	group.bench_function("program", |b| b.iter(|| {
+		let emitter = diagnostics::collecting(&map);
+		black_box(parse(black_box(&src), &emitter))
+	}));
+	group.finish();
+}
+
+/// Parses an error-heavy program, rendering every diagnostic as uncolored human output.
+fn bench_diagnostics(c: &mut Criterion) {
+	let src = error_program();
+	let map = SourceMap::new("bench", &src);
+	let mut group = c.benchmark_group("diagnostics");
+	group.throughput(Throughput::Bytes(src.len() as u64));
+	group.bench_function("errors", |b| b.iter(|| {
+		let emitter = DiagnosticEmitter::new(&map, io::sink()).with_color(false);
+		black_box(parse(black_box(&src), &emitter));
+		black_box(emitter.take_diagnostics())
+	}));
+	group.finish();
+}
+
+criterion_group!(benches, bench_lex, bench_parse, bench_diagnostics);
+criterion_main!(benches);
@@ -52,5 +52,6 @@ fn main() {
 					.eprint(("tests/test2.lang", Source::from(&src)))
 					.unwrap();
 		}
+		std::process::exit(1);
 	}
 }
\ No newline at end of file
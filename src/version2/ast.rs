@@ -2,15 +2,171 @@ use crate::diagnostics::Span;
 
 pub type Spanned<T> = (T, Span);
 
-#[derive(Debug, Clone)]
+/// A float literal's value. Compared and hashed by its bits so that `Expr` can be
+/// `Eq` and `Hash`.
+#[derive(Debug, Copy, Clone)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.to_bits() == other.0.to_bits()
+	}
+}
+
+impl Eq for Float {}
+
+impl std::hash::Hash for Float {
+	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+		self.0.to_bits().hash(state);
+	}
+}
+
+pub fn spanned<T>(value: T, span: Span) -> Spanned<T> {
+	(value, span)
+}
+
+/// Named access to the parts of a `Spanned` instead of `.0` and `.1`.
+pub trait SpannedExt<T> {
+	fn value(&self) -> &T;
+	fn span(&self) -> Span;
+	/// Transforms the value, keeping the span.
+	fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U>;
+}
+
+impl<T> SpannedExt<T> for Spanned<T> {
+	fn value(&self) -> &T {
+		&self.0
+	}
+
+	fn span(&self) -> Span {
+		self.1.clone()
+	}
+
+	fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+		(f(self.0), self.1)
+	}
+}
+
+fn erase<T>(spanned: &mut Spanned<T>) {
+	spanned.1 = 0..0;
+}
+
+/// The functions the parser handles itself, which take a type in their parentheses.
+pub const INTRINSICS: &[&str] = &["sizeof", "alignof"];
+
+/// The type names provided by the language.
+pub const BUILTIN_TYPES: &[&str] = &["int", "bool", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+	Named(Spanned<String>),
+	Optional(Box<Type>),
+	/// `(A, B)` or `(A,)`, spanning the parentheses.
+	Tuple(Spanned<Vec<Type>>),
+	/// `[T; N]`, spanning the brackets.
+	Array(Box<Type>, ArrayLen, Span)
+}
+
+/// The length of an array type, a number or the name of a `const`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArrayLen {
+	Num(Spanned<u64>),
+	Const(Spanned<String>)
+}
+
+impl Type {
+	/// The bit count of a builtin integer type.
+	pub fn int_bits(&self) -> Option<u64> {
+		match self {
+			Type::Named((name, _)) => match name.as_str() {
+				"i8" | "u8" => Some(8),
+				"i16" | "u16" => Some(16),
+				"i32" | "u32" => Some(32),
+				"i64" | "u64" => Some(64),
+				_ => None
+			},
+			_ => None
+		}
+	}
+
+	/// The span of the type's name. The `?` of an optional type isn't recorded.
+	pub fn span(&self) -> Span {
+		match self {
+			Type::Named((_, span)) | Type::Tuple((_, span)) | Type::Array(_, _, span) => span.clone(),
+			Type::Optional(inner) => inner.span()
+		}
+	}
+
+	fn erase_spans(&mut self) {
+		match self {
+			Type::Named(name) => erase(name),
+			Type::Optional(inner) => inner.erase_spans(),
+			Type::Tuple(tuple) => {
+				tuple.0.iter_mut().for_each(Type::erase_spans);
+				erase(tuple);
+			}
+			Type::Array(element, len, span) => {
+				element.erase_spans();
+				match len {
+					ArrayLen::Num(num) => erase(num),
+					ArrayLen::Const(name) => erase(name)
+				}
+				*span = 0..0;
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Field {
+	pub name: Spanned<String>,
+	pub r#type: Type,
+	pub doc: Option<String>,
+	/// The bit width of a bitfield member (`flags: u8 : 3`).
+	pub width: Option<Spanned<u64>>,
+	/// The value used when a construct leaves the field out (`count: i32 = 0`).
+	pub default: Option<Expr>
+}
+
+/// `#[name]` or `#[name(args)]` before a declaration.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Attribute {
+	pub name: Spanned<String>,
+	pub args: Vec<Expr>
+}
+
+/// An expression compared and hashed by structure alone, so the same expression at
+/// different places in the source is equal. Useful as a `HashMap` key when memoizing
+/// subexpressions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IgnoreSpans(Expr);
+
+impl IgnoreSpans {
+	pub fn new(expr: &Expr) -> Self {
+		let mut expr = expr.clone();
+		expr.erase_spans();
+		Self(expr)
+	}
+}
+
+/// An expression or statement. Nodes with a keyword, operator or delimiter that no
+/// child covers, like the `-` of `-x` or the `)` of a call, record their whole span
+/// in `span` or as their last tuple field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expr {
 	Error,
 	Var(Spanned<String>),
 	Num(Spanned<u64>),
+	/// `3.14` or `1e9`
+	FloatLiteral(Spanned<Float>),
 	CharLiteral(Spanned<String>),
 	StringLiteral(Spanned<String>),
 
-	Neg(Box<Expr>),
+	Neg(Box<Expr>, Span),
+	/// Unary plus, `+expr`, which leaves the value unchanged.
+	Pos(Box<Expr>, Span),
+	/// Logical not, `!expr`.
+	Not(Box<Expr>, Span),
 	Add(Box<Expr>, Box<Expr>),
 	Sub(Box<Expr>, Box<Expr>),
 	Mul(Box<Expr>, Box<Expr>),
@@ -18,41 +174,445 @@ pub enum Expr {
 	Mod(Box<Expr>, Box<Expr>),
 	And(Box<Expr>, Box<Expr>),
 	Or(Box<Expr>, Box<Expr>),
+	BitAnd(Box<Expr>, Box<Expr>),
+	BitOr(Box<Expr>, Box<Expr>),
+	Shl(Box<Expr>, Box<Expr>),
+	Shr(Box<Expr>, Box<Expr>),
+	Eq(Box<Expr>, Box<Expr>),
+	Ne(Box<Expr>, Box<Expr>),
+	Lt(Box<Expr>, Box<Expr>),
+	Gt(Box<Expr>, Box<Expr>),
+	Le(Box<Expr>, Box<Expr>),
+	Ge(Box<Expr>, Box<Expr>),
+
+	/// `start..end` or `start..=end`, where either bound may be left out except the end
+	/// of an inclusive range.
+	Range {
+		start: Option<Box<Expr>>,
+		end: Option<Box<Expr>>,
+		inclusive: bool,
+		span: Span
+	},
 
+	Cast {
+		expr: Box<Expr>,
+		ty: Type
+	},
+
+	/// `sizeof(Type)`
+	SizeOf(Type, Span),
+	/// `alignof(Type)`
+	AlignOf(Type, Span),
+
+	/// Evaluates to the assigned value, which can be used in a larger expression when
+	/// the assignment is parenthesized: `b = (a = 1) + 2`.
 	Assign {
 		target: Box<Expr>,
-		value: Box<Expr>
+		value: Box<Expr>,
+		/// From the start of the target to the end of the value, covering the `=`.
+		span: Span
 	},
 
 	Struct {
 		name: Spanned<String>,
-		fields: Vec<(Spanned<String>, Spanned<String>)>
+		/// The type composed into this one with `struct : Base { .. }`.
+		base: Option<Type>,
+		fields: Vec<Field>,
+		attributes: Vec<Attribute>,
+		/// From the name to the closing `}`.
+		span: Span
 	},
 
 	Function {
 		name: Spanned<String>,
-		args: Vec<(Spanned<String>, Spanned<String>)>,
-		ret_type: Spanned<String>,
-		body: Option<Vec<Expr>>
+		/// The `T, U` of `f[T, U] = (..) { .. }`.
+		type_params: Vec<Spanned<String>>,
+		/// The `where T: Num` constraints on the type parameters, as (parameter, bound)
+		/// pairs. They are recorded but not checked.
+		constraints: Vec<(Spanned<String>, Spanned<String>)>,
+		args: Vec<(Spanned<String>, Type)>,
+		ret_type: Option<Type>,
+		body: Option<Vec<Expr>>,
+		attributes: Vec<Attribute>,
+		/// Declared with `extern`, so it is defined elsewhere and has no body.
+		is_extern: bool,
+		/// From the name to the closing `}` of the body, or to the end of the signature.
+		span: Span
 	},
 
 	VarDecl {
 		name: Spanned<String>,
-		r#type: Spanned<String>,
+		r#type: Type,
+		value: Option<Box<Expr>>
+	},
+
+	/// `a, b: type [= value]`. The initializer is evaluated once and its value assigned
+	/// to every name.
+	MultiVarDecl {
+		names: Vec<Spanned<String>>,
+		r#type: Type,
 		value: Option<Box<Expr>>
 	},
 
 	Construct {
 		name: Spanned<String>,
-		fields: Vec<(Spanned<String>, Box<Expr>)>
+		fields: Vec<(Spanned<String>, Box<Expr>)>,
+		span: Span
 	},
 
-	FieldAccess {
+	/// `(1, 2)` or the one-element `(1,)`.
+	Tuple(Vec<Expr>, Span),
+
+	/// `[1, 2, 3]`
+	Array(Vec<Expr>, Span),
+
+	/// `Foo { 1, 2 }`, with values in the order of the struct's fields.
+	PositionalConstruct {
 		name: Spanned<String>,
+		values: Vec<Expr>,
+		span: Span
+	},
+
+	FieldAccess {
+		base: Box<Expr>,
 		field: Spanned<String>
 	},
 
+	/// `base[index]`
+	Index {
+		base: Box<Expr>,
+		index: Box<Expr>,
+		span: Span
+	},
+
+	/// `name[T, U]` without a call, only valid as the name of a generic declaration.
+	Generic {
+		name: Spanned<String>,
+		type_args: Vec<Type>,
+		span: Span
+	},
+
+	/// `callee[type_args](args, .name = value)`, where the named arguments come after the
+	/// positional ones. Type arguments are only parsed after a plain name.
+	Call {
+		callee: Box<Expr>,
+		type_args: Vec<Type>,
+		args: Vec<Expr>,
+		named_args: Vec<(Spanned<String>, Expr)>,
+		span: Span
+	},
+
 	Ret {
-		value: Option<Box<Expr>>
+		value: Option<Box<Expr>>,
+		span: Span
+	},
+
+	/// `if cond { .. } else { .. }`. An `else if` is an else body holding just the nested `If`.
+	If {
+		cond: Box<Expr>,
+		then_body: Vec<Expr>,
+		else_body: Option<Vec<Expr>>,
+		span: Span
+	},
+
+	/// `for init; cond; step { .. }`, where each of the three clauses may be empty.
+	For {
+		init: Option<Box<Expr>>,
+		cond: Option<Box<Expr>>,
+		step: Option<Box<Expr>>,
+		body: Vec<Expr>,
+		span: Span
+	},
+
+	/// `assert cond;` or `assert cond, message;`
+	Assert {
+		cond: Box<Expr>,
+		message: Option<Box<Expr>>,
+		span: Span
+	},
+
+	/// `'label: { .. }`, which evaluates to the value of a `break 'label value` inside it.
+	Block {
+		label: Spanned<String>,
+		body: Vec<Expr>,
+		span: Span
+	},
+
+	/// `break 'label [value]`
+	Break {
+		label: Spanned<String>,
+		value: Option<Box<Expr>>,
+		span: Span
+	},
+
+	/// The last expression of a block when it has no `;`, which is the block's value:
+	/// the `a + 1` of `{ b = 2; a + 1 }`.
+	Tail {
+		value: Box<Expr>
+	},
+
+	Script {
+		body: Vec<Expr>
+	},
+
+	/// `import "path";` or `import "path" as alias;` at top level.
+	Import {
+		path: Spanned<String>,
+		alias: Option<Spanned<String>>,
+		span: Span
+	},
+
+	/// `const NAME = value;`, spanning from `const` to the end of the value.
+	Const {
+		name: Spanned<String>,
+		value: Box<Expr>,
+		span: Span
+	}
+}
+impl Expr {
+	/// The expressions directly nested in this one, in source order.
+	pub fn children(&self) -> Vec<&Expr> {
+		match self {
+			Expr::Error | Expr::Var(_) | Expr::Num(_) | Expr::FloatLiteral(_) | Expr::CharLiteral(_) | Expr::StringLiteral(_)
+			| Expr::SizeOf(..) | Expr::AlignOf(..) | Expr::Generic {..} | Expr::Import {..} => Vec::new(),
+			Expr::Struct {fields, ..} => fields.iter().filter_map(|field| field.default.as_ref()).collect(),
+			Expr::Neg(expr, _) | Expr::Pos(expr, _) | Expr::Not(expr, _) | Expr::Cast {expr, ..} | Expr::FieldAccess {base: expr, ..} => vec![expr],
+			Expr::Index {base, index, ..} => vec![base, index],
+			Expr::Tail {value} | Expr::Const {value, ..} => vec![value],
+			Expr::Assert {cond, message, ..} => std::iter::once(&**cond).chain(message.as_deref()).collect(),
+			Expr::If {cond, then_body, else_body, ..} =>
+				std::iter::once(&**cond).chain(then_body).chain(else_body.iter().flatten()).collect(),
+			Expr::For {init, cond, step, body, ..} =>
+				init.iter().chain(cond).chain(step).map(|expr| &**expr).chain(body).collect(),
+			Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) | Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs)
+			| Expr::Mod(lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs)
+			| Expr::BitAnd(lhs, rhs) | Expr::BitOr(lhs, rhs) | Expr::Shl(lhs, rhs) | Expr::Shr(lhs, rhs)
+			| Expr::Eq(lhs, rhs) | Expr::Ne(lhs, rhs) | Expr::Lt(lhs, rhs) | Expr::Gt(lhs, rhs)
+			| Expr::Le(lhs, rhs) | Expr::Ge(lhs, rhs) => vec![lhs, rhs],
+			Expr::Range {start, end, ..} => start.iter().chain(end).map(|expr| &**expr).collect(),
+			Expr::Assign {target, value, ..} => vec![target, value],
+			Expr::Function {body, ..} => body.iter().flatten().collect(),
+			Expr::VarDecl {value, ..} | Expr::MultiVarDecl {value, ..} | Expr::Ret {value, ..} | Expr::Break {value, ..} =>
+				value.iter().map(|expr| &**expr).collect(),
+			Expr::Construct {fields, ..} => fields.iter().map(|(_, expr)| &**expr).collect(),
+			Expr::PositionalConstruct {values, ..} | Expr::Tuple(values, _) | Expr::Array(values, _) => values.iter().collect(),
+			Expr::Call {callee, args, named_args, ..} =>
+				std::iter::once(&**callee).chain(args).chain(named_args.iter().map(|(_, expr)| expr)).collect(),
+			Expr::Block {body, ..} | Expr::Script {body} => body.iter().collect()
+		}
+	}
+
+	/// The full extent of this expression, like `4..9` for the `1 + 2` of `a = 1 + 2;`
+	/// or `4..10` for the `f(x)` of `a = f(x);`.
+	/// `None` if the expression holds no spans, like `Expr::Error`.
+	pub fn span(&self) -> Option<Span> {
+		let mut spans = self.own_spans();
+		spans.extend(self.children().into_iter().filter_map(Expr::span));
+		let start = spans.iter().map(|span| span.start).min()?;
+		let end = spans.iter().map(|span| span.end).max()?;
+		Some(start..end)
+	}
+
+	/// The spans held directly by this expression rather than by its children.
+	fn own_spans(&self) -> Vec<Span> {
+		match self {
+			Expr::Var((_, span)) | Expr::CharLiteral((_, span)) | Expr::StringLiteral((_, span))
+			| Expr::Num((_, span)) | Expr::FloatLiteral((_, span)) | Expr::FieldAccess {field: (_, span), ..} =>
+				vec![span.clone()],
+			Expr::Cast {ty, ..} => vec![ty.span()],
+			Expr::Neg(_, span) | Expr::Pos(_, span) | Expr::Not(_, span) | Expr::SizeOf(_, span) | Expr::AlignOf(_, span)
+			| Expr::Tuple(_, span) | Expr::Array(_, span) | Expr::Range {span, ..} | Expr::Construct {span, ..}
+			| Expr::PositionalConstruct {span, ..} | Expr::Index {span, ..} | Expr::Generic {span, ..}
+			| Expr::Call {span, ..} | Expr::Ret {span, ..} | Expr::If {span, ..} | Expr::For {span, ..}
+			| Expr::Assert {span, ..} | Expr::Block {span, ..} | Expr::Break {span, ..}
+			| Expr::Import {span, ..} | Expr::Const {span, ..} | Expr::Assign {span, ..}
+			| Expr::Struct {span, ..} | Expr::Function {span, ..} => vec![span.clone()],
+			Expr::VarDecl {name, r#type, ..} => vec![name.1.clone(), r#type.span()],
+			Expr::MultiVarDecl {names, r#type, ..} => {
+				let mut spans = name_spans(names.iter());
+				spans.push(r#type.span());
+				spans
+			}
+			_ => Vec::new()
+		}
+	}
+
+	fn erase_spans(&mut self) {
+		match self {
+			Expr::Error => {}
+			Expr::Var(name) | Expr::CharLiteral(name) | Expr::StringLiteral(name) => erase(name),
+			Expr::Num(num) => erase(num),
+			Expr::FloatLiteral(float) => erase(float),
+			Expr::Neg(expr, span) | Expr::Pos(expr, span) | Expr::Not(expr, span) => {
+				expr.erase_spans();
+				*span = 0..0;
+			}
+			Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) | Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs)
+			| Expr::Mod(lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs)
+			| Expr::BitAnd(lhs, rhs) | Expr::BitOr(lhs, rhs) | Expr::Shl(lhs, rhs) | Expr::Shr(lhs, rhs)
+			| Expr::Eq(lhs, rhs) | Expr::Ne(lhs, rhs) | Expr::Lt(lhs, rhs) | Expr::Gt(lhs, rhs)
+			| Expr::Le(lhs, rhs) | Expr::Ge(lhs, rhs) => {
+				lhs.erase_spans();
+				rhs.erase_spans();
+			}
+			Expr::Range {start, end, span, ..} => {
+				start.iter_mut().chain(end).for_each(|expr| expr.erase_spans());
+				*span = 0..0;
+			}
+			Expr::Cast {expr, ty} => {
+				expr.erase_spans();
+				ty.erase_spans();
+			}
+			Expr::SizeOf(ty, span) | Expr::AlignOf(ty, span) => {
+				ty.erase_spans();
+				*span = 0..0;
+			}
+			Expr::Assign {target, value, span} => {
+				target.erase_spans();
+				value.erase_spans();
+				*span = 0..0;
+			}
+			Expr::Struct {name, base, fields, attributes, span} => {
+				erase(name);
+				*span = 0..0;
+				base.iter_mut().for_each(Type::erase_spans);
+				for field in fields {
+					erase(&mut field.name);
+					field.r#type.erase_spans();
+					field.width.iter_mut().for_each(erase);
+					field.default.iter_mut().for_each(Expr::erase_spans);
+				}
+				erase_attribute_spans(attributes);
+			}
+			Expr::Function {name, type_params, constraints, args, ret_type, body, attributes, span, ..} => {
+				erase(name);
+				*span = 0..0;
+				type_params.iter_mut().for_each(erase);
+				for (param, bound) in constraints {
+					erase(param);
+					erase(bound);
+				}
+				for (name, ty) in args {
+					erase(name);
+					ty.erase_spans();
+				}
+				ret_type.iter_mut().for_each(Type::erase_spans);
+				body.iter_mut().flatten().for_each(Expr::erase_spans);
+				erase_attribute_spans(attributes);
+			}
+			Expr::VarDecl {name, r#type, value} => {
+				erase(name);
+				r#type.erase_spans();
+				value.iter_mut().for_each(|expr| expr.erase_spans());
+			}
+			Expr::MultiVarDecl {names, r#type, value} => {
+				names.iter_mut().for_each(erase);
+				r#type.erase_spans();
+				value.iter_mut().for_each(|expr| expr.erase_spans());
+			}
+			Expr::Construct {name, fields, span} => {
+				erase(name);
+				for (name, value) in fields {
+					erase(name);
+					value.erase_spans();
+				}
+				*span = 0..0;
+			}
+			Expr::PositionalConstruct {name, values, span} => {
+				erase(name);
+				values.iter_mut().for_each(Expr::erase_spans);
+				*span = 0..0;
+			}
+			Expr::Tuple(values, span) | Expr::Array(values, span) => {
+				values.iter_mut().for_each(Expr::erase_spans);
+				*span = 0..0;
+			}
+			Expr::FieldAccess {base, field} => {
+				base.erase_spans();
+				erase(field);
+			}
+			Expr::Index {base, index, span} => {
+				base.erase_spans();
+				index.erase_spans();
+				*span = 0..0;
+			}
+			Expr::Generic {name, type_args, span} => {
+				erase(name);
+				type_args.iter_mut().for_each(Type::erase_spans);
+				*span = 0..0;
+			}
+			Expr::Call {callee, type_args, args, named_args, span} => {
+				callee.erase_spans();
+				type_args.iter_mut().for_each(Type::erase_spans);
+				args.iter_mut().for_each(Expr::erase_spans);
+				for (name, value) in named_args {
+					erase(name);
+					value.erase_spans();
+				}
+				*span = 0..0;
+			}
+			Expr::Ret {value, span} => {
+				value.iter_mut().for_each(|expr| expr.erase_spans());
+				*span = 0..0;
+			}
+			Expr::If {cond, then_body, else_body, span} => {
+				cond.erase_spans();
+				then_body.iter_mut().chain(else_body.iter_mut().flatten()).for_each(Expr::erase_spans);
+				*span = 0..0;
+			}
+			Expr::For {init, cond, step, body, span} => {
+				init.iter_mut().chain(cond.iter_mut()).chain(step.iter_mut()).for_each(|expr| expr.erase_spans());
+				body.iter_mut().for_each(Expr::erase_spans);
+				*span = 0..0;
+			}
+			Expr::Assert {cond, message, span} => {
+				cond.erase_spans();
+				message.iter_mut().for_each(|expr| expr.erase_spans());
+				*span = 0..0;
+			}
+			Expr::Tail {value} => value.erase_spans(),
+			Expr::Block {label, body, span} => {
+				erase(label);
+				body.iter_mut().for_each(Expr::erase_spans);
+				*span = 0..0;
+			}
+			Expr::Break {label, value, span} => {
+				erase(label);
+				value.iter_mut().for_each(|expr| expr.erase_spans());
+				*span = 0..0;
+			}
+			Expr::Script {body} => body.iter_mut().for_each(Expr::erase_spans),
+			Expr::Import {path, alias, span} => {
+				erase(path);
+				alias.iter_mut().for_each(erase);
+				*span = 0..0;
+			}
+			Expr::Const {name, value, span} => {
+				erase(name);
+				value.erase_spans();
+				*span = 0..0;
+			}
+		}
+	}
+}
+
+/// The function declarations at the top level of `ast`.
+pub fn functions(ast: &[Expr]) -> impl Iterator<Item = &Expr> {
+	ast.iter().filter(|expr| matches!(expr, Expr::Function {..}))
+}
+
+/// The struct declarations at the top level of `ast`.
+pub fn structs(ast: &[Expr]) -> impl Iterator<Item = &Expr> {
+	ast.iter().filter(|expr| matches!(expr, Expr::Struct {..}))
+}
+
+fn name_spans<'a>(names: impl Iterator<Item = &'a Spanned<String>>) -> Vec<Span> {
+	names.map(|(_, span)| span.clone()).collect()
+}
+
+fn erase_attribute_spans(attributes: &mut [Attribute]) {
+	for attribute in attributes {
+		erase(&mut attribute.name);
+		attribute.args.iter_mut().for_each(Expr::erase_spans);
 	}
-}
\ No newline at end of file
+}
@@ -1,9 +1,10 @@
 #![allow(unused)]
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fmt::Display;
+use std::env;
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::ops::Range;
 use std::rc::Rc;
 use crate::lexer::SourceMap;
@@ -35,23 +36,51 @@ pub mod color {
 	pub const BRIGHT_WHITE: &'static str = "\x1b[97m";
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum EmitType {
 	Info,
 	Warning,
 	Error
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EmitFormat {
+	Human,
+	/// One uncolored `file:line:column: severity: message` line per diagnostic, for
+	/// editors that parse line-based output.
+	Short,
+	Json
+}
+
+/// An emitted diagnostic as data, for callers that report diagnostics themselves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+	pub severity: EmitType,
+	pub message: String,
+	pub span: Span,
+	pub help: Option<String>,
+	pub secondary: Vec<(Span, String)>
+}
+
 pub struct Emit<'source, W: Write> {
 	label: String,
 	span: Span,
+	help: Option<String>,
+	secondary: Vec<(Span, String)>,
 	emit_type: EmitType,
+	format: EmitFormat,
+	use_color: bool,
 	map: &'source SourceMap<'source>,
-	writer: Rc<RefCell<W>>
+	writer: Rc<RefCell<W>>,
+	collected: Rc<RefCell<Vec<Diagnostic>>>,
+	errors: Rc<Cell<usize>>
 }
 
 impl<'source, W: Write> Emit<'source, W> {
-	fn new(map: &'source SourceMap<'source>, writer: Rc<RefCell<W>>) -> Self {
-		Self {label: String::new(), span: 0..0, emit_type: EmitType::Info, map, writer}
+	fn new(map: &'source SourceMap<'source>, writer: Rc<RefCell<W>>, format: EmitFormat, use_color: bool,
+	       collected: Rc<RefCell<Vec<Diagnostic>>>, errors: Rc<Cell<usize>>) -> Self {
+		Self {label: String::new(), span: 0..0, help: None, secondary: Vec::new(), emit_type: EmitType::Info,
+			format, use_color, map, writer, collected, errors}
 	}
 
 	pub fn with_label<T: Display>(mut self, label: T) -> Self {
@@ -64,6 +93,19 @@ impl<'source, W: Write> Emit<'source, W> {
 		self
 	}
 
+	/// Attaches a suggestion shown after the location, e.g. how to fix the code.
+	pub fn with_help<T: Display>(mut self, help: T) -> Self {
+		self.help = Some(help.to_string());
+		self
+	}
+
+	/// Adds another location related to the diagnostic, e.g. the token that opened a
+	/// construct the primary span fails to complete.
+	pub fn with_secondary<T: Display>(mut self, span: Span, label: T) -> Self {
+		self.secondary.push((span, label.to_string()));
+		self
+	}
+
 	pub fn with_eoi_span(mut self) -> Self {
 		self.span = self.map.eoi_span();
 		self
@@ -75,60 +117,233 @@ impl<'source, W: Write> Emit<'source, W> {
 	}
 
 	pub fn emit(self) {
-		match self.emit_type {
-			EmitType::Info => {
-				writeln!(self.writer.clone().borrow_mut(),
-				         "{}info: {}{}", color::GREEN, color::RESET,
-				         self.label).unwrap();
-				writeln!(self.writer.clone().borrow_mut(),
-				         "  {}--> {}{}{}", color::CYAN, color::BLUE,
-				         self.map.span_to_loc(self.span), color::RESET).unwrap();
-			},
-			EmitType::Warning => {
-				writeln!(self.writer.clone().borrow_mut(),
-				         "{}warning: {}{}", color::YELLOW, color::RESET,
-				         self.label).unwrap();
-				writeln!(self.writer.clone().borrow_mut(),
-				         "  {}--> {}{}{}", color::CYAN, color::BLUE,
-				          self.map.span_to_loc(self.span), color::RESET).unwrap();
-			}
-			EmitType::Error => {
-				writeln!(self.writer.clone().borrow_mut(),
-				         "{}error: {}{}", color::RED, color::RESET,
-				         self.label).unwrap();
-				writeln!(self.writer.clone().borrow_mut(),
-				         "  {}--> {}{}{}", color::CYAN, color::BLUE,
-				          self.map.span_to_loc(self.span), color::RESET).unwrap();
-			}
+		if self.emit_type == EmitType::Error {
+			self.errors.set(self.errors.get() + 1);
+		}
+		self.collected.borrow_mut().push(Diagnostic {
+			severity: self.emit_type,
+			message: self.label.clone(),
+			span: self.span.clone(),
+			help: self.help.clone(),
+			secondary: self.secondary.clone()
+		});
+		match self.format {
+			EmitFormat::Human => self.emit_human(),
+			EmitFormat::Short => self.emit_short(),
+			EmitFormat::Json => self.emit_json()
+		}
+	}
+
+	fn emit_short(self) {
+		let severity = match self.emit_type {
+			EmitType::Info => "info",
+			EmitType::Warning => "warning",
+			EmitType::Error => "error"
+		};
+		writeln!(self.writer.clone().borrow_mut(), "{}: {}: {}",
+		         self.map.span_to_loc(self.span.clone()), severity, self.label).unwrap();
+	}
+
+	/// Writes the diagnostic as a single-line JSON object carrying both the raw byte
+	/// span and the line/column it maps to.
+	fn emit_json(self) {
+		let severity = match self.emit_type {
+			EmitType::Info => "info",
+			EmitType::Warning => "warning",
+			EmitType::Error => "error"
+		};
+		let loc = self.map.span_to_loc(self.span.clone());
+		let help = match &self.help {
+			Some(help) => format!(",\"help\":\"{}\"", escape_json(help)),
+			None => String::new()
+		};
+		let secondary = self.secondary.iter().map(|(span, label)| {
+			let loc = self.map.span_to_loc(span.clone());
+			format!("{{\"message\":\"{}\",\"line\":{},\"column\":{},\"span\":[{},{}]}}",
+			        escape_json(label), loc.line, loc.column, span.start, span.end)
+		}).collect::<Vec<_>>();
+		let secondary = if secondary.is_empty() {
+			String::new()
+		}
+		else {
+			format!(",\"secondary\":[{}]", secondary.join(","))
+		};
+		writeln!(self.writer.clone().borrow_mut(),
+		         "{{\"severity\":\"{}\",\"message\":\"{}\",\"file\":\"{}\",\"line\":{},\"column\":{},\"span\":[{},{}]{}{}}}",
+		         severity, escape_json(&self.label), escape_json(loc.file),
+		         loc.line, loc.column, self.span.start, self.span.end, help, secondary).unwrap();
+	}
+
+	/// `code` if colors are enabled, otherwise an empty string.
+	fn paint(&self, code: &'static str) -> &'static str {
+		if self.use_color { code } else { "" }
+	}
+
+	fn emit_human(self) {
+		let (severity, severity_color) = match self.emit_type {
+			EmitType::Info => ("info", color::GREEN),
+			EmitType::Warning => ("warning", color::YELLOW),
+			EmitType::Error => ("error", color::RED)
+		};
+		let (severity_color, cyan, blue, reset) = (self.paint(severity_color), self.paint(color::CYAN),
+			self.paint(color::BLUE), self.paint(color::RESET));
+		writeln!(self.writer.clone().borrow_mut(),
+		         "{}{}: {}{}", severity_color, severity, reset, self.label).unwrap();
+		writeln!(self.writer.clone().borrow_mut(),
+		         "  {}--> {}{}{}", cyan, blue, self.map.span_to_loc(self.span.clone()), reset).unwrap();
+		if let Some(snippet) = render_snippet(self.map, self.span.clone(), severity_color, self.use_color) {
+			write!(self.writer.clone().borrow_mut(), "{}", snippet).unwrap();
+		}
+		for (span, label) in &self.secondary {
+			writeln!(self.writer.clone().borrow_mut(),
+			         "  {}--> {}{}{}: {}", cyan, blue, self.map.span_to_loc(span.clone()), reset, label).unwrap();
+		}
+		if let Some(help) = &self.help {
+			writeln!(self.writer.clone().borrow_mut(),
+			         "  {}= help: {}{}", cyan, reset, help).unwrap();
+		}
+	}
+}
+
+/// Columns a tab advances in a rendered snippet.
+const TAB_WIDTH: usize = 4;
+
+/// Renders the first source line of `span` with a caret under every column the span
+/// covers on it, expanding tabs so the carets line up. A zero-width span gets a
+/// single caret. `caret_color` is used as is, the gutter is only colored if `use_color`.
+fn render_snippet(map: &SourceMap, span: Span, caret_color: &str, use_color: bool) -> Option<String> {
+	let loc = map.span_to_loc(span.clone());
+	let (line_start, text) = map.line(loc.line)?;
+
+	let width = |text: &str| text.chars().map(|c| if c == '\t' { TAB_WIDTH } else { 1 }).sum::<usize>();
+	let clamp = |offset: usize| {
+		let mut offset = offset.saturating_sub(line_start).min(text.len());
+		while !text.is_char_boundary(offset) {
+			offset -= 1;
+		}
+		offset
+	};
+	let (start, end) = (clamp(span.start), clamp(span.end.max(span.start)));
+	let padding = width(&text[..start]);
+	let carets = width(&text[start..end]).max(1);
+
+	let gutter = " ".repeat(loc.line.to_string().len());
+	let (cyan, reset) = if use_color { (color::CYAN, color::RESET) } else { ("", "") };
+	Some(format!("{0} {1}|{2}\n{3} {1}|{2} {4}\n{0} {1}|{2} {5}{6}{7}{2}\n",
+	             gutter, cyan, reset, loc.line, text.replace('\t', &" ".repeat(TAB_WIDTH)),
+	             " ".repeat(padding), caret_color, "^".repeat(carets)))
+}
+
+pub(crate) fn escape_json(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for char in text.chars() {
+		match char {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			'\n' => escaped.push_str("\\n"),
+			'\t' => escaped.push_str("\\t"),
+			'\r' => escaped.push_str("\\r"),
+			c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+			c => escaped.push(c)
 		}
 	}
+	escaped
 }
 
 pub struct DiagnosticEmitter<'a, W: Write> {
 	map: &'a SourceMap<'a>,
-	writer: Rc<RefCell<W>>
+	writer: Rc<RefCell<W>>,
+	format: EmitFormat,
+	use_color: bool,
+	collected: Rc<RefCell<Vec<Diagnostic>>>,
+	errors: Rc<Cell<usize>>
 }
 
 impl<'a, W: Write> DiagnosticEmitter<'a, W> {
+	/// Colors human output unless the `NO_COLOR` environment variable is set and non-empty.
+	/// Whether an arbitrary writer is a terminal is unknown, `with_stderr` checks stderr.
 	pub fn new(map: &'a SourceMap<'a>, writer: W) -> Self {
-		Self {map, writer: Rc::new(RefCell::new(writer))}
+		Self {map, writer: Rc::new(RefCell::new(writer)), format: EmitFormat::Human,
+			use_color: !no_color(), collected: Rc::new(RefCell::new(Vec::new())),
+			errors: Rc::new(Cell::new(0))}
+	}
+
+	/// An emitter writing each diagnostic to `writer` as a line of JSON.
+	pub fn json(map: &'a SourceMap<'a>, writer: W) -> Self {
+		Self::new(map, writer).with_format(EmitFormat::Json)
+	}
+
+	pub fn with_format(mut self, format: EmitFormat) -> Self {
+		self.format = format;
+		self
+	}
+
+	/// Enables or disables the ANSI color codes in human output.
+	pub fn with_color(mut self, use_color: bool) -> Self {
+		self.use_color = use_color;
+		self
+	}
+
+	/// Takes every diagnostic emitted so far, in order. They are recorded whatever the
+	/// writer, so a `collecting` emitter keeps them only here.
+	pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+		self.collected.take()
+	}
+
+	/// The number of errors emitted so far, including those already taken with
+	/// `take_diagnostics`.
+	pub fn error_count(&self) -> usize {
+		self.errors.get()
+	}
+
+	/// Writes a closing `error: aborting due to N previous errors` line if any errors were
+	/// emitted. JSON output gets no summary, so every line stays a diagnostic.
+	pub fn finish(&self) {
+		let count = self.error_count();
+		if count == 0 || self.format == EmitFormat::Json {
+			return;
+		}
+		let (red, reset) = match (self.format, self.use_color) {
+			(EmitFormat::Human, true) => (color::RED, color::RESET),
+			_ => ("", "")
+		};
+		writeln!(self.writer.borrow_mut(), "{}error: {}aborting due to {}", red, reset,
+		         if count == 1 { "previous error".to_string() } else { format!("{} previous errors", count) }).unwrap();
+	}
+
+	/// The source text covered by `span`, or an empty string if it is out of bounds.
+	pub fn source_slice(&self, span: Span) -> &'a str {
+		self.map.source().get(span).unwrap_or("")
 	}
 
 	pub fn info(&self) -> Emit<W> {
-		Emit::new(self.map, self.writer.clone()).with_type(EmitType::Info)
+		Emit::new(self.map, self.writer.clone(), self.format, self.use_color, self.collected.clone(), self.errors.clone())
+			.with_type(EmitType::Info)
 	}
 
 	pub fn warning(&self) -> Emit<W> {
-		Emit::new(self.map, self.writer.clone()).with_type(EmitType::Warning)
+		Emit::new(self.map, self.writer.clone(), self.format, self.use_color, self.collected.clone(), self.errors.clone())
+			.with_type(EmitType::Warning)
 	}
 
 	pub fn error(&self) -> Emit<W> {
-		Emit::new(self.map, self.writer.clone()).with_type(EmitType::Error)
+		Emit::new(self.map, self.writer.clone(), self.format, self.use_color, self.collected.clone(), self.errors.clone())
+			.with_type(EmitType::Error)
 	}
 }
 
+/// Whether `NO_COLOR` asks for plain output. Per no-color.org an empty value doesn't count.
+fn no_color() -> bool {
+	env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
 pub fn with_stderr<'a>(map: &'a SourceMap<'a>) -> DiagnosticEmitter<'a, io::Stderr> {
-	DiagnosticEmitter::new(map, io::stderr())
+	let use_color = io::stderr().is_terminal() && !no_color();
+	DiagnosticEmitter::new(map, io::stderr()).with_color(use_color)
+}
+
+pub fn collecting<'a>(map: &'a SourceMap<'a>) -> DiagnosticEmitter<'a, io::Sink> {
+	DiagnosticEmitter::new(map, io::sink())
 }
 
 pub fn with_string<'a>(map: &'a SourceMap<'a>, string: &'a mut String)
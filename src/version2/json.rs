@@ -0,0 +1,307 @@
+use crate::ast::{ArrayLen, Attribute, Expr, Field, Spanned, Type};
+use crate::diagnostics::{escape_json, Span};
+
+/// A JSON value, built from the AST for external tooling.
+pub enum Json {
+	Null,
+	Bool(bool),
+	Num(u64),
+	Float(f64),
+	Str(String),
+	Array(Vec<Json>),
+	Object(Vec<(&'static str, Json)>)
+}
+
+impl Json {
+	/// Renders the value with one tab of indentation per nesting level.
+	pub fn pretty(&self) -> String {
+		let mut out = String::new();
+		self.write_pretty(&mut out, 0);
+		out
+	}
+
+	fn write_pretty(&self, out: &mut String, depth: usize) {
+		match self {
+			Json::Null => out.push_str("null"),
+			Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+			Json::Num(value) => out.push_str(&value.to_string()),
+			// JSON has no representation for NaN or infinity.
+			Json::Float(value) if !value.is_finite() => out.push_str("null"),
+			Json::Float(value) => out.push_str(&format!("{:?}", value)),
+			Json::Str(value) => {
+				out.push('"');
+				out.push_str(&escape_json(value));
+				out.push('"');
+			}
+			Json::Array(values) => {
+				if values.is_empty() {
+					out.push_str("[]");
+					return;
+				}
+				out.push('[');
+				for (i, value) in values.iter().enumerate() {
+					out.push_str(if i == 0 { "\n" } else { ",\n" });
+					push_indent(out, depth + 1);
+					value.write_pretty(out, depth + 1);
+				}
+				out.push('\n');
+				push_indent(out, depth);
+				out.push(']');
+			}
+			Json::Object(entries) => {
+				if entries.is_empty() {
+					out.push_str("{}");
+					return;
+				}
+				out.push('{');
+				for (i, (key, value)) in entries.iter().enumerate() {
+					out.push_str(if i == 0 { "\n" } else { ",\n" });
+					push_indent(out, depth + 1);
+					out.push('"');
+					out.push_str(key);
+					out.push_str("\": ");
+					value.write_pretty(out, depth + 1);
+				}
+				out.push('\n');
+				push_indent(out, depth);
+				out.push('}');
+			}
+		}
+	}
+}
+
+fn push_indent(out: &mut String, depth: usize) {
+	for _ in 0..depth {
+		out.push('\t');
+	}
+}
+
+pub fn ast_to_json(ast: &[Expr]) -> Json {
+	Json::Array(ast.iter().map(expr_to_json).collect())
+}
+
+fn node(kind: &'static str, mut entries: Vec<(&'static str, Json)>) -> Json {
+	entries.insert(0, ("node", Json::Str(kind.to_string())));
+	Json::Object(entries)
+}
+
+fn span_to_json(span: &Span) -> Json {
+	Json::Array(vec![Json::Num(span.start as u64), Json::Num(span.end as u64)])
+}
+
+fn spanned_to_json(spanned: &Spanned<String>) -> Json {
+	Json::Object(vec![("value", Json::Str(spanned.0.clone())), ("span", span_to_json(&spanned.1))])
+}
+
+fn option_to_json<T>(value: &Option<T>, to_json: impl Fn(&T) -> Json) -> Json {
+	value.as_ref().map_or(Json::Null, to_json)
+}
+
+fn exprs_to_json(exprs: &[Expr]) -> Json {
+	Json::Array(exprs.iter().map(expr_to_json).collect())
+}
+
+fn type_to_json(ty: &Type) -> Json {
+	match ty {
+		Type::Named(name) => node("Named", vec![("name", spanned_to_json(name))]),
+		Type::Optional(inner) => node("Optional", vec![("inner", type_to_json(inner))]),
+		Type::Tuple((types, span)) => node("Tuple", vec![
+			("types", Json::Array(types.iter().map(type_to_json).collect())),
+			("span", span_to_json(span))
+		]),
+		Type::Array(element, len, span) => node("Array", vec![
+			("element", type_to_json(element)),
+			("len", match len {
+				ArrayLen::Num((num, span)) => Json::Object(vec![("value", Json::Num(*num)), ("span", span_to_json(span))]),
+				ArrayLen::Const(name) => spanned_to_json(name)
+			}),
+			("span", span_to_json(span))
+		])
+	}
+}
+
+fn field_to_json(field: &Field) -> Json {
+	Json::Object(vec![
+		("name", spanned_to_json(&field.name)),
+		("type", type_to_json(&field.r#type)),
+		("doc", option_to_json(&field.doc, |doc| Json::Str(doc.clone()))),
+		("width", option_to_json(&field.width, |(width, _)| Json::Num(*width))),
+		("default", option_to_json(&field.default, expr_to_json))
+	])
+}
+
+fn attributes_to_json(attributes: &[Attribute]) -> Json {
+	Json::Array(attributes.iter().map(|attribute| Json::Object(vec![
+		("name", spanned_to_json(&attribute.name)),
+		("args", exprs_to_json(&attribute.args))
+	])).collect())
+}
+
+fn binary(kind: &'static str, lhs: &Expr, rhs: &Expr) -> Json {
+	node(kind, vec![("lhs", expr_to_json(lhs)), ("rhs", expr_to_json(rhs))])
+}
+
+pub fn expr_to_json(expr: &Expr) -> Json {
+	match expr {
+		Expr::Error => node("Error", Vec::new()),
+		Expr::Var(name) => node("Var", vec![("name", spanned_to_json(name))]),
+		Expr::Num((value, span)) => node("Num", vec![("value", Json::Num(*value)), ("span", span_to_json(span))]),
+		Expr::FloatLiteral((value, span)) => node("FloatLiteral", vec![
+			("value", Json::Float(value.0)),
+			("span", span_to_json(span))
+		]),
+		Expr::CharLiteral(value) => node("CharLiteral", vec![("value", spanned_to_json(value))]),
+		Expr::StringLiteral(value) => node("StringLiteral", vec![("value", spanned_to_json(value))]),
+		Expr::Neg(expr, span) => node("Neg", vec![("expr", expr_to_json(expr)), ("span", span_to_json(span))]),
+		Expr::Pos(expr, span) => node("Pos", vec![("expr", expr_to_json(expr)), ("span", span_to_json(span))]),
+		Expr::Not(expr, span) => node("Not", vec![("expr", expr_to_json(expr)), ("span", span_to_json(span))]),
+		Expr::Add(lhs, rhs) => binary("Add", lhs, rhs),
+		Expr::Sub(lhs, rhs) => binary("Sub", lhs, rhs),
+		Expr::Mul(lhs, rhs) => binary("Mul", lhs, rhs),
+		Expr::Div(lhs, rhs) => binary("Div", lhs, rhs),
+		Expr::Mod(lhs, rhs) => binary("Mod", lhs, rhs),
+		Expr::And(lhs, rhs) => binary("And", lhs, rhs),
+		Expr::Or(lhs, rhs) => binary("Or", lhs, rhs),
+		Expr::BitAnd(lhs, rhs) => binary("BitAnd", lhs, rhs),
+		Expr::BitOr(lhs, rhs) => binary("BitOr", lhs, rhs),
+		Expr::Shl(lhs, rhs) => binary("Shl", lhs, rhs),
+		Expr::Shr(lhs, rhs) => binary("Shr", lhs, rhs),
+		Expr::Eq(lhs, rhs) => binary("Eq", lhs, rhs),
+		Expr::Ne(lhs, rhs) => binary("Ne", lhs, rhs),
+		Expr::Lt(lhs, rhs) => binary("Lt", lhs, rhs),
+		Expr::Gt(lhs, rhs) => binary("Gt", lhs, rhs),
+		Expr::Le(lhs, rhs) => binary("Le", lhs, rhs),
+		Expr::Ge(lhs, rhs) => binary("Ge", lhs, rhs),
+		Expr::Range {start, end, inclusive, span} => node("Range", vec![
+			("start", option_to_json(start, |expr| expr_to_json(expr))),
+			("end", option_to_json(end, |expr| expr_to_json(expr))),
+			("inclusive", Json::Bool(*inclusive)),
+			("span", span_to_json(span))
+		]),
+		Expr::Cast {expr, ty} => node("Cast", vec![("expr", expr_to_json(expr)), ("type", type_to_json(ty))]),
+		Expr::SizeOf(ty, span) => node("SizeOf", vec![("type", type_to_json(ty)), ("span", span_to_json(span))]),
+		Expr::AlignOf(ty, span) => node("AlignOf", vec![("type", type_to_json(ty)), ("span", span_to_json(span))]),
+		Expr::Assign {target, value, span} => node("Assign", vec![
+			("target", expr_to_json(target)),
+			("value", expr_to_json(value)),
+			("span", span_to_json(span))
+		]),
+		Expr::Struct {name, base, fields, attributes, span} => node("Struct", vec![
+			("name", spanned_to_json(name)),
+			("base", option_to_json(base, type_to_json)),
+			("fields", Json::Array(fields.iter().map(field_to_json).collect())),
+			("attributes", attributes_to_json(attributes)),
+			("span", span_to_json(span))
+		]),
+		Expr::Function {name, type_params, constraints, args, ret_type, body, attributes, is_extern, span} => node("Function", vec![
+			("name", spanned_to_json(name)),
+			("type_params", Json::Array(type_params.iter().map(spanned_to_json).collect())),
+			("constraints", Json::Array(constraints.iter().map(|(param, bound)| Json::Object(vec![
+				("param", spanned_to_json(param)),
+				("bound", spanned_to_json(bound))
+			])).collect())),
+			("args", Json::Array(args.iter().map(|(name, ty)| Json::Object(vec![
+				("name", spanned_to_json(name)),
+				("type", type_to_json(ty))
+			])).collect())),
+			("ret_type", option_to_json(ret_type, type_to_json)),
+			("body", option_to_json(body, |body| exprs_to_json(body))),
+			("attributes", attributes_to_json(attributes)),
+			("is_extern", Json::Bool(*is_extern)),
+			("span", span_to_json(span))
+		]),
+		Expr::VarDecl {name, r#type, value} => node("VarDecl", vec![
+			("name", spanned_to_json(name)),
+			("type", type_to_json(r#type)),
+			("value", option_to_json(value, |expr| expr_to_json(expr)))
+		]),
+		Expr::MultiVarDecl {names, r#type, value} => node("MultiVarDecl", vec![
+			("names", Json::Array(names.iter().map(spanned_to_json).collect())),
+			("type", type_to_json(r#type)),
+			("value", option_to_json(value, |expr| expr_to_json(expr)))
+		]),
+		Expr::Construct {name, fields, span} => node("Construct", vec![
+			("name", spanned_to_json(name)),
+			("fields", Json::Array(fields.iter().map(|(name, value)| Json::Object(vec![
+				("name", spanned_to_json(name)),
+				("value", expr_to_json(value))
+			])).collect())),
+			("span", span_to_json(span))
+		]),
+		Expr::Tuple(values, span) => node("Tuple", vec![("values", exprs_to_json(values)), ("span", span_to_json(span))]),
+		Expr::Array(values, span) => node("Array", vec![("values", exprs_to_json(values)), ("span", span_to_json(span))]),
+		Expr::PositionalConstruct {name, values, span} => node("PositionalConstruct", vec![
+			("name", spanned_to_json(name)),
+			("values", exprs_to_json(values)),
+			("span", span_to_json(span))
+		]),
+		Expr::FieldAccess {base, field} => node("FieldAccess", vec![
+			("base", expr_to_json(base)),
+			("field", spanned_to_json(field))
+		]),
+		Expr::Index {base, index, span} => node("Index", vec![
+			("base", expr_to_json(base)),
+			("index", expr_to_json(index)),
+			("span", span_to_json(span))
+		]),
+		Expr::Generic {name, type_args, span} => node("Generic", vec![
+			("name", spanned_to_json(name)),
+			("type_args", Json::Array(type_args.iter().map(type_to_json).collect())),
+			("span", span_to_json(span))
+		]),
+		Expr::Call {callee, type_args, args, named_args, span} => node("Call", vec![
+			("callee", expr_to_json(callee)),
+			("type_args", Json::Array(type_args.iter().map(type_to_json).collect())),
+			("args", exprs_to_json(args)),
+			("named_args", Json::Array(named_args.iter().map(|(name, value)| Json::Object(vec![
+				("name", spanned_to_json(name)),
+				("value", expr_to_json(value))
+			])).collect())),
+			("span", span_to_json(span))
+		]),
+		Expr::Ret {value, span} => node("Ret", vec![
+			("value", option_to_json(value, |expr| expr_to_json(expr))),
+			("span", span_to_json(span))
+		]),
+		Expr::If {cond, then_body, else_body, span} => node("If", vec![
+			("cond", expr_to_json(cond)),
+			("then_body", exprs_to_json(then_body)),
+			("else_body", option_to_json(else_body, |body| exprs_to_json(body))),
+			("span", span_to_json(span))
+		]),
+		Expr::For {init, cond, step, body, span} => node("For", vec![
+			("init", option_to_json(init, |expr| expr_to_json(expr))),
+			("cond", option_to_json(cond, |expr| expr_to_json(expr))),
+			("step", option_to_json(step, |expr| expr_to_json(expr))),
+			("body", exprs_to_json(body)),
+			("span", span_to_json(span))
+		]),
+		Expr::Assert {cond, message, span} => node("Assert", vec![
+			("cond", expr_to_json(cond)),
+			("message", option_to_json(message, |expr| expr_to_json(expr))),
+			("span", span_to_json(span))
+		]),
+		Expr::Tail {value} => node("Tail", vec![("value", expr_to_json(value))]),
+		Expr::Block {label, body, span} => node("Block", vec![
+			("label", spanned_to_json(label)),
+			("body", exprs_to_json(body)),
+			("span", span_to_json(span))
+		]),
+		Expr::Break {label, value, span} => node("Break", vec![
+			("label", spanned_to_json(label)),
+			("value", option_to_json(value, |expr| expr_to_json(expr))),
+			("span", span_to_json(span))
+		]),
+		Expr::Script {body} => node("Script", vec![("body", exprs_to_json(body))]),
+		Expr::Import {path, alias, span} => node("Import", vec![
+			("path", spanned_to_json(path)),
+			("alias", option_to_json(alias, spanned_to_json)),
+			("span", span_to_json(span))
+		]),
+		Expr::Const {name, value, span} => node("Const", vec![
+			("name", spanned_to_json(name)),
+			("value", expr_to_json(value)),
+			("span", span_to_json(span))
+		])
+	}
+}
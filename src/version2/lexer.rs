@@ -1,9 +1,11 @@
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
-use std::io::Write;
-use std::iter::Peekable;
+use std::fs::read_to_string;
+use std::io;
+use std::io::{BufRead, Write};
 use std::str::Chars;
-use logos::Source;
 use crate::diagnostics::{DiagnosticEmitter, Span};
 
 #[derive(Copy, Clone, Debug)]
@@ -25,21 +27,40 @@ impl<'source> Display for Loc<'source> {
 	}
 }
 
+/// Maps byte offsets in a source file to lines and columns. The file name and
+/// source are either borrowed or, when read with `from_file`, owned by the map.
 pub struct SourceMap<'source> {
-	file: &'source str,
-	lines: Vec<(Span, &'source str)>
+	file: Cow<'source, str>,
+	src: Cow<'source, str>,
+	lines: Vec<Span>
 }
 
 impl<'source> SourceMap<'source> {
 	pub fn new(file: &'source str, src: &'source str) -> Self {
-		let mut loc = 0usize;
+		let lines = Self::line_spans(src);
+		Self {file: Cow::Borrowed(file), src: Cow::Borrowed(src), lines}
+	}
+
+	/// Reads the file at `path` into a map that owns its source, which the lexer can
+	/// then borrow through `source`.
+	pub fn from_file(path: &str) -> io::Result<SourceMap<'static>> {
+		let src = read_to_string(path)?;
+		let lines = Self::line_spans(&src);
+		Ok(SourceMap {file: Cow::Owned(path.to_string()), src: Cow::Owned(src), lines})
+	}
+
+	/// The lexer skips a leading byte order mark, so the first line starts after it
+	/// and columns and snippets don't count it.
+	fn line_spans(src: &str) -> Vec<Span> {
+		let bom = src.strip_prefix('\u{FEFF}').map_or(0, |rest| src.len() - rest.len());
+		let mut loc = bom;
 		let mut lines = Vec::new();
 		let mut line = String::new();
-		let mut start = 0usize;
-		for char in src.chars() {
+		let mut start = bom;
+		for char in src[bom..].chars() {
 			if char == '\n' {
 				loc += line.len() + 1;
-				lines.push((start..loc, src.slice(start..loc).unwrap()));
+				lines.push(start..loc);
 				start = loc;
 				line.clear();
 			}
@@ -49,24 +70,69 @@ impl<'source> SourceMap<'source> {
 		}
 		if !line.is_empty() {
 			loc += line.len();
-			lines.push((start..loc, src.slice(start..loc).unwrap()));
+			lines.push(start..loc);
 		}
-		Self {file, lines}
+		lines
 	}
 
+	pub fn file(&self) -> &str {
+		&self.file
+	}
+
+	pub fn source(&self) -> &str {
+		&self.src
+	}
+
+	/// Maps the start of a byte span to a 1-based line and column, where the column
+	/// counts characters rather than bytes. Offsets past the end of the source map to
+	/// the end of the last line, the same location as `eoi_span`.
 	pub fn span_to_loc(&self, span: Span) -> Loc {
-		for (i, (range, _)) in self.lines.iter().enumerate() {
-			if range.contains(&span.start) {
-				let column = span.start - range.start;
-				return Loc::new(self.file, i + 1, column + 1);
+		let first = self.lines.first().map_or(0, |range| range.start);
+		let start = span.start.clamp(first, self.src.len().max(first));
+		// The lines are sorted and contiguous, so only the end of input misses them all
+		let line = self.lines.binary_search_by(|range| {
+			if range.end <= start {
+				Ordering::Less
 			}
-		}
-		let (range, _) = self.lines.last().unwrap();
-		return Loc::new(self.file, self.lines.len(), span.start - range.start + 1);
+			else if range.start > start {
+				Ordering::Greater
+			}
+			else {
+				Ordering::Equal
+			}
+		}).unwrap_or(self.lines.len().saturating_sub(1));
+		let range = self.lines.get(line).cloned().unwrap_or(0..0);
+		let offset = start - range.start;
+		let column = self.src[range].get(..offset)
+			.map_or(offset, |prefix| prefix.chars().count());
+		Loc::new(&self.file, line + 1, column + 1)
+	}
+
+	/// Maps a 1-based line and column, as returned by `span_to_loc`, back to a byte
+	/// offset. The column may point one past the end of the line.
+	pub fn loc_to_offset(&self, line: usize, column: usize) -> Option<usize> {
+		let range = match self.lines.get(line.checked_sub(1)?) {
+			Some(range) => range.clone(),
+			None if self.lines.is_empty() && line == 1 => self.eoi_span(),
+			None => return None
+		};
+		let text = &self.src[range.clone()];
+		text.char_indices()
+			.map(|(offset, _)| offset)
+			.chain(std::iter::once(text.len()))
+			.nth(column.checked_sub(1)?)
+			.map(|offset| range.start + offset)
+	}
+
+	/// The start offset and text, without the line break, of the 1-based `line`.
+	pub fn line(&self, line: usize) -> Option<(usize, &str)> {
+		let range = self.lines.get(line.checked_sub(1)?)?;
+		let text = self.src[range.clone()].trim_end_matches(['\n', '\r']);
+		Some((range.start, text))
 	}
 
 	pub fn eoi_span(&self) -> Span {
-		let (range, _) = self.lines.last().unwrap_or(&(0..0, ""));
+		let range = self.lines.last().cloned().unwrap_or(self.src.len()..self.src.len());
 		range.end..range.end
 	}
 }
@@ -80,32 +146,160 @@ pub enum BinOp {
 	Modulo,
 	And,
 	Or,
-	Not
+	BitAnd,
+	BitOr,
+	ShiftLeft,
+	ShiftRight,
+	Not,
+	Equal,
+	NotEqual,
+	Less,
+	Greater,
+	LessEqual,
+	GreaterEqual
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
 	Struct,
 	Ret,
+	As,
+	Break,
+	Extern,
+	Assert,
+	If,
+	Else,
+	For,
+	Import,
+	Const,
 
 	LBrace,
 	RBrace,
 	LParen,
 	RParen,
+	LBracket,
+	RBracket,
 	Colon,
 	Semicolon,
 	Dot,
+	DotDot,
+	DotDotEquals,
 	Comma,
 	Arrow,
+	Question,
+	Hash,
+	Newline,
+	DocComment(String),
 
 	BinOp(BinOp),
 	Equals,
 	BinOpEquals(BinOp),
 
 	Identifier(String),
+	/// `'name`, naming a block for `break`.
+	Label(String),
+	/// A literal produced by a hook registered with `Lexer::with_literal_hook`.
+	Custom(String),
 	CharLiteral(String),
 	StringLiteral(String),
-	Num(u64)
+	Num(u64),
+	Float(f64),
+	/// Marks the end of input at the end of `Lexer::tokenize_all`.
+	Eof
+}
+
+/// The kind of a `TokenType` without its payload, for callers like syntax highlighters
+/// that already have the source text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+	Struct,
+	Ret,
+	As,
+	Break,
+	Extern,
+	Assert,
+	If,
+	Else,
+	For,
+	Import,
+	Const,
+
+	LBrace,
+	RBrace,
+	LParen,
+	RParen,
+	LBracket,
+	RBracket,
+	Colon,
+	Semicolon,
+	Dot,
+	DotDot,
+	DotDotEquals,
+	Comma,
+	Arrow,
+	Question,
+	Hash,
+	Newline,
+	DocComment,
+
+	BinOp,
+	Equals,
+	BinOpEquals,
+
+	Identifier,
+	Label,
+	Custom,
+	CharLiteral,
+	StringLiteral,
+	Num,
+	Float,
+	Eof
+}
+
+impl TokenType {
+	pub fn kind(&self) -> TokenKind {
+		match self {
+			TokenType::Struct => TokenKind::Struct,
+			TokenType::Ret => TokenKind::Ret,
+			TokenType::As => TokenKind::As,
+			TokenType::Break => TokenKind::Break,
+			TokenType::Extern => TokenKind::Extern,
+			TokenType::Assert => TokenKind::Assert,
+			TokenType::If => TokenKind::If,
+			TokenType::Else => TokenKind::Else,
+			TokenType::For => TokenKind::For,
+			TokenType::Import => TokenKind::Import,
+			TokenType::Const => TokenKind::Const,
+			TokenType::LBrace => TokenKind::LBrace,
+			TokenType::RBrace => TokenKind::RBrace,
+			TokenType::LParen => TokenKind::LParen,
+			TokenType::RParen => TokenKind::RParen,
+			TokenType::LBracket => TokenKind::LBracket,
+			TokenType::RBracket => TokenKind::RBracket,
+			TokenType::Colon => TokenKind::Colon,
+			TokenType::Semicolon => TokenKind::Semicolon,
+			TokenType::Dot => TokenKind::Dot,
+			TokenType::DotDot => TokenKind::DotDot,
+			TokenType::DotDotEquals => TokenKind::DotDotEquals,
+			TokenType::Comma => TokenKind::Comma,
+			TokenType::Arrow => TokenKind::Arrow,
+			TokenType::Question => TokenKind::Question,
+			TokenType::Hash => TokenKind::Hash,
+			TokenType::Newline => TokenKind::Newline,
+			TokenType::DocComment(_) => TokenKind::DocComment,
+			TokenType::BinOp(_) => TokenKind::BinOp,
+			TokenType::BinOpEquals(_) => TokenKind::BinOpEquals,
+			TokenType::Identifier(_) => TokenKind::Identifier,
+			TokenType::Label(_) => TokenKind::Label,
+			TokenType::Custom(_) => TokenKind::Custom,
+			TokenType::CharLiteral(_) => TokenKind::CharLiteral,
+			TokenType::StringLiteral(_) => TokenKind::StringLiteral,
+			TokenType::Num(_) => TokenKind::Num,
+			TokenType::Float(_) => TokenKind::Float,
+			TokenType::Equals => TokenKind::Equals,
+			TokenType::Eof => TokenKind::Eof
+		}
+	}
 }
 
 impl Display for TokenType {
@@ -113,22 +307,43 @@ impl Display for TokenType {
 		match self {
 			TokenType::Struct => write!(f, "struct"),
 			TokenType::Ret => write!(f, "ret"),
+			TokenType::As => write!(f, "as"),
+			TokenType::Break => write!(f, "break"),
+			TokenType::Extern => write!(f, "extern"),
+			TokenType::Assert => write!(f, "assert"),
+			TokenType::If => write!(f, "if"),
+			TokenType::Else => write!(f, "else"),
+			TokenType::For => write!(f, "for"),
+			TokenType::Import => write!(f, "import"),
+			TokenType::Const => write!(f, "const"),
 			TokenType::LBrace => write!(f, "'{{'"),
 			TokenType::RBrace => write!(f, "'}}'"),
 			TokenType::LParen => write!(f, "'('"),
 			TokenType::RParen => write!(f, "')'"),
+			TokenType::LBracket => write!(f, "'['"),
+			TokenType::RBracket => write!(f, "']'"),
 			TokenType::Colon => write!(f, "':'"),
 			TokenType::Semicolon => write!(f, "';'"),
 			TokenType::Dot => write!(f, "'.'"),
+			TokenType::DotDot => write!(f, "'..'"),
+			TokenType::DotDotEquals => write!(f, "'..='"),
 			TokenType::Comma => write!(f, "','"),
 			TokenType::BinOp(_) => write!(f, "an operator"),
 			TokenType::Equals => write!(f, "'='"),
 			TokenType::BinOpEquals(_) => write!(f, "an operator"),
 			TokenType::Identifier(_) => write!(f, "an identifier"),
+			TokenType::Label(_) => write!(f, "a label"),
+			TokenType::Custom(_) => write!(f, "a custom literal"),
 			TokenType::Num(_) => write!(f, "a number"),
+			TokenType::Float(_) => write!(f, "a float"),
 			TokenType::CharLiteral(_) => write!(f, "a character literal"),
 			TokenType::StringLiteral(_) => write!(f, "a string literal"),
-			TokenType::Arrow => write!(f, "'->'")
+			TokenType::Arrow => write!(f, "'->'"),
+			TokenType::Question => write!(f, "'?'"),
+			TokenType::Hash => write!(f, "'#'"),
+			TokenType::Newline => write!(f, "a newline"),
+			TokenType::DocComment(_) => write!(f, "a doc comment"),
+			TokenType::Eof => write!(f, "eof")
 		}
 	}
 }
@@ -145,33 +360,184 @@ impl Token {
 	}
 }
 
-pub struct Lexer<'source, W: Write> {
-	src: Peekable<Chars<'source>>,
+/// A `#if name` ... `#endif` section. The lexer records these for tooling without
+/// evaluating them, and the tokens inside are lexed as usual.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConditionalSection {
+	pub condition: (String, Span),
+	/// From the `#` of `#if` to the end of `#endif`.
+	pub span: Span
+}
+
+/// Character input driving a `Lexer`.
+pub trait Input {
+	fn next_char(&mut self) -> Option<char>;
+
+	/// The error that ended the input early, if any. Called once the input is exhausted.
+	fn take_error(&mut self) -> Option<io::Error> {
+		None
+	}
+}
+
+impl Input for Chars<'_> {
+	fn next_char(&mut self) -> Option<char> {
+		self.next()
+	}
+}
+
+/// Decodes UTF-8 incrementally from a reader, so a byte slice or a file can drive
+/// the lexer without being loaded up front. Reads byte by byte, so the reader must be
+/// buffered; wrap a `File` in a `BufReader`.
+/// Input ends at the first invalid sequence or read error, which `take_error` returns.
+pub struct Utf8Reader<R: BufRead> {
+	bytes: io::Bytes<R>,
+	offset: usize,
+	error: Option<io::Error>
+}
+
+impl<R: BufRead> Utf8Reader<R> {
+	pub fn new(reader: R) -> Self {
+		Self {bytes: reader.bytes(), offset: 0, error: None}
+	}
+
+	fn next_byte(&mut self) -> Option<u8> {
+		match self.bytes.next()? {
+			Ok(byte) => Some(byte),
+			Err(error) => {
+				self.error = Some(error);
+				None
+			}
+		}
+	}
+}
+
+impl<R: BufRead> Input for Utf8Reader<R> {
+	fn next_char(&mut self) -> Option<char> {
+		if self.error.is_some() {
+			return None;
+		}
+
+		let first = self.next_byte()?;
+		let len = match first {
+			0x00..=0x7F => 1,
+			0xC0..=0xDF => 2,
+			0xE0..=0xEF => 3,
+			0xF0..=0xF7 => 4,
+			_ => 0
+		};
+
+		let mut buf = [first, 0, 0, 0];
+		let mut read = 1;
+		while read < len {
+			match self.next_byte() {
+				Some(byte) => buf[read] = byte,
+				None => break
+			}
+			read += 1;
+		}
+		if self.error.is_some() {
+			return None;
+		}
+
+		match std::str::from_utf8(&buf[..read]) {
+			Ok(char) => {
+				self.offset += len;
+				char.chars().next()
+			}
+			_ => {
+				self.error = Some(io::Error::new(io::ErrorKind::InvalidData,
+					format!("invalid UTF-8 at byte {}", self.offset)));
+				None
+			}
+		}
+	}
+
+	fn take_error(&mut self) -> Option<io::Error> {
+		self.error.take()
+	}
+}
+
+/// Wraps an `Input` with the character lookahead the lexer needs.
+struct CharStream<I: Input> {
+	input: I,
+	lookahead: VecDeque<char>
+}
+
+impl<I: Input> CharStream<I> {
+	fn new(input: I) -> Self {
+		Self {input, lookahead: VecDeque::new()}
+	}
+
+	fn next(&mut self) -> Option<char> {
+		self.lookahead.pop_front().or_else(|| self.input.next_char())
+	}
+
+	fn peek_nth(&mut self, n: usize) -> Option<char> {
+		while self.lookahead.len() <= n {
+			let char = self.input.next_char()?;
+			self.lookahead.push_back(char);
+		}
+		Some(self.lookahead[n])
+	}
+
+	fn peek(&mut self) -> Option<char> {
+		self.peek_nth(0)
+	}
+
+	fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+		match self.peek() {
+			Some(char) if func(&char) => self.next(),
+			_ => None
+		}
+	}
+}
+
+/// Turns the text after a custom literal prefix into a token, see `Lexer::with_literal_hook`.
+pub type LiteralHook = Box<dyn Fn(&str) -> Option<TokenType>>;
+
+pub struct Lexer<'source, W: Write, I: Input = Chars<'source>> {
+	src: CharStream<I>,
 	read: usize,
 	special_chars: HashMap<char, TokenType>,
 	second_special_chars: HashSet<char>,
 	keywords: HashMap<&'static str, TokenType>,
-	next: [Option<Token>; 2],
+	literal_hooks: HashMap<char, LiteralHook>,
+	next: VecDeque<Token>,
 	emitter: &'source DiagnosticEmitter<'source, W>,
-	has_error: bool
+	has_error: bool,
+	newlines: bool,
+	open_sections: Vec<((String, Span), usize)>,
+	sections: Vec<ConditionalSection>,
+	unexpected_chars: usize,
+	error_limit: usize
 }
 
 pub enum PeekCount {
 	One,
-	Two
+	Two,
+	Three
 }
 
 impl<'source, W: Write> Lexer<'source, W> {
 	pub fn new(src: &'source str, emitter: &'source DiagnosticEmitter<'source, W>) -> Self {
+		Self::from_input(src.chars(), emitter)
+	}
+}
+
+impl<'source, W: Write, I: Input> Lexer<'source, W, I> {
+	/// Creates a lexer reading from any `Input`, e.g. a `Utf8Reader` over bytes.
+	pub fn from_input(input: I, emitter: &'source DiagnosticEmitter<'source, W>) -> Self {
 		let special_chars = HashMap::from([
 			('+', TokenType::BinOp(BinOp::Add)),
 			('-', TokenType::BinOp(BinOp::Minus)),
 			('*', TokenType::BinOp(BinOp::Multiply)),
 			('/', TokenType::BinOp(BinOp::Divide)),
 			('%', TokenType::BinOp(BinOp::Modulo)),
-			('|', TokenType::BinOp(BinOp::Or)),
-			('&', TokenType::BinOp(BinOp::And)),
+			('|', TokenType::BinOp(BinOp::BitOr)),
+			('&', TokenType::BinOp(BinOp::BitAnd)),
 			('!', TokenType::BinOp(BinOp::Not)),
+			('<', TokenType::BinOp(BinOp::Less)),
+			('>', TokenType::BinOp(BinOp::Greater)),
 			(';', TokenType::Semicolon),
 			('.', TokenType::Dot),
 			(',', TokenType::Comma),
@@ -179,113 +545,205 @@ impl<'source, W: Write> Lexer<'source, W> {
 			('}', TokenType::RBrace),
 			('(', TokenType::LParen),
 			(')', TokenType::RParen),
+			('[', TokenType::LBracket),
+			(']', TokenType::RBracket),
 			('=', TokenType::Equals),
-			(':', TokenType::Colon)
+			(':', TokenType::Colon),
+			('?', TokenType::Question),
+			('#', TokenType::Hash)
 		]);
 		let second_special_chars = HashSet::from([
-			'=', '>'
+			'=', '<', '>', '&', '|'
 		]);
 		let keywords = HashMap::from([
 			("struct", TokenType::Struct),
-			("ret", TokenType::Ret)
+			("ret", TokenType::Ret),
+			("as", TokenType::As),
+			("break", TokenType::Break),
+			("extern", TokenType::Extern),
+			("assert", TokenType::Assert),
+			("if", TokenType::If),
+			("else", TokenType::Else),
+			("for", TokenType::For),
+			("import", TokenType::Import),
+			("const", TokenType::Const)
 		]);
-		Self {src: src.chars().peekable(), read: 0, special_chars, second_special_chars,
-		keywords, next: [None, None], emitter, has_error: false}
+		Self {src: CharStream::new(input), read: 0, special_chars, second_special_chars,
+		keywords, literal_hooks: HashMap::new(), next: VecDeque::new(), emitter, has_error: false, newlines: false,
+		open_sections: Vec::new(), sections: Vec::new(), unexpected_chars: 0, error_limit: 20}
+	}
+
+	/// Makes the lexer emit a `Newline` token for every line break instead of
+	/// skipping it like other whitespace.
+	pub fn with_newlines(mut self, newlines: bool) -> Self {
+		self.newlines = newlines;
+		self
+	}
+
+	/// Caps how many unexpected characters are reported, 20 by default. Any further
+	/// ones are skipped without a diagnostic, so a binary file doesn't flood the output.
+	pub fn with_error_limit(mut self, error_limit: usize) -> Self {
+		self.error_limit = error_limit;
+		self
+	}
+
+	/// Registers a custom literal starting with `prefix`, e.g. `#` for `#FF00FF`.
+	/// The hook gets the text after the prefix up to the next whitespace or special
+	/// character and returns the token to produce, or `None` to lex the prefix as usual.
+	pub fn with_literal_hook(mut self, prefix: char,
+	                         hook: impl Fn(&str) -> Option<TokenType> + 'static) -> Self {
+		let hook: LiteralHook = Box::new(hook);
+		self.literal_hooks.insert(prefix, hook);
+		self
+	}
+
+	/// The `#if` sections closed so far, in the order of their `#endif`.
+	pub fn conditional_sections(&self) -> &[ConditionalSection] {
+		&self.sections
 	}
 
 	pub fn peek(&mut self, count: PeekCount) -> Option<Token> {
-		match count {
-			PeekCount::One => {
-				if let Some(token) = &self.next[0] {
-					return Some(token.clone());
-				}
-				let token = self.next_internal();
-				self.next[0] = token;
-				self.next[0].clone()
-			},
-			PeekCount::Two => {
-				if let Some(token) = &self.next[1] {
-					return Some(token.clone());
-				}
-				if self.next[0].is_none() {
-					self.next[0] = self.next_internal();
-				}
-				let token = self.next_internal();
-				self.next[1] = token;
-				self.next[1].clone()
-			}
+		self.peek_nth(count as usize)
+	}
+
+	/// The token `n` tokens ahead, where 0 is the next one.
+	pub fn peek_nth(&mut self, n: usize) -> Option<Token> {
+		while self.next.len() <= n {
+			let token = self.next_internal()?;
+			self.next.push_back(token);
 		}
+		self.next.get(n).cloned()
 	}
 
 	pub fn next(&mut self) -> Option<Token> {
-		if let Some(token) = self.next[0].take() {
-			self.next[0] = self.next[1].take();
-			return Some(token);
-		}
-		if let Some(token) = self.next[1].take() {
-			return Some(token);
-		}
-		self.next_internal()
+		self.next.pop_front().or_else(|| self.next_internal())
 	}
 
 	fn next_internal(&mut self) -> Option<Token> {
 		loop {
 			let start = self.read;
 
-			let char = self.src.next()?;
-			self.read += 1;
+			let char = match self.src.next() {
+				Some(char) => char,
+				None => {
+					self.report_open_sections();
+					self.report_input_error();
+					return None;
+				}
+			};
+			self.read += char.len_utf8();
 
-			if char.is_whitespace() {
+			if char == '\n' && self.newlines {
+				return Some(Token::new(TokenType::Newline, start..self.read));
+			}
+			// A byte order mark at the start of the file is skipped like whitespace. `read`
+			// still counts its bytes so spans match the source.
+			else if char.is_whitespace() || (char == '\u{FEFF}' && start == 0) {
+				continue;
+			}
+			else if char.is_control() {
+				if self.unexpected_chars < self.error_limit {
+					self.emitter.error()
+						.with_label(format!("unexpected character `{}`", char.escape_default()))
+						.with_span(start..self.read)
+						.emit();
+				}
+				self.unexpected_chars += 1;
+				self.has_error = true;
 				continue;
 			}
+			else if char == '/' && self.src.peek_nth(0) == Some('/') && self.src.peek_nth(1) == Some('/') {
+				self.src.next();
+				self.src.next();
+				self.read += 2;
+
+				let mut text = String::new();
+				while let Some(char) = self.src.next_if(|c| *c != '\n') {
+					text.push(char);
+					self.read += char.len_utf8();
+				}
+
+				return Some(Token::new(TokenType::DocComment(text.trim().to_string()), start..self.read));
+			}
+			else if char == '/' && self.src.next_if(|c| *c == '/').is_some() {
+				self.read += 1;
+				while let Some(char) = self.src.next_if(|c| *c != '\n') {
+					self.read += char.len_utf8();
+				}
+			}
+			else if char == '/' && self.src.next_if(|c| *c == '*').is_some() {
+				self.read += 1;
+				self.skip_block_comment(start);
+			}
+			else if char == '#' && self.lex_directive(start) {
+				continue;
+			}
+			else if let Some(token_type) = self.lex_custom_literal(char) {
+				return Some(Token::new(token_type, start..self.read));
+			}
 			else if let Some(first) = self.special_chars.get(&char) {
+				if char == '.' && self.src.next_if(|c| *c == '.').is_some() {
+					self.read += 1;
+					let token_type = if self.src.next_if(|c| *c == '=').is_some() {
+						self.read += 1;
+						TokenType::DotDotEquals
+					}
+					else {
+						TokenType::DotDot
+					};
+					return Some(Token::new(token_type, start..self.read));
+				}
+
 				let mut token_type = first.clone();
 				let mut text = String::from(char);
 				if let Some(second) = self.src.peek() {
-					if self.second_special_chars.contains(second) {
-						if let TokenType::BinOp(op) = token_type {
-							if *second == '=' {
-								token_type = TokenType::BinOpEquals(op);
-							}
-							else {
-								token_type = TokenType::Arrow;
-							}
-							text.push(*second);
+					if self.second_special_chars.contains(&second) {
+						let combined = match &token_type {
+							TokenType::Equals if second == '=' => Some(TokenType::BinOp(BinOp::Equal)),
+							TokenType::BinOp(BinOp::Not) if second == '=' => Some(TokenType::BinOp(BinOp::NotEqual)),
+							TokenType::BinOp(BinOp::Less) if second == '=' => Some(TokenType::BinOp(BinOp::LessEqual)),
+							TokenType::BinOp(BinOp::Greater) if second == '=' => Some(TokenType::BinOp(BinOp::GreaterEqual)),
+							TokenType::BinOp(BinOp::BitAnd) if second == '&' => Some(TokenType::BinOp(BinOp::And)),
+							TokenType::BinOp(BinOp::BitOr) if second == '|' => Some(TokenType::BinOp(BinOp::Or)),
+							TokenType::BinOp(BinOp::Less) if second == '<' => Some(TokenType::BinOp(BinOp::ShiftLeft)),
+							TokenType::BinOp(BinOp::Greater) if second == '>' => Some(TokenType::BinOp(BinOp::ShiftRight)),
+							TokenType::BinOp(op) if second == '=' => Some(TokenType::BinOpEquals(op.clone())),
+							TokenType::BinOp(BinOp::Minus) if second == '>' => Some(TokenType::Arrow),
+							_ => None
+						};
+						if let Some(combined) = combined {
+							token_type = combined;
+							text.push(second);
 							self.src.next();
 							self.read += 1;
 						}
+						// `<<=` and `>>=` are the only three character operators.
+						if let TokenType::BinOp(op @ (BinOp::ShiftLeft | BinOp::ShiftRight)) = &token_type {
+							if self.src.next_if(|c| *c == '=').is_some() {
+								self.read += 1;
+								token_type = TokenType::BinOpEquals(op.clone());
+							}
+						}
 					}
 				}
 
 				return Some(Token::new(token_type, start..self.read));
 			}
+			else if let Some(label) = self.lex_label(char) {
+				return Some(Token::new(TokenType::Label(label), start..self.read));
+			}
 			else if ['"', '\''].contains(&char) {
 				let start_char = char;
 				let mut text = String::new();
 				while let Some(char) = self.src.next_if(|c| *c != start_char) {
+					let char_start = self.read;
+					self.read += char.len_utf8();
 					if char == '\\' {
-						if let Some(next) = self.src.peek() {
-							match *next {
-								'n' => text.push('\n'),
-								't' => text.push('\t'),
-								'\\' => text.push('\\'),
-								'0' => text.push('\0'),
-								e => {
-									self.emitter.error().with_label(
-										format!("invalid escape sequence {}", e))
-										.with_span(self.read..self.read+1)
-										.emit();
-									self.has_error = true;
-								}
-							}
-							self.src.next();
-							self.read += 1;
-						}
+						self.lex_escape(char_start, &mut text);
 					}
 					else {
 						text.push(char);
 					}
-					self.read += 1;
 				}
 
 				let is_char_literal = start_char == '\'';
@@ -310,6 +768,7 @@ impl<'source, W: Write> Lexer<'source, W> {
 				}
 				else {
 					self.src.next();
+					self.read += 1;
 				}
 
 				if start_char == '\'' && len > 1 {
@@ -321,23 +780,39 @@ impl<'source, W: Write> Lexer<'source, W> {
 
 				return Some(Token::new(token_type(text), start..self.read));
 			}
+			else if char.is_ascii_digit() {
+				let token_type = self.lex_number(start, char);
+				return Some(Token::new(token_type, start..self.read));
+			}
 			else {
 				let mut text = String::from(char);
 
 				while let Some(char) = self.src.next_if(|c| {
-					!c.is_whitespace() && !self.special_chars.contains_key(c)
+					!c.is_whitespace() && !c.is_control() && !self.special_chars.contains_key(c)
 				}) {
 					text.push(char);
-					self.read += 1;
+					self.read += char.len_utf8();
+				}
+
+				// The identifier keeps the backslash so the parser sees one token.
+				for (offset, _) in text.match_indices('\\') {
+					self.emitter.error()
+						.with_label("backslash in identifier, escape sequences are only allowed in string and character literals")
+						.with_span(start + offset..start + offset + 1)
+						.emit();
+					self.has_error = true;
 				}
 
-				let is_number = text.chars().all(|c| c.is_digit(10));
+				// `_100` is a number with a misplaced separator rather than a name
+				let digits = text.trim_start_matches('_');
+				if digits.starts_with(|c: char| c.is_ascii_digit()) && digits.chars().all(|c| c.is_ascii_digit() || c == '_') {
+					self.check_underscores(start, &text, 10);
+					let value = text.replace('_', "").parse().unwrap_or(0);
+					return Some(Token::new(TokenType::Num(value), start..self.read));
+				}
 
 				let token_type;
-				if is_number {
-					token_type = TokenType::Num(text.parse().unwrap());
-				}
-				else if let Some(k) = self.keywords.get(text.as_str()) {
+				if let Some(k) = self.keywords.get(text.as_str()) {
 					token_type = k.clone();
 				}
 				else {
@@ -349,7 +824,397 @@ impl<'source, W: Write> Lexer<'source, W> {
 		}
 	}
 
+	/// Consumes the name of a label like `'outer` after its `'`, which unlike a character
+	/// literal isn't closed by another `'`.
+	fn lex_label(&mut self, quote: char) -> Option<String> {
+		if quote != '\'' {
+			return None;
+		}
+		let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+		if !self.src.peek().is_some_and(|c| c.is_alphabetic() || c == '_') {
+			return None;
+		}
+		let mut len = 1;
+		while self.src.peek_nth(len).is_some_and(is_name_char) {
+			len += 1;
+		}
+		if self.src.peek_nth(len) == Some('\'') {
+			return None;
+		}
+
+		let mut label = String::new();
+		for _ in 0..len {
+			let char = self.src.next().unwrap();
+			self.read += char.len_utf8();
+			label.push(char);
+		}
+		Some(label)
+	}
+
+	/// Lexes a number starting with the digit `first` at `start`. A fraction (`1.5`) or an
+	/// exponent (`1e-9`) makes it a `Float`. Underscores may separate digits, but only
+	/// with a digit on both sides, so `1_.5` and `1.5_e3` are errors.
+	fn lex_number(&mut self, start: usize, first: char) -> TokenType {
+		let radix = match (first, self.src.peek()) {
+			('0', Some('x')) => Some((16, "hexadecimal")),
+			('0', Some('o')) => Some((8, "octal")),
+			('0', Some('b')) => Some((2, "binary")),
+			_ => None
+		};
+		if let Some((radix, radix_name)) = radix {
+			return self.lex_radix_number(start, radix, radix_name);
+		}
+
+		let mut text = String::from(first);
+		self.take_digits(&mut text);
+
+		let mut is_float = false;
+		if self.src.peek() == Some('.')
+			&& self.src.peek_nth(1).is_some_and(|c| c.is_ascii_digit() || c == '_') {
+			self.src.next();
+			self.read += 1;
+			text.push('.');
+			self.take_digits(&mut text);
+			is_float = true;
+		}
+
+		if matches!(self.src.peek(), Some('e' | 'E')) {
+			let prefix_len = if matches!(self.src.peek_nth(1), Some('+' | '-')) { 2 } else { 1 };
+			if self.src.peek_nth(prefix_len).is_some_and(|c| c.is_ascii_digit() || c == '_') {
+				for _ in 0..prefix_len {
+					text.push(self.src.next().unwrap());
+					self.read += 1;
+				}
+				self.take_digits(&mut text);
+				is_float = true;
+			}
+		}
+
+		let mut suffix = String::new();
+		while let Some(char) = self.src.next_if(|c| {
+			!c.is_whitespace() && !self.special_chars.contains_key(c)
+		}) {
+			suffix.push(char);
+			self.read += char.len_utf8();
+		}
+		if !suffix.is_empty() {
+			self.emitter.error()
+				.with_label(format!("invalid suffix `{}` on number literal", suffix))
+				.with_span(start + text.len()..self.read)
+				.emit();
+			self.has_error = true;
+		}
+
+		self.check_underscores(start, &text, 10);
+
+		let digits = text.replace('_', "");
+		if is_float {
+			match digits.parse::<f64>() {
+				Ok(value) if value.is_finite() => TokenType::Float(value),
+				_ => {
+					self.emitter.error()
+						.with_label(format!("float literal `{}` is too large for an f64", text))
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+					TokenType::Float(0.0)
+				}
+			}
+		}
+		else {
+			match digits.parse() {
+				Ok(value) => TokenType::Num(value),
+				Err(_) => {
+					self.emitter.error()
+						.with_label(format!("integer literal `{}` is too large for a u64", text))
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+					TokenType::Num(0)
+				}
+			}
+		}
+	}
+
+	/// Lexes the rest of a `0x`, `0o` or `0b` integer after its `0` at `start`. The span
+	/// covers the prefix.
+	fn lex_radix_number(&mut self, start: usize, radix: u32, radix_name: &str) -> TokenType {
+		let prefix = self.src.next().unwrap();
+		self.read += 1;
+		let digits_start = self.read;
+
+		let mut text = String::new();
+		while let Some(char) = self.src.next_if(|c| c.is_alphanumeric() || *c == '_') {
+			text.push(char);
+			self.read += char.len_utf8();
+		}
+
+		if let Some((i, digit)) = text.char_indices().find(|(_, c)| *c != '_' && !c.is_digit(radix)) {
+			self.emitter.error()
+				.with_label(format!("invalid digit `{}` in {} literal", digit, radix_name))
+				.with_span(digits_start + i..digits_start + i + digit.len_utf8())
+				.emit();
+			self.has_error = true;
+			return TokenType::Num(0);
+		}
+		if text.is_empty() {
+			self.emitter.error()
+				.with_label(format!("expected {} digits after `0{}`", radix_name, prefix))
+				.with_span(start..self.read)
+				.emit();
+			self.has_error = true;
+			return TokenType::Num(0);
+		}
+		self.check_underscores(digits_start, &text, radix);
+
+		match u64::from_str_radix(&text.replace('_', ""), radix) {
+			Ok(value) => TokenType::Num(value),
+			Err(_) => {
+				self.emitter.error()
+					.with_label(format!("{} literal `0{}{}` is too large for a u64", radix_name, prefix, text))
+					.with_span(start..self.read)
+					.emit();
+				self.has_error = true;
+				TokenType::Num(0)
+			}
+		}
+	}
+
+	/// Reports the first underscore in the number `text`, which starts at `start`, that
+	/// isn't between two digits of `radix`.
+	fn check_underscores(&mut self, start: usize, text: &str, radix: u32) {
+		let chars: Vec<char> = text.chars().collect();
+		let is_digit = |i: Option<&char>| i.is_some_and(|c| c.is_digit(radix));
+		let misplaced = (0..chars.len()).find(|&i| chars[i] == '_'
+			&& !(i > 0 && is_digit(chars.get(i - 1)) && is_digit(chars.get(i + 1))));
+		if let Some(i) = misplaced {
+			self.emitter.error()
+				.with_label("underscores in a number must be between two digits")
+				.with_span(start + i..start + i + 1)
+				.emit();
+			self.has_error = true;
+		}
+	}
+
+	fn take_digits(&mut self, text: &mut String) {
+		while let Some(char) = self.src.next_if(|c| c.is_ascii_digit() || *c == '_') {
+			text.push(char);
+			self.read += 1;
+		}
+	}
+
+	/// Skips the rest of a block comment opened at `start`. Block comments nest, so
+	/// every `/*` inside needs its own `*/`. Quotes have no meaning inside a comment.
+	fn skip_block_comment(&mut self, start: usize) {
+		let mut depth = 1;
+		while let Some(char) = self.src.next() {
+			self.read += char.len_utf8();
+			if char == '*' && self.src.next_if(|c| *c == '/').is_some() {
+				self.read += 1;
+				depth -= 1;
+				if depth == 0 {
+					return;
+				}
+			}
+			else if char == '/' && self.src.next_if(|c| *c == '*').is_some() {
+				self.read += 1;
+				depth += 1;
+			}
+		}
+
+		self.emitter.error()
+			.with_label("unterminated block comment")
+			.with_span(start..self.read)
+			.emit();
+		self.has_error = true;
+	}
+
+	/// Consumes `#if name` or `#endif` after the `#` at `start`, returning false for
+	/// any other use of `#`.
+	fn lex_directive(&mut self, start: usize) -> bool {
+		let mut word = String::new();
+		while let Some(char) = self.src.peek_nth(word.len()).filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+			word.push(char);
+		}
+		if word != "if" && word != "endif" {
+			return false;
+		}
+		for _ in 0..word.len() {
+			self.src.next();
+		}
+		self.read += word.len();
+
+		if word == "endif" {
+			match self.open_sections.pop() {
+				Some((condition, section_start)) => self.sections.push(ConditionalSection {
+					condition, span: section_start..self.read
+				}),
+				None => {
+					self.emitter.error()
+						.with_label("`#endif` without a matching `#if`")
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+				}
+			}
+			return true;
+		}
+
+		while let Some(char) = self.src.next_if(|c| *c == ' ' || *c == '\t') {
+			self.read += char.len_utf8();
+		}
+		let condition_start = self.read;
+		let mut condition = String::new();
+		while let Some(char) = self.src.next_if(|c| !c.is_whitespace()) {
+			condition.push(char);
+			self.read += char.len_utf8();
+		}
+		if condition.is_empty() {
+			self.emitter.error()
+				.with_label("expected a condition after `#if`")
+				.with_span(start..self.read)
+				.emit();
+			self.has_error = true;
+		}
+		self.open_sections.push(((condition, condition_start..self.read), start));
+		true
+	}
+
+	fn report_open_sections(&mut self) {
+		for ((_, condition_span), start) in std::mem::take(&mut self.open_sections) {
+			self.emitter.error()
+				.with_label("unterminated `#if`, expected `#endif` before the end of the file")
+				.with_span(start..condition_span.end)
+				.emit();
+			self.has_error = true;
+		}
+	}
+
+	fn lex_custom_literal(&mut self, prefix: char) -> Option<TokenType> {
+		let hook = self.literal_hooks.get(&prefix)?;
+
+		let mut text = String::new();
+		while let Some(char) = self.src.peek_nth(text.chars().count()) {
+			if char.is_whitespace() || self.special_chars.contains_key(&char) {
+				break;
+			}
+			text.push(char);
+		}
+
+		let token_type = hook(&text)?;
+		for _ in text.chars() {
+			self.src.next();
+		}
+		self.read += text.len();
+		Some(token_type)
+	}
+
+	fn report_input_error(&mut self) {
+		if let Some(error) = self.src.input.take_error() {
+			self.emitter.error().with_label(format!("could not read source: {}", error))
+				.with_span(self.read..self.read)
+				.emit();
+			self.has_error = true;
+		}
+	}
+
+	/// Decodes the escape sequence following a `\` at `start` into `text`.
+	/// `\xNN` takes exactly two hex digits and `\NNN` one to three octal digits, with `\0`
+	/// the shortest octal escape. Both are limited to ASCII (at most `\x7F` or `\177`),
+	/// so every escape decodes to a single-byte character.
+	fn lex_escape(&mut self, start: usize, text: &mut String) {
+		let escape = match self.src.next() {
+			Some(escape) => escape,
+			None => return
+		};
+		self.read += escape.len_utf8();
+
+		match escape {
+			'n' => text.push('\n'),
+			't' => text.push('\t'),
+			'\\' => text.push('\\'),
+			'0'..='7' => {
+				let mut digits = String::from(escape);
+				while digits.len() < 3 {
+					match self.src.next_if(|c| ('0'..='7').contains(c)) {
+						Some(digit) => {
+							digits.push(digit);
+							self.read += 1;
+						}
+						None => break
+					}
+				}
+
+				let value = u32::from_str_radix(&digits, 8).unwrap();
+				if value > 0o177 {
+					self.emitter.error()
+						.with_label(format!("octal escape \\{} is out of range, must be at most \\177", digits))
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+				}
+				else {
+					text.push(value as u8 as char);
+				}
+			}
+			'x' => {
+				let mut digits = String::new();
+				while digits.len() < 2 {
+					match self.src.next_if(|c| c.is_ascii_hexdigit()) {
+						Some(digit) => {
+							digits.push(digit);
+							self.read += 1;
+						}
+						None => break
+					}
+				}
+
+				if digits.len() < 2 {
+					self.emitter.error()
+						.with_label("invalid hex escape, expected exactly two hex digits after \\x")
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+					return;
+				}
+
+				let value = u8::from_str_radix(&digits, 16).unwrap();
+				if value > 0x7F {
+					self.emitter.error()
+						.with_label(format!("hex escape \\x{} is out of range, must be at most \\x7F", digits))
+						.with_span(start..self.read)
+						.emit();
+					self.has_error = true;
+				}
+				else {
+					text.push(value as char);
+				}
+			}
+			e => {
+				self.emitter.error().with_label(
+					format!("invalid escape sequence {}", e))
+					.with_span(start..self.read)
+					.emit();
+				self.has_error = true;
+			}
+		}
+	}
+
 	pub fn has_error(&self) -> bool {
 		self.has_error
 	}
+
+	/// Drains the lexer into the kind and span of each token, without the `Eof`. Each
+	/// token is still lexed in full and its payload dropped, so this saves keeping the
+	/// payloads around, not building them.
+	pub fn lex_ranges(&mut self) -> Vec<(TokenKind, Span)> {
+		std::iter::from_fn(|| self.next()).map(|token| (token.kind.kind(), token.span)).collect()
+	}
+
+	/// Drains the lexer, ending the tokens with an `Eof` at the end of input.
+	pub fn tokenize_all(&mut self) -> Vec<Token> {
+		let mut tokens: Vec<_> = std::iter::from_fn(|| self.next()).collect();
+		tokens.push(Token::new(TokenType::Eof, self.read..self.read));
+		tokens
+	}
 }
\ No newline at end of file
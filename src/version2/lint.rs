@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use crate::ast::{self, ArrayLen, Expr, Field, Spanned, Type, BUILTIN_TYPES, INTRINSICS};
+use crate::diagnostics::{DiagnosticEmitter, Span};
+use crate::lexer::{BinOp, SourceMap, Token, TokenType};
+
+/// The functions provided by the runtime. Together with the parser's `INTRINSICS` they
+/// are the builtins user declarations shouldn't reuse.
+pub const BUILTINS: &[&str] = &["print"];
+
+/// Optional style and semantic checks. Every lint is off until enabled with its
+/// `with_*` method.
+pub struct Linter<'source, W: Write> {
+	map: &'source SourceMap<'source>,
+	emitter: &'source DiagnosticEmitter<'source, W>,
+	operator_spacing: bool,
+	cast_truncation: bool,
+	builtin_shadowing: bool,
+	missing_fields: bool,
+	float_equality: bool,
+	type_assignments: bool,
+	array_lengths: bool
+}
+
+/// What a name in scope refers to, for the array length check.
+#[derive(Clone)]
+enum Binding {
+	/// A `const`, with whether its value is an integer.
+	Const(bool),
+	/// A variable or argument, with the span of its name.
+	Variable(Span)
+}
+
+impl<'source, W: Write> Linter<'source, W> {
+	pub fn new(map: &'source SourceMap<'source>, emitter: &'source DiagnosticEmitter<'source, W>) -> Self {
+		Self {map, emitter, operator_spacing: false, cast_truncation: false, builtin_shadowing: false,
+			missing_fields: false, float_equality: false, type_assignments: false, array_lengths: false}
+	}
+
+	/// Warns about binary and assignment operators without whitespace on both sides, like `a+b`.
+	pub fn with_operator_spacing(mut self, operator_spacing: bool) -> Self {
+		self.operator_spacing = operator_spacing;
+		self
+	}
+
+	/// Warns about casts from an integer type to a narrower one, like `x as u8` where `x`
+	/// is a `u64`.
+	pub fn with_cast_truncation(mut self, cast_truncation: bool) -> Self {
+		self.cast_truncation = cast_truncation;
+		self
+	}
+
+	/// Warns about variables and arguments named like a builtin, e.g. `print: int = 0;`.
+	pub fn with_builtin_shadowing(mut self, builtin_shadowing: bool) -> Self {
+		self.builtin_shadowing = builtin_shadowing;
+		self
+	}
+
+	/// Reports constructs like `Foo {}` that leave out fields of `Foo` without a default.
+	/// Only the struct's own fields are checked, not those of its base.
+	/// Unlike the other lints these are errors.
+	pub fn with_missing_fields(mut self, missing_fields: bool) -> Self {
+		self.missing_fields = missing_fields;
+		self
+	}
+
+	/// Warns about `==` and `!=` with a float literal operand, like `a == 1.0`, since
+	/// rounding makes exact float comparisons unreliable.
+	pub fn with_float_equality(mut self, float_equality: bool) -> Self {
+		self.float_equality = float_equality;
+		self
+	}
+
+	/// Warns about `x = int;` where `x` isn't declared yet, which likely meant to declare
+	/// `x: int`. Builtin types and the structs in the file count as type names.
+	pub fn with_type_assignments(mut self, type_assignments: bool) -> Self {
+		self.type_assignments = type_assignments;
+		self
+	}
+
+	/// Reports array types like `[int; N]` where `N` isn't a `const` with an integer
+	/// value. Like missing fields these are errors.
+	pub fn with_array_lengths(mut self, array_lengths: bool) -> Self {
+		self.array_lengths = array_lengths;
+		self
+	}
+
+	/// Runs the enabled token-level lints over `tokens`, lexed from this linter's source.
+	pub fn check_tokens(&self, tokens: &[Token]) {
+		if self.operator_spacing {
+			self.check_operator_spacing(tokens);
+		}
+	}
+
+	/// Runs the enabled lints that need the AST.
+	pub fn check_ast(&self, ast: &[Expr]) {
+		if self.cast_truncation {
+			let mut globals = HashMap::new();
+			for expr in ast {
+				self.check_casts(expr, &mut globals);
+			}
+		}
+		if self.builtin_shadowing {
+			for expr in ast {
+				self.check_shadowing(expr);
+			}
+		}
+		if self.missing_fields {
+			let structs: HashMap<_, _> = ast::structs(ast).filter_map(|expr| match expr {
+				Expr::Struct {name, fields, ..} => Some((name.0.as_str(), (name, fields))),
+				_ => None
+			}).collect();
+			for expr in ast {
+				self.check_constructs(expr, &structs);
+			}
+		}
+		if self.float_equality {
+			for expr in ast {
+				self.check_float_equality(expr);
+			}
+		}
+		if self.type_assignments {
+			let types: HashSet<_> = ast::structs(ast).filter_map(|expr| match expr {
+				Expr::Struct {name, ..} => Some(name.0.as_str()),
+				_ => None
+			}).collect();
+			let mut globals = HashSet::new();
+			for expr in ast {
+				self.check_type_assignments(expr, &mut globals, &types);
+			}
+		}
+		if self.array_lengths {
+			// Top-level constants are visible to the functions declared before them
+			let mut globals = HashMap::new();
+			for expr in ast {
+				if let Expr::Const {name, value, ..} = expr {
+					let binding = const_binding(value, &globals);
+					globals.insert(name.0.as_str(), binding);
+				}
+			}
+			for expr in ast {
+				self.check_array_lengths(expr, &mut globals);
+			}
+		}
+	}
+
+	/// Checks the array types in `expr`, with `scope` holding the names declared so far.
+	fn check_array_lengths<'a>(&self, expr: &'a Expr, scope: &mut HashMap<&'a str, Binding>) {
+		if let Expr::Function {args, ret_type, body, ..} = expr {
+			for (_, r#type) in args.iter() {
+				self.check_array_type(r#type, scope);
+			}
+			if let Some(ret_type) = ret_type {
+				self.check_array_type(ret_type, scope);
+			}
+			let mut locals = scope.clone();
+			for ((name, span), _) in args {
+				locals.insert(name, Binding::Variable(span.clone()));
+			}
+			for expr in body.iter().flatten() {
+				self.check_array_lengths(expr, &mut locals);
+			}
+			return;
+		}
+
+		for child in expr.children() {
+			self.check_array_lengths(child, scope);
+		}
+
+		match expr {
+			Expr::Const {name, value, ..} => {
+				let binding = const_binding(value, scope);
+				scope.insert(&name.0, binding);
+			}
+			Expr::VarDecl {name, r#type, ..} => {
+				self.check_array_type(r#type, scope);
+				scope.insert(&name.0, Binding::Variable(name.1.clone()));
+			}
+			Expr::MultiVarDecl {names, r#type, ..} => {
+				self.check_array_type(r#type, scope);
+				for (name, span) in names {
+					scope.insert(name, Binding::Variable(span.clone()));
+				}
+			}
+			Expr::Assign {target, ..} => {
+				if let Expr::Var((name, span)) = &**target {
+					scope.entry(name).or_insert_with(|| Binding::Variable(span.clone()));
+				}
+			}
+			Expr::Struct {fields, ..} => {
+				for field in fields {
+					self.check_array_type(&field.r#type, scope);
+				}
+			}
+			Expr::Cast {ty, ..} => self.check_array_type(ty, scope),
+			_ => {}
+		}
+	}
+
+	fn check_array_type(&self, ty: &Type, scope: &HashMap<&str, Binding>) {
+		let (element, name, span) = match ty {
+			Type::Named(_) => return,
+			Type::Optional(inner) => return self.check_array_type(inner, scope),
+			Type::Tuple((types, _)) => return types.iter().for_each(|ty| self.check_array_type(ty, scope)),
+			Type::Array(element, ArrayLen::Num(_), _) => return self.check_array_type(element, scope),
+			Type::Array(element, ArrayLen::Const((name, span)), _) => (element, name, span)
+		};
+		self.check_array_type(element, scope);
+
+		match scope.get(name.as_str()) {
+			Some(Binding::Const(true)) => {}
+			Some(Binding::Const(false)) => {
+				self.emitter.error()
+					.with_label(format!("array length `{}` is not an integer constant", name))
+					.with_span(span.clone())
+					.emit();
+			}
+			Some(Binding::Variable(decl)) => {
+				self.emitter.error()
+					.with_label(format!("array length `{}` is a variable, not a constant", name))
+					.with_span(span.clone())
+					.with_secondary(decl.clone(), format!("`{}` declared here", name))
+					.with_help(format!("declare it with `const {} = ...;`", name))
+					.emit();
+			}
+			None => {
+				self.emitter.error()
+					.with_label(format!("cannot find constant `{}` for the array length", name))
+					.with_span(span.clone())
+					.emit();
+			}
+		}
+	}
+
+	/// Checks the assignments in `expr`, with `scope` holding the variables declared so far.
+	fn check_type_assignments<'a>(&self, expr: &'a Expr, scope: &mut HashSet<&'a str>, types: &HashSet<&str>) {
+		match expr {
+			Expr::Function {args, body: Some(body), ..} => {
+				let mut locals = scope.clone();
+				locals.extend(args.iter().map(|((name, _), _)| name.as_str()));
+				for expr in body {
+					self.check_type_assignments(expr, &mut locals, types);
+				}
+				return;
+			}
+			Expr::Assign {target, value, ..} => {
+				if let (Expr::Var((name, _)), Expr::Var((value, span))) = (&**target, &**value) {
+					let is_type = types.contains(value.as_str()) || BUILTIN_TYPES.contains(&value.as_str());
+					if is_type && !scope.contains(name.as_str()) {
+						self.emitter.warning()
+							.with_label(format!("`{}` is a type, not a value", value))
+							.with_span(span.clone())
+							.with_help(format!("did you mean `{}: {}`?", name, value))
+							.emit();
+					}
+				}
+				if let Expr::Var((name, _)) = &**target {
+					scope.insert(name);
+				}
+			}
+			Expr::VarDecl {name, ..} => {
+				scope.insert(&name.0);
+			}
+			Expr::MultiVarDecl {names, ..} => {
+				scope.extend(names.iter().map(|(name, _)| name.as_str()));
+			}
+			// Declarations inside a body end with it
+			Expr::If {cond, then_body, else_body, ..} => {
+				self.check_type_assignments(cond, scope, types);
+				for body in std::iter::once(then_body).chain(else_body) {
+					let mut locals = scope.clone();
+					for expr in body {
+						self.check_type_assignments(expr, &mut locals, types);
+					}
+				}
+				return;
+			}
+			Expr::For {..} | Expr::Block {..} => {
+				let mut locals = scope.clone();
+				for child in expr.children() {
+					self.check_type_assignments(child, &mut locals, types);
+				}
+				return;
+			}
+			_ => {}
+		}
+		for child in expr.children() {
+			self.check_type_assignments(child, scope, types);
+		}
+	}
+
+	fn check_float_equality(&self, expr: &Expr) {
+		if let Expr::Eq(lhs, rhs) | Expr::Ne(lhs, rhs) = expr {
+			if is_float_literal(lhs) || is_float_literal(rhs) {
+				let op = if matches!(expr, Expr::Eq(..)) { "==" } else { "!=" };
+				if let Some(span) = expr.span() {
+					self.emitter.warning()
+						.with_label(format!("comparing floats with `{}` is unreliable", op))
+						.with_span(span)
+						.with_help("compare the difference against a small epsilon instead")
+						.emit();
+				}
+			}
+		}
+		for child in expr.children() {
+			self.check_float_equality(child);
+		}
+	}
+
+	fn check_constructs(&self, expr: &Expr, structs: &HashMap<&str, (&Spanned<String>, &Vec<Field>)>) {
+		match expr {
+			Expr::Construct {name, fields: given, ..} => {
+				if let Some((decl_name, fields)) = structs.get(name.0.as_str()) {
+					let missing: Vec<_> = fields.iter()
+						.filter(|field| !given.iter().any(|(given, _)| given.0 == field.name.0))
+						.collect();
+					self.report_missing_fields(name, decl_name, &missing);
+				}
+			}
+			// Positional values fill the fields in declaration order
+			Expr::PositionalConstruct {name, values, ..} => {
+				if let Some((decl_name, fields)) = structs.get(name.0.as_str()) {
+					if values.len() > fields.len() {
+						self.emitter.error()
+							.with_label(format!("too many values in construct of `{}`, expected at most {}",
+								name.0, fields.len()))
+							.with_span(name.1.clone())
+							.with_secondary(decl_name.1.clone(), format!("`{}` declared here", name.0))
+							.emit();
+					}
+					let missing: Vec<_> = fields.iter().skip(values.len()).collect();
+					self.report_missing_fields(name, decl_name, &missing);
+				}
+			}
+			_ => {}
+		}
+		for child in expr.children() {
+			self.check_constructs(child, structs);
+		}
+	}
+
+	/// Reports the fields of `fields` without a default, which a construct of `name` left out.
+	fn report_missing_fields(&self, name: &Spanned<String>, decl_name: &Spanned<String>, fields: &[&Field]) {
+		let missing: Vec<_> = fields.iter()
+			.filter(|field| field.default.is_none())
+			.map(|field| format!("`{}`", field.name.0))
+			.collect();
+		if !missing.is_empty() {
+			self.emitter.error()
+				.with_label(format!("missing {} {} in construct of `{}`",
+					if missing.len() == 1 { "field" } else { "fields" }, missing.join(", "), name.0))
+				.with_span(name.1.clone())
+				.with_secondary(decl_name.1.clone(), format!("`{}` declared here", name.0))
+				.emit();
+		}
+	}
+
+	fn check_shadowing(&self, expr: &Expr) {
+		match expr {
+			Expr::VarDecl {name, ..} => self.check_shadowed_name(name),
+			Expr::MultiVarDecl {names, ..} => names.iter().for_each(|name| self.check_shadowed_name(name)),
+			Expr::Function {name, args, ..} => {
+				self.check_shadowed_name(name);
+				args.iter().for_each(|(name, _)| self.check_shadowed_name(name));
+			}
+			_ => {}
+		}
+		for child in expr.children() {
+			self.check_shadowing(child);
+		}
+	}
+
+	fn check_shadowed_name(&self, (name, span): &Spanned<String>) {
+		if BUILTINS.contains(&name.as_str()) || INTRINSICS.contains(&name.as_str()) {
+			self.emitter.warning()
+				.with_label(format!("`{}` shadows the builtin of the same name", name))
+				.with_span(span.clone())
+				.emit();
+		}
+	}
+
+	/// Checks the casts in `expr`, with `scope` holding the declared type of each variable
+	/// visible so far.
+	fn check_casts<'a>(&self, expr: &'a Expr, scope: &mut HashMap<&'a str, &'a Type>) {
+		if let Expr::Function {args, body: Some(body), ..} = expr {
+			let mut locals = scope.clone();
+			for ((name, _), r#type) in args {
+				locals.insert(name, r#type);
+			}
+			for expr in body {
+				self.check_casts(expr, &mut locals);
+			}
+			return;
+		}
+
+		for child in expr.children() {
+			self.check_casts(child, scope);
+		}
+
+		match expr {
+			Expr::Cast {expr: inner, ty} => {
+				let from = match int_type(inner, scope) {
+					Some(from) => from,
+					None => return
+				};
+				if let (Type::Named((from_name, _)), Type::Named((to_name, span))) = (from, ty) {
+					if let (Some(from_bits), Some(to_bits)) = (from.int_bits(), ty.int_bits()) {
+						if to_bits < from_bits {
+							self.emitter.warning()
+								.with_label(format!("casting `{}` to `{}` may truncate the value", from_name, to_name))
+								.with_span(span.clone())
+								.emit();
+						}
+					}
+				}
+			}
+			Expr::VarDecl {name, r#type, ..} => {
+				scope.insert(&name.0, r#type);
+			}
+			Expr::MultiVarDecl {names, r#type, ..} => {
+				for (name, _) in names {
+					scope.insert(name, r#type);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn check_operator_spacing(&self, tokens: &[Token]) {
+		let src = self.map.source();
+		for (i, token) in tokens.iter().enumerate() {
+			let is_binary = match &token.kind {
+				TokenType::BinOp(BinOp::Not) => false,
+				TokenType::BinOp(_) => i > 0 && ends_operand(&tokens[i - 1].kind),
+				TokenType::Equals | TokenType::BinOpEquals(_) => true,
+				_ => false
+			};
+			if !is_binary {
+				continue;
+			}
+
+			let spaced_before = i > 0 && src[tokens[i - 1].span.end..token.span.start]
+				.contains(char::is_whitespace);
+			let spaced_after = match tokens.get(i + 1) {
+				Some(next) => src[token.span.end..next.span.start].contains(char::is_whitespace),
+				None => true
+			};
+
+			if !spaced_before || !spaced_after {
+				self.emitter.warning()
+					.with_label(format!("missing spaces around `{}`", &src[token.span.clone()]))
+					.with_span(token.span.clone())
+					.emit();
+			}
+		}
+	}
+}
+
+/// Whether a token can end an operand, making a following `-` binary rather than unary.
+fn ends_operand(kind: &TokenType) -> bool {
+	matches!(kind, TokenType::Identifier(_) | TokenType::Num(_) | TokenType::Float(_) | TokenType::CharLiteral(_)
+		| TokenType::StringLiteral(_) | TokenType::RParen | TokenType::RBracket)
+}
+
+/// Whether `expr` is a float literal, possibly signed like `-1.0`.
+fn is_float_literal(expr: &Expr) -> bool {
+	match expr {
+		Expr::FloatLiteral(_) => true,
+		Expr::Neg(inner, _) | Expr::Pos(inner, _) => is_float_literal(inner),
+		_ => false
+	}
+}
+
+/// A `const` with `value`, which is an integer if it is built from numbers and other
+/// integer constants with integer arithmetic, like `2 * N` or `-1`.
+fn const_binding(value: &Expr, scope: &HashMap<&str, Binding>) -> Binding {
+	Binding::Const(is_int_const(value, scope))
+}
+
+fn is_int_const(value: &Expr, scope: &HashMap<&str, Binding>) -> bool {
+	match value {
+		Expr::Num(_) => true,
+		Expr::Var((name, _)) => matches!(scope.get(name.as_str()), Some(Binding::Const(true))),
+		Expr::Neg(expr, _) | Expr::Pos(expr, _) => is_int_const(expr, scope),
+		Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) | Expr::Mul(lhs, rhs) | Expr::Div(lhs, rhs)
+		| Expr::Mod(lhs, rhs) | Expr::BitAnd(lhs, rhs) | Expr::BitOr(lhs, rhs) | Expr::Shl(lhs, rhs)
+		| Expr::Shr(lhs, rhs) => is_int_const(lhs, scope) && is_int_const(rhs, scope),
+		_ => false
+	}
+}
+
+/// The declared type of `expr` if it is known to be an integer.
+fn int_type<'a>(expr: &'a Expr, scope: &HashMap<&'a str, &'a Type>) -> Option<&'a Type> {
+	let r#type = match expr {
+		Expr::Var((name, _)) => *scope.get(name.as_str())?,
+		Expr::Cast {ty, ..} => ty,
+		_ => return None
+	};
+	r#type.int_bits().map(|_| r#type)
+}
@@ -1,20 +1,83 @@
-use std::fs::read_to_string;
-use crate::diagnostics::DiagnosticEmitter;
+use std::io::Write;
+use std::{env, io, process};
+use crate::diagnostics::{DiagnosticEmitter, EmitFormat};
 use crate::lexer::{Lexer, SourceMap, Token};
+use crate::lint::Linter;
 use crate::parser::Parser;
 
 mod lexer;
 mod parser;
 mod ast;
 mod diagnostics;
+mod lint;
+mod json;
+mod pretty;
+#[cfg(test)]
 mod tests;
 
-fn main() {
-	let src = read_to_string("../../tests/test2.lang").unwrap();
-	let map = SourceMap::new("tests/test2.lang", &src);
-	let emitter = diagnostics::with_stderr(&map);
-	let lexer = Lexer::new(&src, &emitter);
+/// Parses the file named in `args` (a test file by default) and writes the AST to `out`,
+/// as pretty JSON with `--ast-json` or as source-like text with `--ast-pretty`. With
+/// `--tokens` the file is only lexed and the kind and span of each token written instead.
+/// Diagnostics go to stderr, one line each with
+/// `--error-format=short` or as JSON objects with `--error-format=json`. Array lengths and
+/// assignments of a type name like `x = int;` are checked with the `Linter`.
+/// Returns the exit code, 1 if any errors were reported.
+fn run(args: &[String], out: &mut impl Write) -> i32 {
+	let mut ast_json = false;
+	let mut ast_pretty = false;
+	let mut tokens = false;
+	let mut format = EmitFormat::Human;
+	let mut path = "../../tests/test2.lang";
+	for arg in args {
+		match arg.as_str() {
+			"--ast-json" => ast_json = true,
+			"--ast-pretty" => ast_pretty = true,
+			"--tokens" => tokens = true,
+			"--error-format=human" => format = EmitFormat::Human,
+			"--error-format=short" => format = EmitFormat::Short,
+			"--error-format=json" => format = EmitFormat::Json,
+			flag if flag.starts_with("--") => {
+				eprintln!("unknown flag {}", flag);
+				return 2;
+			}
+			file => path = file
+		}
+	}
+
+	let map = match SourceMap::from_file(path) {
+		Ok(map) => map,
+		Err(e) => {
+			eprintln!("could not read {}: {}", path, e);
+			return 1;
+		}
+	};
+	let emitter = diagnostics::with_stderr(&map).with_format(format);
+	let mut lexer = Lexer::new(map.source(), &emitter);
+	if tokens {
+		for (kind, span) in lexer.lex_ranges() {
+			writeln!(out, "{:?} {:?}", kind, span).unwrap();
+		}
+		emitter.finish();
+		return if emitter.error_count() > 0 { 1 } else { 0 };
+	}
+
 	let mut parser = Parser::new(lexer, &emitter);
 	let result = parser.parse();
-	println!("{:?}", result);
-}
\ No newline at end of file
+	Linter::new(&map, &emitter).with_array_lengths(true).with_type_assignments(true).check_ast(&result);
+	if ast_json {
+		writeln!(out, "{}", json::ast_to_json(&result).pretty()).unwrap();
+	}
+	else if ast_pretty {
+		write!(out, "{}", pretty::ast_to_source(&result)).unwrap();
+	}
+	else {
+		writeln!(out, "{:?}", result).unwrap();
+	}
+	emitter.finish();
+	if emitter.error_count() > 0 { 1 } else { 0 }
+}
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	process::exit(run(&args, &mut io::stdout()));
+}
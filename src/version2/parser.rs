@@ -1,37 +1,200 @@
 use std::io::Write;
+use std::str::Chars;
 use crate::{DiagnosticEmitter, Lexer, Token};
-use crate::ast::{Expr, Spanned};
-use crate::diagnostics::Span;
-use crate::lexer::{BinOp, PeekCount, TokenType};
+use crate::ast::{spanned, ArrayLen, Attribute, Expr, Field, Float, Spanned, SpannedExt, Type, BUILTIN_TYPES, INTRINSICS};
+use crate::diagnostics::{self, Diagnostic, Span};
+use crate::lexer::{BinOp, Input, PeekCount, SourceMap, TokenType};
 
-pub struct Parser<'source, W: Write> {
-	lexer: Lexer<'source, W>,
-	emitter: &'source DiagnosticEmitter<'source, W>,
-	has_error: bool
+/// Everything parsing a source produced: the AST, the diagnostics and whether any
+/// of them was an error.
+#[derive(Debug)]
+pub struct ParseResult {
+	pub ast: Vec<Expr>,
+	pub diagnostics: Vec<Diagnostic>,
+	pub had_error: bool
 }
 
-#[derive(Debug)]
-enum Recovery {
-	Continue(Span),
-	Break(Span),
-	TopLevel(Span),
-	Eof
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Associativity {
+	Left,
+	Right
+}
+
+/// The precedence and associativity of a binary operator, where a higher precedence
+/// binds tighter. `None` for `!`, which is not a binary operator.
+pub fn binop_info(op: &BinOp) -> Option<(u32, Associativity)> {
+	match op {
+		BinOp::Add | BinOp::Minus => Some((12, Associativity::Left)),
+		BinOp::Multiply | BinOp::Divide | BinOp::Modulo => Some((20, Associativity::Left)),
+		BinOp::ShiftLeft | BinOp::ShiftRight => Some((10, Associativity::Left)),
+		BinOp::BitAnd => Some((9, Associativity::Left)),
+		BinOp::BitOr => Some((8, Associativity::Left)),
+		BinOp::Equal | BinOp::NotEqual | BinOp::Less | BinOp::Greater | BinOp::LessEqual
+		| BinOp::GreaterEqual => Some((7, Associativity::Left)),
+		BinOp::And => Some((6, Associativity::Left)),
+		BinOp::Or => Some((5, Associativity::Left)),
+		BinOp::Not => None
+	}
+}
+
+/// Whether `expr` can be assigned to. `Expr::Error` is accepted to avoid a second
+/// diagnostic for the same mistake.
+fn is_assignable(expr: &Expr) -> bool {
+	matches!(expr, Expr::Var(_) | Expr::FieldAccess {..} | Expr::Index {..} | Expr::Error)
+}
+
+/// The constructor of the expression node for the binary operator `op`.
+fn binary_expr(op: &BinOp) -> fn(Box<Expr>, Box<Expr>) -> Expr {
+	match op {
+		BinOp::Add => Expr::Add,
+		BinOp::Minus => Expr::Sub,
+		BinOp::Multiply => Expr::Mul,
+		BinOp::Divide => Expr::Div,
+		BinOp::Modulo => Expr::Mod,
+		BinOp::And => Expr::And,
+		BinOp::Or => Expr::Or,
+		BinOp::BitAnd => Expr::BitAnd,
+		BinOp::BitOr => Expr::BitOr,
+		BinOp::ShiftLeft => Expr::Shl,
+		BinOp::ShiftRight => Expr::Shr,
+		BinOp::Equal => Expr::Eq,
+		BinOp::NotEqual => Expr::Ne,
+		BinOp::Less => Expr::Lt,
+		BinOp::Greater => Expr::Gt,
+		BinOp::LessEqual => Expr::Le,
+		BinOp::GreaterEqual => Expr::Ge,
+		BinOp::Not => unreachable!()
+	}
+}
+
+/// A type argument or index between the brackets of `name[...]`, which is only known to
+/// be one or the other once the closing bracket has been seen.
+enum BracketItem {
+	Expr(Expr),
+	Type(Type)
+}
+
+/// What the right-hand side of `name = ...` declares.
+#[derive(PartialEq)]
+enum AssignKind {
+	Struct,
+	Function,
+	Value
+}
+
+/// Parses `src` as the module `file` without printing anything.
+pub fn parse(file: &str, src: &str) -> ParseResult {
+	let map = SourceMap::new(file, src);
+	let emitter = diagnostics::collecting(&map);
+	let lexer = Lexer::new(src, &emitter);
+	let mut parser = Parser::new(lexer, &emitter);
+	let ast = parser.parse();
+	ParseResult {ast, diagnostics: emitter.take_diagnostics(), had_error: parser.has_error()}
+}
+
+/// Parses `src` for editor tooling like a language server. Unlike `parse` it reports
+/// every unexpected character instead of stopping after the lexer's error limit, since
+/// an editor marks each one where it is. Declarations that failed to parse are left as
+/// `Expr::Error` between the ones that didn't.
+pub fn parse_for_ide(file: &str, src: &str) -> ParseResult {
+	let map = SourceMap::new(file, src);
+	let emitter = diagnostics::collecting(&map);
+	let lexer = Lexer::new(src, &emitter).with_error_limit(usize::MAX);
+	let mut parser = Parser::new(lexer, &emitter);
+	let ast = parser.parse();
+	ParseResult {ast, diagnostics: emitter.take_diagnostics(), had_error: parser.has_error()}
+}
+
+pub struct Parser<'source, W: Write, I: Input = Chars<'source>> {
+	lexer: Lexer<'source, W, I>,
+	emitter: &'source DiagnosticEmitter<'source, W>,
+	has_error: bool,
+	relaxed: bool,
+	newline_seen: bool,
+	block_depth: usize,
+	doc: Option<String>,
+	script: bool,
+	/// The labels of the blocks being parsed, innermost last.
+	labels: Vec<String>,
+	/// Set while parsing an `if` condition, where `name {` starts the body rather than
+	/// a construct.
+	in_condition: bool,
+	/// The end offset of the last consumed token, where the node being parsed ends.
+	last_end: usize,
+	/// Set once a missing token at the end of input has been reported. Every enclosing
+	/// node is unfinished then too, so only the first is reported.
+	eof_reported: bool
 }
 
-impl<'source, W: Write> Parser<'source, W> {
-	pub fn new(lexer: Lexer<'source, W>,
+impl<'source, W: Write, I: Input> Parser<'source, W, I> {
+	pub fn new(lexer: Lexer<'source, W, I>,
 	           emitter: &'source DiagnosticEmitter<'source, W>) -> Self {
-		Self {lexer, emitter, has_error: false}
+		Self {lexer, emitter, has_error: false, relaxed: false, newline_seen: false, block_depth: 0,
+			doc: None, script: false, labels: Vec::new(), in_condition: false, last_end: 0,
+			eof_reported: false}
+	}
+
+	/// In script mode top-level statements that aren't struct or function declarations
+	/// (assignments, variable declarations, calls and other expressions) are collected,
+	/// in order, into a trailing `Expr::Script`. In module mode (the default) only
+	/// declarations and global assignments are allowed at top level.
+	pub fn with_script(mut self, script: bool) -> Self {
+		self.script = script;
+		self
+	}
+
+	/// In relaxed mode a newline can terminate a simple statement in place of `;`.
+	pub fn with_relaxed(mut self, relaxed: bool) -> Self {
+		self.relaxed = relaxed;
+		self.lexer = self.lexer.with_newlines(relaxed);
+		self
+	}
+
+	/// Skips newlines and doc comments before the next token. Doc comments are
+	/// collected into `doc` until that token is consumed.
+	fn skip_trivia(&mut self) {
+		while let Some(token) = self.lexer.peek(PeekCount::One) {
+			match token.kind {
+				TokenType::Newline => self.newline_seen = true,
+				TokenType::DocComment(text) => match &mut self.doc {
+					Some(doc) => {
+						doc.push('\n');
+						doc.push_str(&text);
+					}
+					None => self.doc = Some(text)
+				},
+				_ => break
+			}
+			self.lexer.next();
+		}
 	}
 
 	fn next(&mut self) -> Option<Token> {
+		self.skip_trivia();
 		let token = self.lexer.next();
+		if let Some(token) = &token {
+			self.last_end = token.span.end;
+		}
+		self.newline_seen = false;
+		self.doc = None;
 		self.has_error |= self.lexer.has_error();
 		token
 	}
 
+	/// The token `count` tokens ahead. Newlines and doc comments between them are
+	/// skipped like before the first one.
 	fn peek(&mut self, count: PeekCount) -> Option<Token> {
-		let token = self.lexer.peek(count);
+		self.skip_trivia();
+		let mut remaining = count as usize;
+		let mut i = 0;
+		let token = loop {
+			match self.lexer.peek_nth(i) {
+				Some(Token {kind: TokenType::Newline | TokenType::DocComment(_), ..}) => {}
+				Some(_) if remaining > 0 => remaining -= 1,
+				token => break token
+			}
+			i += 1;
+		};
 		self.has_error |= self.lexer.has_error();
 		token
 	}
@@ -40,14 +203,14 @@ impl<'source, W: Write> Parser<'source, W> {
 		self.peek(PeekCount::One)
 	}
 
-	fn get_prec(token: &Token) -> Option<u32> {
+	/// The span from `start` to the end of the last consumed token.
+	fn span_from(&self, start: usize) -> Span {
+		start..self.last_end
+	}
+
+	fn get_prec(token: &Token) -> Option<(u32, Associativity)> {
 		match &token.kind {
-			TokenType::BinOp(op) => match op {
-				BinOp::Add | BinOp::Minus => Some(10),
-				BinOp::Multiply | BinOp::Divide | BinOp::Modulo => Some(20),
-				BinOp::And | BinOp::Or => Some(5),
-				BinOp::Not => None
-			}
+			TokenType::BinOp(op) => binop_info(op),
 			_ => None
 		}
 	}
@@ -56,7 +219,7 @@ impl<'source, W: Write> Parser<'source, W> {
 		let mut next = self.peek_one();
 		while let Some(token) = next {
 			let op_prec;
-			if let Some(prec) = Self::get_prec(&token) {
+			if let Some((prec, _)) = Self::get_prec(&token) {
 				if prec < min_precedence {
 					break;
 				}
@@ -70,10 +233,22 @@ impl<'source, W: Write> Parser<'source, W> {
 
 			let mut rhs = match self.parse_primary() {
 				Some(primary) => primary,
+				// Another operator like in `a * / b`, which then continues the expression
+				None if matches!(self.peek_one(), Some(Token {kind: TokenType::BinOp(_), ..})) => {
+					let next_op = self.peek_one().unwrap();
+					self.emitter.error()
+						.with_label(format!("missing operand between `{}` and `{}`",
+							self.emitter.source_slice(op.span.clone()), self.emitter.source_slice(next_op.span.clone())))
+						.with_span(op.span.end..next_op.span.start)
+						.emit();
+					self.has_error = true;
+					Expr::Error
+				}
 				None => {
 					let op_len = op.span.end - op.span.start;
 					self.emitter.error()
-						.with_label(format!("expected a primary expression after {}", op.kind))
+						.with_label(format!("expected a primary expression after `{}`",
+							self.emitter.source_slice(op.span.clone())))
 						.with_span(op.span.start+op_len..op.span.end+op_len)
 						.emit();
 					self.has_error = true;
@@ -84,8 +259,8 @@ impl<'source, W: Write> Parser<'source, W> {
 			next = self.peek_one();
 
 			while let Some(token) = &next {
-				if let Some(prec) = Self::get_prec(&token) {
-					if prec <= op_prec {
+				if let Some((prec, associativity)) = Self::get_prec(token) {
+					if prec < op_prec || (prec == op_prec && associativity == Associativity::Left) {
 						break;
 					}
 				}
@@ -95,7 +270,7 @@ impl<'source, W: Write> Parser<'source, W> {
 
 				let is_greater = match &next {
 					Some(token) => {
-						if let Some(prec) = Self::get_prec(token) {
+						if let Some((prec, _)) = Self::get_prec(token) {
 							if prec > op_prec {
 								1
 							}
@@ -114,144 +289,837 @@ impl<'source, W: Write> Parser<'source, W> {
 			}
 
 			let op = match op.kind {
-				TokenType::BinOp(op) => {
-					match op {
-						BinOp::Add => Expr::Add,
-						BinOp::Minus => Expr::Sub,
-						BinOp::Multiply => Expr::Mul,
-						BinOp::Divide => Expr::Div,
-						BinOp::Modulo => Expr::Mod,
-						BinOp::And => Expr::And,
-						BinOp::Or => Expr::Or,
-						_ => unreachable!()
-					}
-				}
+				TokenType::BinOp(op) => binary_expr(&op),
 				_ => unreachable!()
 			};
 
 			lhs = op(Box::new(lhs), Box::new(rhs));
 		}
 
-		return lhs;
+		lhs
+	}
+
+	/// Parses `target op= value` after `target`, which becomes `target = target op value`.
+	fn parse_compound_assign(&mut self, target: Expr) -> Expr {
+		let token = self.next().unwrap();
+		let op = match token.kind {
+			TokenType::BinOpEquals(op) => op,
+			_ => unreachable!()
+		};
+		let start = target.span().map_or(token.span.start, |span| span.start);
+		if !is_assignable(&target) {
+			self.report_invalid_target(token.span);
+		}
+		let value = self.parse_atom();
+		let value = binary_expr(&op)(Box::new(target.clone()), Box::new(value));
+		Expr::Assign {target: Box::new(target), value: Box::new(value), span: self.span_from(start)}
 	}
 
 	fn parse_primary(&mut self) -> Option<Expr> {
-		let mut minus_stack = Vec::new();
+		let primary = self.parse_unary()?;
+		Some(self.parse_cast(primary))
+	}
+
+	fn parse_cast(&mut self, mut expr: Expr) -> Expr {
 		while let Some(token) = self.peek_one() {
-			if let TokenType::BinOp(op) = token.kind {
-				if op == BinOp::Minus {
-					minus_stack.push(BinOp::Minus);
-					self.next();
+			if token.kind != TokenType::As {
+				break;
+			}
+			self.next();
+
+			let ty = match self.parse_type() {
+				Some(ty) => ty,
+				None => return Expr::Error
+			};
+
+			expr = Expr::Cast {expr: Box::new(expr), ty};
+		}
+
+		expr
+	}
+
+	/// Parses the `{ .. }` of a construct. Fields are either all named (`.x = 1`) or all
+	/// positional (`1`), decided by the first one.
+	fn parse_construct(&mut self, name: Spanned<String>) -> Expr {
+		let open = self.next().unwrap();
+
+		let positional = !matches!(self.peek_one(),
+			Some(Token {kind: TokenType::Dot | TokenType::RBrace, ..}));
+		let mut fields = Vec::new();
+		let mut values = Vec::new();
+		let mut closed = false;
+		// Set once a malformed field has been reported, so the missing `}` isn't reported again
+		let mut failed = false;
+		while let Some(token) = self.peek_one() {
+			if token.kind == TokenType::RBrace {
+				self.next();
+				closed = true;
+				break;
+			}
+
+			if token.kind == TokenType::Dot {
+				self.next();
+				let field = match self.parse_ident("a field name") {
+					Some(ident) => ident,
+					None => {
+						failed = true;
+						break;
+					}
+				};
+
+				if self.expect(&[TokenType::Equals]).is_none() {
+					failed = true;
+					break;
 				}
-				else {
+
+				let value = self.parse_atom();
+				if positional {
+					self.report_mixed_construct(field.span());
+				}
+				fields.push((field, Box::new(value)));
+			}
+			else {
+				let value = self.parse_atom();
+				if !positional {
+					self.report_mixed_construct(token.span);
+				}
+				values.push(value);
+			}
+
+			match self.expect_closing(&[TokenType::Comma, TokenType::RBrace], &open) {
+				Some(Token {kind: TokenType::RBrace, ..}) => {
+					closed = true;
+					break;
+				}
+				Some(_) => {}
+				None => {
+					failed = true;
 					break;
 				}
 			}
+		}
+
+		if !closed && !failed {
+			self.expect_closing(&[TokenType::RBrace], &open);
+		}
+
+		let span = self.span_from(name.1.start);
+		if positional {
+			Expr::PositionalConstruct {name, values, span}
+		}
+		else {
+			Expr::Construct {name, fields, span}
+		}
+	}
+
+	fn report_mixed_construct(&mut self, span: Span) {
+		self.emitter.error()
+			.with_label("cannot mix named and positional fields in a construct")
+			.with_span(span)
+			.emit();
+		self.has_error = true;
+	}
+
+	/// Parses an operand with any prefix `-`, `+` and `!` operators, like `!-x`. A sign
+	/// is always an operator rather than part of a number literal, so `-5` is `Neg(5)`
+	/// and `+5` is `Pos(5)`.
+	fn parse_unary(&mut self) -> Option<Expr> {
+		let mut prefix_ops = Vec::new();
+		while let Some(token) = self.peek_one() {
+			if let TokenType::BinOp(BinOp::Minus | BinOp::Add | BinOp::Not) = token.kind {
+				prefix_ops.push(token);
+				self.next();
+			}
 			else {
-				break
+				break;
 			}
 		}
 
+		let operand = match self.parse_unary_operand() {
+			Some(operand) => operand,
+			None => {
+				let op = prefix_ops.last()?;
+				let error = self.emitter.error()
+					.with_label(format!("expected a primary expression after operator `{}`",
+						self.emitter.source_slice(op.span.clone())));
+				match self.peek_one() {
+					Some(token) => error.with_span(token.span),
+					None => error.with_eoi_span()
+				}.emit();
+				self.has_error = true;
+				Expr::Error
+			}
+		};
+
+		// The operator closest to the operand applies first.
+		let end = self.last_end;
+		Some(prefix_ops.into_iter().rev().fold(operand, |expr, op| match op.kind {
+			TokenType::BinOp(BinOp::Not) => Expr::Not(Box::new(expr), op.span.start..end),
+			TokenType::BinOp(BinOp::Add) => Expr::Pos(Box::new(expr), op.span.start..end),
+			_ => Expr::Neg(Box::new(expr), op.span.start..end)
+		}))
+	}
+
+	fn parse_unary_operand(&mut self) -> Option<Expr> {
 		let primary_token = self.peek_one()?;
 
 		match primary_token.kind {
 			TokenType::Num(num) => {
 				self.next();
-				Some(
-					minus_stack.into_iter()
-						.fold(Expr::Num((num, primary_token.span)),
-						      |e, _| Expr::Neg(Box::new(e)))
-				)
+				Some(Expr::Num((num, primary_token.span)))
+			},
+			TokenType::Float(float) => {
+				self.next();
+				Some(Expr::FloatLiteral((Float(float), primary_token.span)))
 			},
 			TokenType::Identifier(ident) => {
 				self.next();
-				if let Some(next) = self.peek_one() {
-					if next.kind == TokenType::LBrace {
+				let name = spanned(ident, primary_token.span);
+				let expr = match self.peek_one().map(|token| token.kind) {
+					Some(TokenType::LBrace) if !self.in_condition => return Some(self.parse_construct(name)),
+					Some(TokenType::LParen) if INTRINSICS.contains(&name.0.as_str()) => self.parse_intrinsic(name),
+					Some(TokenType::LBracket) => self.nested(|parser| parser.parse_brackets(name)),
+					_ => Expr::Var(name)
+				};
+				Some(self.parse_postfix(expr))
+			},
+			TokenType::CharLiteral(literal) => {
+				self.next();
+				Some(Expr::CharLiteral((literal, primary_token.span)))
+			},
+			TokenType::StringLiteral(literal) => {
+				self.next();
+				Some(Expr::StringLiteral((literal, primary_token.span)))
+			}
+			TokenType::LParen => {
+				self.next();
+				let expr = self.nested(|parser| parser.parse_paren(primary_token));
+				Some(self.parse_postfix(expr))
+			}
+			TokenType::LBracket => {
+				self.next();
+				let expr = self.nested(|parser| parser.parse_array(primary_token));
+				Some(self.parse_postfix(expr))
+			}
+			TokenType::Label(label) => {
+				self.next();
+				Some(self.parse_labeled_block(spanned(label, primary_token.span)))
+			}
+			TokenType::If => {
+				self.next();
+				Some(self.parse_if(primary_token))
+			}
+			TokenType::For => {
+				self.next();
+				Some(self.parse_for(primary_token))
+			}
+			_ => None
+		}
+	}
+
+	/// Parses the `: { .. }` after the label of a block.
+	fn parse_labeled_block(&mut self, label: Spanned<String>) -> Expr {
+		if self.expect(&[TokenType::Colon]).is_none() {
+			return Expr::Error;
+		}
+		let open = match self.expect(&[TokenType::LBrace]) {
+			Some(open) => open,
+			None => return Expr::Error
+		};
+
+		self.labels.push(label.0.clone());
+		let body = self.parse_block_body(&open);
+		self.labels.pop();
+
+		let span = self.span_from(label.1.start);
+		Expr::Block {label, body, span}
+	}
+
+	/// Parses the statements of a block up to and including its closing `}`, after the
+	/// `{` in `open`.
+	fn parse_block_body(&mut self, open: &Token) -> Vec<Expr> {
+		let mut body = Vec::new();
+		self.block_depth += 1;
+		while let Some(token) = self.peek_one() {
+			if token.kind == TokenType::RBrace {
+				break;
+			}
+			body.push(self.parse_expression());
+		}
+		self.block_depth -= 1;
+		self.expect_closing(&[TokenType::RBrace], open);
+		body
+	}
+
+	/// Runs `parse` on the inside of parentheses or brackets, where a `{` can start a
+	/// construct again even within an `if` or `for` condition.
+	fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+		let in_condition = std::mem::replace(&mut self.in_condition, false);
+		let result = parse(self);
+		self.in_condition = in_condition;
+		result
+	}
+
+	/// Parses an `if cond { .. }` after its `if`, with an optional `else { .. }` or
+	/// `else if ..`.
+	fn parse_if(&mut self, keyword: Token) -> Expr {
+		let in_condition = std::mem::replace(&mut self.in_condition, true);
+		let cond = self.parse_atom();
+		self.in_condition = in_condition;
+
+		let then_body = self.parse_if_body("the `if` condition", &keyword);
+
+		let else_body = match self.peek_one() {
+			Some(else_keyword) if else_keyword.kind == TokenType::Else => {
+				self.next();
+				match self.peek_one() {
+					Some(token) if token.kind == TokenType::If => {
 						self.next();
+						Some(vec![self.parse_if(token)])
+					}
+					_ => Some(self.parse_if_body("`else`", &else_keyword))
+				}
+			}
+			_ => None
+		};
 
-						let mut fields = Vec::new();
-						while let Some(token) = self.peek_one() {
-							if token.kind == TokenType::RBrace {
-								break;
-							}
+		Expr::If {cond: Box::new(cond), then_body, else_body, span: self.span_from(keyword.span.start)}
+	}
 
-							if self.expect(&[TokenType::Dot]).is_none() {
-								break;
-							}
+	/// Parses `init; cond; step { .. }` after a `for`. The init clause is a statement
+	/// and ends with its own `;`.
+	fn parse_for(&mut self, keyword: Token) -> Expr {
+		let init = match self.peek_one() {
+			Some(Token {kind: TokenType::Semicolon, ..}) => {
+				self.next();
+				None
+			}
+			_ => Some(Box::new(self.parse_expression()))
+		};
 
-							let name = match self.parse_ident("a field name") {
-								Some(ident) => ident,
-								None => break
-							};
+		let in_condition = std::mem::replace(&mut self.in_condition, true);
+		let cond = match self.peek_one() {
+			Some(Token {kind: TokenType::Semicolon, ..}) => None,
+			_ => Some(Box::new(self.parse_atom()))
+		};
+		self.expect(&[TokenType::Semicolon]);
+		let step = match self.peek_one() {
+			Some(Token {kind: TokenType::LBrace, ..}) => None,
+			_ => Some(Box::new(self.parse_for_step()))
+		};
+		self.in_condition = in_condition;
 
-							if self.expect(&[TokenType::Equals]).is_none() {
-								break;
-							}
+		let body = self.parse_if_body("the `for` clauses", &keyword);
+		Expr::For {init, cond, step, body, span: self.span_from(keyword.span.start)}
+	}
+
+	/// Parses the step clause of a `for`, an expression or an assignment without a `;`.
+	fn parse_for_step(&mut self) -> Expr {
+		let target = self.parse_atom();
+		match self.peek_one() {
+			Some(Token {kind: TokenType::Equals, ..}) => self.parse_assign(target, false),
+			Some(Token {kind: TokenType::BinOpEquals(_), ..}) => self.parse_compound_assign(target),
+			_ => target
+		}
+	}
+
+	/// Parses the `{ .. }` of an `if`, `else` or `for` that follows `what`. Without the `{` the
+	/// rest of the statement is skipped and the body is empty.
+	fn parse_if_body(&mut self, what: &str, keyword: &Token) -> Vec<Expr> {
+		match self.peek_one() {
+			Some(token) if token.kind == TokenType::LBrace => {
+				self.next();
+				self.parse_block_body(&token)
+			}
+			token => {
+				let error = self.emitter.error()
+					.with_label(format!("expected '{{' after {}", what))
+					.with_secondary(keyword.span.clone(), format!("`{}` here", keyword.kind));
+				match token {
+					Some(token) => error.with_span(token.span),
+					None => error.with_eoi_span()
+				}.emit();
+				self.has_error = true;
+				self.recover_to_statement();
+				Vec::new()
+			}
+		}
+	}
+
+	/// Parses the `cond [, message]` of an assert after its `assert`.
+	fn parse_assert(&mut self, keyword: Token) -> Expr {
+		let cond = self.parse_atom();
+		let message = match self.peek_one() {
+			Some(Token {kind: TokenType::Comma, ..}) => {
+				self.next();
+				Some(Box::new(self.parse_atom()))
+			}
+			_ => None
+		};
+		Expr::Assert {cond: Box::new(cond), message, span: self.span_from(keyword.span.start)}
+	}
+
+	/// Parses `"path" [as alias];` after an `import`.
+	fn parse_import(&mut self, keyword: Token) -> Expr {
+		let path = match self.peek_one() {
+			Some(Token {kind: TokenType::StringLiteral(path), span}) => {
+				self.next();
+				spanned(path, span)
+			}
+			token => {
+				let error = self.emitter.error()
+					.with_label("expected a path string after `import`");
+				match token {
+					Some(token) => error.with_span(token.span),
+					None => error.with_eoi_span()
+				}.emit();
+				self.has_error = true;
+				self.recover_to_statement();
+				return Expr::Error;
+			}
+		};
+
+		let mut alias = None;
+		if matches!(self.peek_one(), Some(Token {kind: TokenType::As, ..})) {
+			self.next();
+			alias = self.parse_ident("an alias name after `as`");
+		}
+		let span = self.span_from(keyword.span.start);
+		self.expect_terminator();
+		Expr::Import {path, alias, span}
+	}
+
+	/// Parses `NAME = value;` after a `const`.
+	fn parse_const(&mut self, keyword: Token) -> Expr {
+		let name = match self.parse_ident("a constant name after `const`") {
+			Some(name) => name,
+			None => {
+				self.recover_to_statement();
+				return Expr::Error;
+			}
+		};
+		if self.expect(&[TokenType::Equals]).is_none() {
+			self.recover_to_statement();
+			return Expr::Error;
+		}
+		let value = self.parse_atom();
+		let span = self.span_from(keyword.span.start);
+		self.expect_terminator();
+		Expr::Const {name, value: Box::new(value), span}
+	}
+
+	/// Parses a `break 'label [value]` statement after its `break`.
+	fn parse_break(&mut self, keyword: Token) -> Expr {
+		let label = match self.peek_one() {
+			Some(Token {kind: TokenType::Label(label), span}) => {
+				self.next();
+				spanned(label, span)
+			}
+			_ => {
+				self.emitter.error()
+					.with_label("expected a label after `break`, like `break 'block`")
+					.with_span(keyword.span)
+					.emit();
+				self.has_error = true;
+				self.recover_to_statement();
+				return Expr::Error;
+			}
+		};
+		if !self.labels.contains(&label.0) {
+			self.emitter.error()
+				.with_label(format!("undefined label `'{}`", label.0))
+				.with_span(label.1.clone())
+				.emit();
+			self.has_error = true;
+		}
+
+		let value = match self.peek_one() {
+			Some(Token {kind: TokenType::Semicolon | TokenType::RBrace, ..}) | None => None,
+			_ if self.at_newline() => None,
+			_ => Some(Box::new(self.parse_atom()))
+		};
+		let span = self.span_from(keyword.span.start);
+		self.expect_terminator();
+		Expr::Break {label, value, span}
+	}
+
+	/// Parses the elements of an array literal after its `[`, allowing a trailing comma.
+	fn parse_array(&mut self, open: Token) -> Expr {
+		let mut values = Vec::new();
+		loop {
+			match self.peek_one().map(|token| token.kind) {
+				Some(TokenType::RBracket) => {
+					self.next();
+					break;
+				}
+				None => {
+					self.emitter.error()
+						.with_label("unclosed `[` in array literal")
+						.with_eoi_span()
+						.with_secondary(open.span.clone(), "opened here")
+						.emit();
+					self.has_error = true;
+					break;
+				}
+				_ => {}
+			}
+
+			values.push(self.parse_atom());
+			match self.peek_one().map(|token| token.kind) {
+				Some(TokenType::Comma) => {
+					self.next();
+				}
+				Some(TokenType::RBracket) => {
+					self.next();
+					break;
+				}
+				// Reported as unclosed at the top of the loop.
+				None => {}
+				_ => {
+					self.expect_closing(&[TokenType::Comma, TokenType::RBracket], &open);
+					// Skip the rest of the literal, including any nested brackets.
+					let mut depth = 0;
+					while let Some(token) = self.peek_one() {
+						if token.kind == TokenType::Semicolon {
+							break;
+						}
+						self.next();
+						match token.kind {
+							TokenType::LBracket => depth += 1,
+							TokenType::RBracket if depth == 0 => break,
+							TokenType::RBracket => depth -= 1,
+							_ => {}
+						}
+					}
+					break;
+				}
+			}
+		}
+		Expr::Array(values, self.span_from(open.span.start))
+	}
+
+	/// Parses a parenthesized expression after its `(`. An assignment is allowed inside and
+	/// evaluates to the assigned value.
+	fn parse_paren(&mut self, open: Token) -> Expr {
+		let mut expr = self.parse_atom();
+		if let Some(equals) = self.peek_one().filter(|token| token.kind == TokenType::Equals) {
+			self.next();
+			let start = expr.span().map_or(equals.span.start, |span| span.start);
+			if !is_assignable(&expr) {
+				self.report_invalid_target(equals.span);
+			}
+			let value = self.parse_atom();
+			let span = self.span_from(start);
+			expr = Expr::Assign {target: Box::new(expr), value: Box::new(value), span};
+		}
+		// A comma makes a tuple, which may end in a trailing comma like `(1,)`.
+		let is_tuple = matches!(self.peek_one(), Some(Token {kind: TokenType::Comma, ..}));
+		if is_tuple {
+			let mut values = vec![expr];
+			while matches!(self.peek_one(), Some(Token {kind: TokenType::Comma, ..})) {
+				self.next();
+				if matches!(self.peek_one(), Some(Token {kind: TokenType::RParen, ..})) {
+					break;
+				}
+				values.push(self.parse_atom());
+			}
+			expr = Expr::Tuple(values, open.span.clone());
+		}
+		let next = self.peek_one();
+		if let Some(next) = next {
+			if next.kind != TokenType::RParen {
+				self.emitter.error()
+					.with_label(format!("expected ')' but got {}", next.kind))
+					.with_span(next.span)
+					.with_secondary(open.span.clone(), "opened here")
+					.emit();
+				self.has_error = true;
+			}
+			else {
+				self.next();
+			}
+		}
+		else {
+			self.emitter.error()
+				.with_label("expected ')'")
+				.with_eoi_span()
+				.with_secondary(open.span.clone(), "opened here")
+				.emit();
+			self.has_error = true;
+		}
+		if let (true, Expr::Tuple(_, span)) = (is_tuple, &mut expr) {
+			*span = self.span_from(open.span.start);
+		}
+		expr
+	}
+
+	fn report_invalid_target(&mut self, span: Span) {
+		self.emitter.error()
+			.with_label("invalid assignment target, expected a variable or field")
+			.with_span(span)
+			.emit();
+		self.has_error = true;
+	}
+
+	/// Parses the field accesses, indexes and calls following `expr`, like the `.b[i].c(x)`
+	/// of `a.b[i].c(x)`.
+	fn parse_postfix(&mut self, mut expr: Expr) -> Expr {
+		while let Some(token) = self.peek_one() {
+			match token.kind {
+				TokenType::Dot => {
+					self.next();
+					let field = match self.parse_ident("a field name") {
+						Some(ident) => ident,
+						None => return Expr::Error
+					};
+					expr = Expr::FieldAccess {base: Box::new(expr), field};
+				}
+				TokenType::LBracket => {
+					self.next();
+					let index = self.nested(Self::parse_atom);
+					if self.expect(&[TokenType::RBracket]).is_none() {
+						return Expr::Error;
+					}
+					let span = self.span_from(expr.span().map_or(token.span.start, |span| span.start));
+					expr = Expr::Index {base: Box::new(expr), index: Box::new(index), span};
+				}
+				TokenType::LParen => expr = self.nested(|parser| parser.parse_call(expr, Vec::new())),
+				_ => break
+			}
+		}
+		expr
+	}
+
+	/// Parses the `[...]` after `name`: the type arguments of a call when a `(` follows,
+	/// as in `foo[int](1)`, and an index like `a[i]` otherwise. A single name could be
+	/// either, so `a[i](x)` only passes a type argument if `i` is a builtin type.
+	fn parse_brackets(&mut self, name: Spanned<String>) -> Expr {
+		let open = self.next().unwrap();
+		let mut items = Vec::new();
+		let close = loop {
+			let token = match self.peek_one() {
+				Some(token) => token,
+				None => {
+					self.expect(&[TokenType::RBracket]);
+					return Expr::Error;
+				}
+			};
+			if token.kind == TokenType::RBracket {
+				self.next();
+				break token;
+			}
+
+			let is_optional_type = matches!(token.kind, TokenType::Identifier(_))
+				&& matches!(self.peek(PeekCount::Two), Some(Token {kind: TokenType::Question, ..}));
+			// `[int; 3]` or `[[u8; 2]; N]`, which an index can't start with
+			let is_array_type = token.kind == TokenType::LBracket && match self.peek(PeekCount::Two).map(|token| token.kind) {
+				Some(TokenType::Identifier(_)) => matches!(self.peek(PeekCount::Three), Some(Token {kind: TokenType::Semicolon, ..})),
+				Some(TokenType::LBracket) => true,
+				_ => false
+			};
+			let item = if is_optional_type || is_array_type {
+				match self.parse_type() {
+					Some(ty) => BracketItem::Type(ty),
+					None => return Expr::Error
+				}
+			}
+			else {
+				BracketItem::Expr(self.parse_atom())
+			};
+			items.push((token.span, item));
+
+			match self.expect(&[TokenType::Comma, TokenType::RBracket]) {
+				Some(token) if token.kind == TokenType::RBracket => break token,
+				Some(_) => {}
+				None => return Expr::Error
+			}
+		};
+
+		// A single index that isn't known to be a type, like `a[0](x)` or `a[i](x)`, calls
+		// the indexed value
+		let is_value_index = match &items[..] {
+			[(_, BracketItem::Expr(Expr::Var((name, _))))] => !BUILTIN_TYPES.contains(&name.as_str()),
+			[(_, BracketItem::Expr(_))] => true,
+			_ => false
+		};
+		if !is_value_index && matches!(self.peek_one(), Some(Token {kind: TokenType::LParen, ..})) {
+			if items.is_empty() {
+				self.report_brackets(open.span.start..close.span.end, "expected at least one type argument");
+			}
+			let mut type_args = Vec::new();
+			for (span, item) in items {
+				match item {
+					BracketItem::Type(ty) => type_args.push(ty),
+					BracketItem::Expr(Expr::Var(name)) => type_args.push(Type::Named(name)),
+					BracketItem::Expr(_) => self.report_brackets(span, "expected a type argument")
+				}
+			}
+			return self.parse_call(Expr::Var(name), type_args);
+		}
 
-							let value = self.parse_atom();
+		let is_single_index = matches!(items[..], [(_, BracketItem::Expr(_))]);
+		if !is_single_index && matches!(self.peek_one(), Some(Token {kind: TokenType::Equals, ..})) {
+			let mut type_args = Vec::new();
+			for (span, item) in items {
+				match item {
+					BracketItem::Type(ty) => type_args.push(ty),
+					BracketItem::Expr(Expr::Var(name)) => type_args.push(Type::Named(name)),
+					BracketItem::Expr(_) => self.report_brackets(span, "expected a type parameter")
+				}
+			}
+			let span = self.span_from(name.1.start);
+			return Expr::Generic {name, type_args, span};
+		}
 
-							fields.push((name, Box::new(value)));
-						}
+		let mut items = items.into_iter();
+		let index = match items.next() {
+			Some((_, BracketItem::Expr(expr))) => expr,
+			Some((span, BracketItem::Type(_))) => {
+				self.report_brackets(span, "expected an index expression but got a type");
+				Expr::Error
+			}
+			None => {
+				self.report_brackets(open.span.start..close.span.end, "expected an index");
+				Expr::Error
+			}
+		};
+		if let Some((span, _)) = items.next() {
+			self.report_brackets(span, "expected a single index");
+		}
+		let span = self.span_from(name.1.start);
+		Expr::Index {base: Box::new(Expr::Var(name)), index: Box::new(index), span}
+	}
 
-						self.expect(&[TokenType::RBrace]);
+	fn report_brackets(&mut self, span: Span, label: &str) {
+		self.emitter.error()
+			.with_label(label)
+			.with_span(span)
+			.emit();
+		self.has_error = true;
+	}
 
-						Some(Expr::Construct {name: (ident, primary_token.span), fields})
-					}
-					else if next.kind == TokenType::Dot {
-						self.next();
-						let name = match self.parse_ident("a field name") {
-							Some(ident) => ident,
-							None => return None
-						};
+	/// Parses the parenthesized arguments of a call to `callee`, which has already
+	/// been given its `type_args`.
+	fn parse_call(&mut self, callee: Expr, type_args: Vec<Type>) -> Expr {
+		let open = match self.expect(&[TokenType::LParen]) {
+			Some(open) => open,
+			None => return Expr::Error
+		};
 
-						Some(Expr::FieldAccess {name: (ident, primary_token.span), field: name})
-					}
-					else {
-						Some(Expr::Var((ident, primary_token.span)))
-					}
-				}
-				else {
-					Some(Expr::Var((ident, primary_token.span)))
-				}
-			},
-			TokenType::CharLiteral(literal) => {
-				self.next();
-				Some(Expr::CharLiteral((literal, primary_token.span)))
-			},
-			TokenType::StringLiteral(literal) => {
+		let mut args = Vec::new();
+		let mut named_args = Vec::new();
+		let mut closed = false;
+		// Set once a malformed argument list has been reported, so the missing `)` isn't
+		// reported again.
+		let mut failed = false;
+		while let Some(token) = self.peek_one() {
+			if token.kind == TokenType::RParen {
 				self.next();
-				Some(Expr::StringLiteral((literal, primary_token.span)))
+				closed = true;
+				break;
 			}
-			TokenType::LParen => {
+
+			if token.kind == TokenType::Dot {
 				self.next();
-				let expr = self.parse_atom();
-				let next = self.peek_one();
-				if let Some(next) = next {
-					if next.kind != TokenType::RParen {
-						self.emitter.error()
-							.with_label(format!("expected ')' but got {}", next.kind))
-							.with_span(next.span)
-							.emit();
-						self.has_error = true;
-					}
-					else {
-						self.next();
+				let arg_name = match self.parse_ident("an argument name") {
+					Some(ident) => ident,
+					None => {
+						failed = true;
+						break;
 					}
+				};
+				if self.expect(&[TokenType::Equals]).is_none() {
+					failed = true;
+					break;
 				}
-				else {
+				named_args.push((arg_name, self.parse_atom()));
+			}
+			else {
+				let value = self.parse_atom();
+				if let Some((named, _)) = named_args.last() {
 					self.emitter.error()
-						.with_label("expected ')'")
-						.with_eoi_span()
+						.with_label("positional arguments must come before named arguments")
+						.with_span(token.span)
+						.with_secondary(named.span(), "named argument here")
 						.emit();
 					self.has_error = true;
 				}
-				Some(expr)
+				args.push(value);
 			}
-			_ => None
+
+			match self.expect_closing(&[TokenType::Comma, TokenType::RParen], &open) {
+				Some(token) if token.kind == TokenType::RParen => {
+					closed = true;
+					break;
+				}
+				Some(_) => {}
+				None => {
+					failed = true;
+					break;
+				}
+			}
+		}
+
+		// The input ended before the `)`, like after `f(a,`
+		if !closed && !failed {
+			let name = match &callee {
+				Expr::Var((name, _)) => name.as_str(),
+				callee => callee.span().map_or("", |span| self.emitter.source_slice(span))
+			};
+			self.emitter.error()
+				.with_label(format!("unclosed `(` in call to `{}`", name))
+				.with_eoi_span()
+				.with_secondary(open.span.clone(), "opened here")
+				.emit();
+			self.has_error = true;
+		}
+
+		let span = self.span_from(callee.span().map_or(open.span.start, |span| span.start));
+		Expr::Call {callee: Box::new(callee), type_args, args, named_args, span}
+	}
+
+	/// Parses the `(Type)` after `sizeof` or `alignof`, which take a type rather than an
+	/// expression.
+	fn parse_intrinsic(&mut self, name: Spanned<String>) -> Expr {
+		self.next();
+		if let Some(close) = self.peek_one().filter(|token| token.kind == TokenType::RParen) {
+			self.next();
+			self.emitter.error()
+				.with_label(format!("`{}` expects a type", name.0))
+				.with_span(name.1.start..close.span.end)
+				.emit();
+			self.has_error = true;
+			return Expr::Error;
+		}
+
+		let ty = match self.parse_type() {
+			Some(ty) => ty,
+			None => return Expr::Error
+		};
+		if self.expect(&[TokenType::RParen]).is_none() {
+			return Expr::Error;
+		}
+
+		let span = self.span_from(name.1.start);
+		if name.0 == "sizeof" {
+			Expr::SizeOf(ty, span)
+		}
+		else {
+			Expr::AlignOf(ty, span)
 		}
 	}
 
 	fn expect(&mut self, expected: &[TokenType]) -> Option<Token> {
+		self.expect_with_opener(expected, None)
+	}
+
+	/// Like `expect` for the tokens that can close `open`, also pointing at `open` if
+	/// none of them follows.
+	fn expect_closing(&mut self, expected: &[TokenType], open: &Token) -> Option<Token> {
+		self.expect_with_opener(expected, Some(open))
+	}
+
+	fn expect_with_opener(&mut self, expected: &[TokenType], open: Option<&Token>) -> Option<Token> {
 		let label = move || {
 			let mut label = "expected ".to_string();
 			let len = expected.len();
@@ -277,31 +1145,53 @@ impl<'source, W: Write> Parser<'source, W> {
 			}
 			label
 		};
-		match self.peek_one() {
+		let error = match self.peek_one() {
 			Some(token) => {
 				if expected.contains(&token.kind) {
 					self.next();
-					Some(token)
-				}
-				else {
-					let label = label() + format!(" but got {}", token.kind).as_str();
-					self.emitter.error()
-						.with_label(label)
-						.with_span(token.span)
-						.emit();
-					self.has_error = true;
-					None
+					return Some(token);
 				}
+				let label = label() + format!(" but got {}", token.kind).as_str();
+				self.emitter.error()
+					.with_label(label)
+					.with_span(token.span)
+			}
+			None if self.eof_reported => {
+				self.has_error = true;
+				return None;
 			}
 			None => {
+				self.eof_reported = true;
 				let label = label() + " but found eof";
 				self.emitter.error()
 					.with_label(label)
 					.with_eoi_span()
-					.emit();
-				self.has_error = true;
-				None
 			}
+		};
+		match open {
+			Some(open) => error.with_secondary(open.span.clone(), "opened here"),
+			None => error
+		}.emit();
+		self.has_error = true;
+		None
+	}
+
+	/// Whether the next token starts on a new line (or is the end of input) in relaxed
+	/// mode, which ends the current statement.
+	fn at_newline(&mut self) -> bool {
+		self.relaxed && (self.peek_one().is_none() || self.newline_seen)
+	}
+
+	fn expect_terminator(&mut self) {
+		let terminated = if self.relaxed {
+			self.at_newline() || self.expect(&[TokenType::Semicolon, TokenType::Newline]).is_some()
+		}
+		else {
+			self.expect(&[TokenType::Semicolon]).is_some()
+		};
+
+		if !terminated {
+			self.recover_to_statement();
 		}
 	}
 
@@ -310,7 +1200,7 @@ impl<'source, W: Write> Parser<'source, W> {
 			Some(token) => match token.kind {
 				TokenType::Identifier(ident) => {
 					self.next();
-					Some((ident, token.span))
+					Some(spanned(ident, token.span))
 				},
 				_ => {
 					self.emitter.error()
@@ -332,32 +1222,203 @@ impl<'source, W: Write> Parser<'source, W> {
 		}
 	}
 
-	fn parse_ident_type(&mut self) -> Option<(Spanned<String>, Spanned<String>)> {
+	/// Parses a type name with an optional trailing `?` marking an optional type.
+	/// Nested optionals like `int??` are rejected: the extra `?` is reported and
+	/// skipped, leaving a single level of optionality.
+	fn parse_type(&mut self) -> Option<Type> {
+		let mut ty = match self.peek_one() {
+			Some(Token {kind: TokenType::LParen, ..}) => self.parse_tuple_type()?,
+			Some(Token {kind: TokenType::LBracket, ..}) => self.parse_array_type()?,
+			_ => Type::Named(self.parse_ident("a type")?)
+		};
+
+		while let Some(token) = self.peek_one() {
+			if token.kind != TokenType::Question {
+				break;
+			}
+			self.next();
+
+			if let Type::Optional(_) = ty {
+				self.emitter.error()
+					.with_label("nested optional types are not supported")
+					.with_span(token.span)
+					.emit();
+				self.has_error = true;
+			}
+			else {
+				ty = Type::Optional(Box::new(ty));
+			}
+		}
+
+		Some(ty)
+	}
+
+	/// Parses an array type like `[int; 4]` or `[int; N]`, where `N` names a `const`.
+	/// Whether `N` is one is checked by the linter.
+	fn parse_array_type(&mut self) -> Option<Type> {
+		let open = self.next().unwrap();
+		let element = self.parse_type()?;
+		self.expect(&[TokenType::Semicolon])?;
+		let len = match self.peek_one() {
+			Some(Token {kind: TokenType::Num(num), span}) => {
+				self.next();
+				ArrayLen::Num(spanned(num, span))
+			}
+			_ => ArrayLen::Const(self.parse_ident("an array length")?)
+		};
+		let close = self.expect(&[TokenType::RBracket])?;
+		Some(Type::Array(Box::new(element), len, open.span.start..close.span.end))
+	}
+
+	/// Parses a tuple type like `(int, int)` or `(int,)`. Without a comma the parentheses
+	/// only group, so `(int)` is just `int`.
+	fn parse_tuple_type(&mut self) -> Option<Type> {
+		let open = self.next().unwrap();
+		let mut types = Vec::new();
+		let mut is_tuple = false;
+		let close = loop {
+			types.push(self.parse_type()?);
+			let token = self.expect(&[TokenType::Comma, TokenType::RParen])?;
+			if token.kind == TokenType::RParen {
+				break token;
+			}
+			is_tuple = true;
+			if let Some(close) = self.peek_one().filter(|token| token.kind == TokenType::RParen) {
+				self.next();
+				break close;
+			}
+		};
+
+		if is_tuple {
+			Some(Type::Tuple((types, open.span.start..close.span.end)))
+		}
+		else {
+			types.pop()
+		}
+	}
+
+	/// Whether the next token can start a type: a name, the `(` of a tuple type or the
+	/// `[` of an array type.
+	fn at_type(&mut self) -> bool {
+		matches!(self.peek_one().map(|token| token.kind),
+			Some(TokenType::Identifier(_) | TokenType::LParen | TokenType::LBracket))
+	}
+
+	/// Parses the optional `: <width>` of a bitfield member. The width must be positive
+	/// and, for builtin integer types, fit in the type.
+	fn parse_bit_width(&mut self, r#type: &Type) -> Option<Spanned<u64>> {
+		let colon = self.peek_one()?;
+		if colon.kind != TokenType::Colon {
+			return None;
+		}
+		self.next();
+
+		let (width, span) = match self.peek_one() {
+			Some(Token {kind: TokenType::Num(width), span}) => {
+				self.next();
+				(width, span)
+			}
+			Some(token) => {
+				self.emitter.error()
+					.with_label(format!("expected a bit width but got {}", token.kind))
+					.with_span(token.span)
+					.emit();
+				self.has_error = true;
+				return None;
+			}
+			None => {
+				self.emitter.error()
+					.with_label("expected a bit width but found eof")
+					.with_eoi_span()
+					.emit();
+				self.has_error = true;
+				return None;
+			}
+		};
+
+		if width == 0 {
+			self.emitter.error()
+				.with_label("bit width must be positive")
+				.with_span(span.clone())
+				.emit();
+			self.has_error = true;
+		}
+		else if let Some(bits) = r#type.int_bits() {
+			if width > bits {
+				// Echo the width as written, so `0x10` isn't reported as 16.
+				self.emitter.error()
+					.with_label(format!("bit width {} exceeds the {} bits of the field type",
+						self.emitter.source_slice(span.clone()), bits))
+					.with_span(span.clone())
+					.emit();
+				self.has_error = true;
+			}
+		}
+
+		Some((width, span))
+	}
+
+	/// Parses `name: type`, where `what` names the declared item (e.g. "field") for the
+	/// missing type diagnostic.
+	fn parse_ident_type(&mut self, what: &str) -> Option<(Spanned<String>, Type)> {
 		let name = self.parse_ident("an identifier")?;
 
 		self.expect(&[TokenType::Colon])?;
 
-		let r#type = self.parse_ident("a type")?;
+		if !self.at_type() {
+			let error = self.emitter.error()
+				.with_label(format!("missing type for {} `{}`", what, name.0));
+			match self.peek_one() {
+				Some(token) => error.with_span(token.span),
+				None => error.with_eoi_span()
+			}.emit();
+			self.has_error = true;
+			return None;
+		}
+
+		let r#type = self.parse_type()?;
 
 		Some((name, r#type))
 	}
 
-	fn skip_until(&mut self, until: &[(TokenType, usize)]) {
+	fn starts_statement(&mut self, token: &Token) -> bool {
+		match token.kind {
+			TokenType::Ret | TokenType::Assert => true,
+			TokenType::Identifier(_) => match self.peek(PeekCount::Two) {
+				Some(next) => next.kind == TokenType::Equals || next.kind == TokenType::Colon,
+				None => false
+			},
+			_ => false
+		}
+	}
+
+	/// Skips the rest of a malformed statement. Stops after a `;` or a block closing
+	/// at the current nesting depth, or before a token that begins a new statement
+	/// (or a new line in relaxed mode).
+	/// An unmatched `}` is left for the enclosing block, or skipped at top level.
+	fn recover_to_statement(&mut self) {
+		let mut depth = 0usize;
 		while let Some(token) = self.peek_one() {
-			if let Some(next) = self.peek(PeekCount::Two) {
-				for (t, amount) in until.iter() {
-					if *amount == 2 && next.kind == *t {
-						return;
-					}
-				}
+			if depth == 0 && self.relaxed && self.newline_seen {
+				return;
 			}
-			for (t, amount) in until.iter() {
-				if token.kind == *t {
-					if *amount == 0 {
+
+			match token.kind {
+				TokenType::LBrace | TokenType::LParen => depth += 1,
+				TokenType::RBrace | TokenType::RParen if depth > 0 => {
+					depth -= 1;
+					if depth == 0 && token.kind == TokenType::RBrace {
 						self.next();
 						return;
 					}
-					else if *amount == 1 {
+				}
+				TokenType::RBrace if self.block_depth > 0 => return,
+				TokenType::Semicolon if depth == 0 => {
+					self.next();
+					return;
+				}
+				_ => {
+					if depth == 0 && self.starts_statement(&token) {
 						return;
 					}
 				}
@@ -367,42 +1428,50 @@ impl<'source, W: Write> Parser<'source, W> {
 		}
 	}
 
-	fn recover(&mut self, next_elem_mark: Option<TokenType>, clause_end_mark: Option<TokenType>) -> Recovery {
-		let mut braces = 0;
-		while let Some(token) = self.peek_one() {
-			if token.kind == TokenType::LBrace || token.kind == TokenType::LParen {
-				braces += 1;
-			}
-			else if token.kind == TokenType::RBrace || token.kind == TokenType::RParen {
-				braces -= 1;
-			}
-			if let Some(next_elem_mark) = next_elem_mark.clone() {
-				if token.kind == next_elem_mark && braces == 0 {
-					return Recovery::Continue(token.span);
-				}
-			}
-			if let Some(clause_end_mark) = clause_end_mark.clone() {
-				if token.kind == clause_end_mark && braces == 0 {
-					return Recovery::Break(token.span);
-				}
+	/// The name a struct or function declaration assigns to, which must be a plain variable.
+	fn decl_name(&mut self, target: &Expr, equals: &Token) -> Spanned<String> {
+		match target {
+			Expr::Var(ident) => ident.clone(),
+			_ => {
+				self.emitter.error()
+					.with_label("expected an identifier before `=`")
+					.with_span(equals.span.clone())
+					.emit();
+				self.has_error = true;
+				(String::new(), 0..0)
 			}
-
-			self.next();
 		}
+	}
 
-		if braces > 0 {
-			self.emitter.error()
-				.with_eoi_span()
-				.with_label("mismatched braces")
-				.emit();
+	/// Parses the optional `: Base` between `struct` and its `{`.
+	fn parse_struct_base(&mut self) -> Option<Type> {
+		let colon = self.peek_one()?;
+		if colon.kind != TokenType::Colon {
+			return None;
 		}
+		self.next();
 
-		Recovery::Eof
+		match self.peek_one() {
+			Some(Token {kind: TokenType::Identifier(_), ..}) => self.parse_type(),
+			token => {
+				let error = self.emitter.error()
+					.with_label("expected a base type after ':'");
+				match token {
+					Some(token) => error.with_span(token.span),
+					None => error.with_eoi_span()
+				}.emit();
+				self.has_error = true;
+				None
+			}
+		}
 	}
 
-	fn parse_assign(&mut self, target: Expr) -> Expr {
+	/// Parses `target = ...` from the `=`. A plain value assignment must be followed by
+	/// a statement terminator if `terminated` is set.
+	fn parse_assign(&mut self, target: Expr, terminated: bool) -> Expr {
 		// =
 		let equals = self.next().unwrap();
+		let start = target.span().map_or(equals.span.start, |span| span.start);
 
 		let token = match self.peek_one() {
 			Some(token) => token,
@@ -410,31 +1479,21 @@ impl<'source, W: Write> Parser<'source, W> {
 				self.emitter.error()
 					.with_label("expected an expression")
 					.with_eoi_span()
+					.with_secondary(equals.span.clone(), "after this `=`")
 					.emit();
 				self.has_error = true;
 				Token::new(TokenType::Num(0), 0..0)
 			}
 		};
 
-		let mut name = (String::new(), 0..0);
-		if token.kind == TokenType::Struct || token.kind == TokenType::LParen {
-			name = match &target {
-				Expr::Var(ident) => ident.clone(),
-				_ => {
-					self.emitter.error()
-						.with_label("expected an identifier")
-						.with_span(equals.span)
-						.emit();
-					self.has_error = true;
-					(String::new(), 0..0)
-				}
-			};
-		}
-
-		if token.kind == TokenType::Struct {
+		let kind = self.classify_assign();
+		if kind == AssignKind::Struct {
+			let name = self.decl_name(&target, &equals);
 			self.next();
+			let base = self.parse_struct_base();
 
-			if self.expect(&[TokenType::LBrace]).is_none() {
+			let open = self.expect(&[TokenType::LBrace]);
+			if open.is_none() {
 				let mut good = false;
 				if let Some(token) = self.peek_one() {
 					if token.kind == TokenType::RBrace {
@@ -445,16 +1504,13 @@ impl<'source, W: Write> Parser<'source, W> {
 					}
 				}
 
+				// Keep parsing fields only if the second token still looks like `name:`.
+				// Near the end of input there may be no second token, which recovers too.
 				if !good {
-					match self.peek(PeekCount::Two) {
-						Some(token) => {
-							if token.kind != TokenType::Colon {
-								return Expr::Error;
-							}
-						}
-						None => {
-							return Expr::Error;
-						}
+					let second = self.peek(PeekCount::Two);
+					if !matches!(second, Some(Token {kind: TokenType::Colon, ..})) {
+						self.recover_to_statement();
+						return Expr::Error;
 					}
 				}
 			}
@@ -468,17 +1524,27 @@ impl<'source, W: Write> Parser<'source, W> {
 					break;
 				}
 
-				let name_type = match self.parse_ident_type() {
+				let doc = self.doc.take();
+				let (field_name, field_type) = match self.parse_ident_type("field") {
 					Some(name_type) => name_type,
 					None => {
-						self.skip_until(&[(TokenType::Semicolon, 0)]);
+						self.recover_to_statement();
 						return Expr::Error;
 					}
 				};
 
-				fields.push(name_type);
+				let width = self.parse_bit_width(&field_type);
+				let default = match self.peek_one() {
+					Some(Token {kind: TokenType::Equals, ..}) => {
+						self.next();
+						Some(self.parse_atom())
+					}
+					_ => None
+				};
+
+				fields.push(Field {name: field_name, r#type: field_type, doc, width, default});
 
-				match self.expect(&[TokenType::Comma, TokenType::RBrace]) {
+				match self.expect_with_opener(&[TokenType::Comma, TokenType::RBrace], open.as_ref()) {
 					Some(token) => {
 						if token.kind == TokenType::RBrace {
 							is_good = true;
@@ -486,23 +1552,22 @@ impl<'source, W: Write> Parser<'source, W> {
 						}
 					}
 					None => {
-						return Expr::Struct {name, fields};
+						let span = self.span_from(start);
+						return Expr::Struct {name, base, fields, attributes: Vec::new(), span};
 					}
 				}
 			}
 
 			if !is_good {
-				self.emitter.error()
-					.with_label("expected '}' but found eof")
-					.with_eoi_span()
-					.emit();
-				self.has_error = true;
+				self.expect_with_opener(&[TokenType::RBrace], open.as_ref());
 			}
 
-			return Expr::Struct {name, fields};
+			let span = self.span_from(start);
+			Expr::Struct {name, base, fields, attributes: Vec::new(), span}
 		}
-		else if token.kind == TokenType::LParen {
+		else if kind == AssignKind::Function {
 			self.next();
+			let (name, type_params) = self.generic_decl_name(&target, &equals);
 
 			let mut skip_signature = false;
 			if let Some(token) = self.peek_one() {
@@ -525,9 +1590,12 @@ impl<'source, W: Write> Parser<'source, W> {
 						break;
 					}
 
-					let name_type = match self.parse_ident_type() {
+					let name_type = match self.parse_ident_type("argument") {
 						Some(name_type) => name_type,
-						None => return Expr::Error
+						None => {
+							self.recover_to_statement();
+							return Expr::Error;
+						}
 					};
 
 					args.push(name_type);
@@ -542,66 +1610,78 @@ impl<'source, W: Write> Parser<'source, W> {
 							if let Some(token) = self.peek_one() {
 								if let TokenType::Identifier(_) = token.kind {}
 								else {
-									break;
-								}
-							}
-						}
-					}
-				}
-			}
-
-			let mut ret_type = (String::new(), 0..0);
-			if let Some(token) = self.peek_one() {
-				if token.kind == TokenType::Arrow {
-					self.next();
-
-					let r#type = match self.parse_ident("a type") {
-						Some(ident) => ident,
-						None => {
-							if let Some(token) = self.peek_one() {
-								if token.kind != TokenType::Comma {
-									return Expr::Assign {target: Box::new(target),
-										value: Box::new(Expr::Error)}
-								}
-								else {
-									(String::new(), 0..0)
-								}
-							}
-							else {
-								return Expr::Assign {target: Box::new(target),
-									value: Box::new(Expr::Error)}
+									break;
+								}
 							}
 						}
-					};
-
-					ret_type = r#type;
+					}
 				}
 			}
 
-			let s = self.expect(&[TokenType::LBrace, TokenType::Semicolon]);
-			match s {
-				Some(s) => {
-					if s.kind == TokenType::Semicolon {
-						return Expr::Function {name, args, ret_type, body: None};
+			let mut ret_type = None;
+			if let Some(token) = self.peek_one() {
+				match &token.kind {
+					TokenType::Arrow => {
+						self.next();
+
+						if !self.at_type() {
+							let error = self.emitter.error()
+								.with_label("expected a type after '->'")
+								.with_secondary(token.span.clone(), "return type arrow here");
+							match self.peek_one() {
+								Some(next) => error.with_span(next.span),
+								None => error.with_eoi_span()
+							}.emit();
+							self.has_error = true;
+							self.recover_to_statement();
+							let span = self.span_from(start);
+							return Expr::Assign {target: Box::new(target),
+								value: Box::new(Expr::Error), span}
+						}
+
+						let r#type = match self.parse_type() {
+							Some(ty) => ty,
+							None => {
+								self.recover_to_statement();
+								let span = self.span_from(start);
+								return Expr::Assign {target: Box::new(target),
+									value: Box::new(Expr::Error), span}
+							}
+						};
+
+						ret_type = Some(r#type);
 					}
-				}
-				None => {
-					return Expr::Function {name, args, ret_type, body: None};
+					// `where` starts the constraints of a function without a return type
+					TokenType::Identifier(ty) if ty != "where" => {
+						self.emitter.error()
+							.with_label(format!("expected '->' before return type `{}`", ty))
+							.with_span(token.span.clone())
+							.with_help(format!("add '->' to declare the return type: `) -> {}`", ty))
+							.emit();
+						self.has_error = true;
+						ret_type = self.parse_type();
+					}
+					_ => {}
 				}
 			}
 
-			let mut body = Vec::new();
-			while let Some(token) = self.peek_one() {
-				if token.kind == TokenType::RBrace {
-					break;
-				}
+			let constraints = self.parse_where_clause();
 
-				body.push(self.parse_expression());
-			}
+			// A declaration without a body ends at its signature, not at the `;`
+			let signature = self.span_from(start);
+			let open = match self.expect(&[TokenType::LBrace, TokenType::Semicolon]) {
+				Some(s) if s.kind == TokenType::LBrace => s,
+				_ => {
+					return Expr::Function {name, type_params, constraints, args, ret_type, body: None,
+						attributes: Vec::new(), is_extern: false, span: signature};
+				}
+			};
 
-			self.expect(&[TokenType::RBrace]);
+			let body = self.parse_block_body(&open);
 
-			return Expr::Function {name, args, ret_type, body: Some(body)};
+			let span = self.span_from(start);
+			Expr::Function {name, type_params, constraints, args, ret_type, body: Some(body),
+				attributes: Vec::new(), is_extern: false, span}
 		}
 		else if token.kind == TokenType::RParen {
 			self.next();
@@ -610,63 +1690,234 @@ impl<'source, W: Write> Parser<'source, W> {
 				.with_span(token.span)
 				.emit();
 			self.has_error = true;
-			if let Some(token) = self.expect(&[TokenType::Semicolon, TokenType::LBrace]) {
-				if token.kind == TokenType::Semicolon {
-					return Expr::Error;
-				}
-				else {
-					self.skip_until(&[(TokenType::RBrace, 0)]);
-					return Expr::Error;
-				}
-			}
-			else {
-				self.skip_until(&[(TokenType::RBrace, 0)]);
-				return Expr::Error;
-			}
+			self.recover_to_statement();
+			Expr::Error
 		}
 		else {
+			self.check_assign_target(&target, &equals);
 			let value = self.parse_atom();
-			self.expect(&[TokenType::Semicolon]);
-			Expr::Assign {target: Box::new(target), value: Box::new(value)}
+			let span = self.span_from(start);
+			if terminated {
+				self.expect_terminator();
+			}
+			Expr::Assign {target: Box::new(target), value: Box::new(value), span}
+		}
+
+	}
+
+	/// Decides what `name = ...` declares from the tokens after the `=`:
+	/// `struct` starts a struct, `()`, `(name: type`, `(name type` or `(` followed by
+	/// `{` or the end of input starts a function, and anything else is a value.
+	fn classify_assign(&mut self) -> AssignKind {
+		match self.peek_one().map(|token| token.kind) {
+			Some(TokenType::Struct) => AssignKind::Struct,
+			Some(TokenType::LParen) => match self.peek(PeekCount::Two).map(|token| token.kind) {
+				Some(TokenType::Identifier(_)) => match self.peek(PeekCount::Three).map(|token| token.kind) {
+					Some(TokenType::Colon | TokenType::Identifier(_)) => AssignKind::Function,
+					_ => AssignKind::Value
+				},
+				Some(TokenType::RParen | TokenType::LBrace) | None => AssignKind::Function,
+				_ => AssignKind::Value
+			},
+			_ => AssignKind::Value
+		}
+	}
+
+	fn check_assign_target(&mut self, target: &Expr, equals: &Token) {
+		if let Expr::Generic {name, ..} = target {
+			self.emitter.error()
+				.with_label("type parameters are only allowed on function declarations")
+				.with_span(name.span())
+				.with_secondary(equals.span.clone(), "assigned here")
+				.emit();
+			self.has_error = true;
+		}
+	}
+
+	/// The name and type parameters of a function declared as `target`: `f` or `f[T, U]`.
+	fn generic_decl_name(&mut self, target: &Expr, equals: &Token) -> (Spanned<String>, Vec<Spanned<String>>) {
+		let (name, type_args) = match target {
+			Expr::Index {base, index, ..} => match (&**base, &**index) {
+				(Expr::Var(name), Expr::Var(param)) => return (name.clone(), vec![param.clone()]),
+				_ => return (self.decl_name(target, equals), Vec::new())
+			},
+			Expr::Generic {name, type_args, ..} => (name, type_args),
+			_ => return (self.decl_name(target, equals), Vec::new())
+		};
+
+		let mut type_params = Vec::new();
+		for ty in type_args {
+			match ty {
+				Type::Named(param) => type_params.push(param.clone()),
+				Type::Optional(_) | Type::Tuple(_) | Type::Array(..) => {
+					self.emitter.error()
+						.with_label("expected a type parameter name")
+						.with_span(ty.span())
+						.emit();
+					self.has_error = true;
+				}
+			}
+		}
+		(name.clone(), type_params)
+	}
+
+	/// Parses an optional `where T: Bound, U: Bound` before a function body. A malformed
+	/// constraint skips the rest of the clause.
+	fn parse_where_clause(&mut self) -> Vec<(Spanned<String>, Spanned<String>)> {
+		let mut constraints = Vec::new();
+		if !matches!(self.peek_one(), Some(Token {kind: TokenType::Identifier(ident), ..}) if ident == "where") {
+			return constraints;
 		}
+		self.next();
+
+		loop {
+			let param = self.parse_ident("a type parameter");
+			let bound = match param {
+				Some(_) if self.expect(&[TokenType::Colon]).is_some() => self.parse_ident("a bound"),
+				_ => None
+			};
+			match (param, bound) {
+				(Some(param), Some(bound)) => constraints.push((param, bound)),
+				_ => {
+					while !matches!(self.peek_one().map(|token| token.kind),
+						Some(TokenType::LBrace | TokenType::Semicolon) | None) {
+						self.next();
+					}
+					return constraints;
+				}
+			}
 
+			if !matches!(self.peek_one(), Some(Token {kind: TokenType::Comma, ..})) {
+				return constraints;
+			}
+			self.next();
+		}
 	}
 
-	fn parse_vardecl(&mut self, name: Spanned<String>) -> Expr {
+	/// Parses the `: type [= value]` of a declaration of `names`, a `MultiVarDecl` if
+	/// there is more than one.
+	fn parse_vardecl(&mut self, mut names: Vec<Spanned<String>>) -> Expr {
 		self.next();
 
-		let r#type = match self.parse_ident("a type") {
-			Some(ident) => ident,
+		let r#type = match self.parse_type() {
+			Some(ty) => ty,
 			None => {
+				self.recover_to_statement();
 				return Expr::Error;
 			}
 		};
 
-		let s = self.expect(&[TokenType::Equals, TokenType::Semicolon]);
-		if let Some(s) = s {
-			if s.kind == TokenType::Equals {
-				let value = self.parse_atom();
-				self.expect(&[TokenType::Semicolon]);
-				Expr::VarDecl {name, r#type, value: Some(Box::new(value))}
+		let mut value = None;
+		if !self.at_newline() {
+			let s = self.expect(&[TokenType::Equals, TokenType::Semicolon]);
+			if let Some(s) = s {
+				if s.kind == TokenType::Equals {
+					value = Some(Box::new(self.parse_atom()));
+					self.expect_terminator();
+				}
 			}
 			else {
-				Expr::VarDecl {name, r#type, value: None}
+				self.recover_to_statement();
 			}
 		}
+
+		if names.len() == 1 {
+			Expr::VarDecl {name: names.pop().unwrap(), r#type, value}
+		}
 		else {
-			Expr::VarDecl {name, r#type, value: None}
+			Expr::MultiVarDecl {names, r#type, value}
+		}
+	}
+
+	/// Parses the rest of `a, b, c: type [= value]` after the first name.
+	fn parse_multi_vardecl(&mut self, first: Spanned<String>) -> Expr {
+		let mut names = vec![first];
+		while let Some(Token {kind: TokenType::Comma, ..}) = self.peek_one() {
+			self.next();
+			match self.parse_ident("a variable name") {
+				Some(name) => names.push(name),
+				None => {
+					self.recover_to_statement();
+					return Expr::Error;
+				}
+			}
+		}
+
+		if !matches!(self.peek_one(), Some(Token {kind: TokenType::Colon, ..})) {
+			self.expect(&[TokenType::Colon]);
+			self.recover_to_statement();
+			return Expr::Error;
 		}
+		self.parse_vardecl(names)
 	}
 
+	/// Parses an expression in value position, including ranges.
 	fn parse_atom(&mut self) -> Expr {
+		if self.at_range() {
+			return self.parse_range(None);
+		}
+
+		let expr = self.parse_operand();
+		if self.at_range() {
+			self.parse_range(Some(expr))
+		}
+		else {
+			expr
+		}
+	}
+
+	fn at_range(&mut self) -> bool {
+		matches!(self.peek_one(), Some(Token {kind: TokenType::DotDot | TokenType::DotDotEquals, ..}))
+	}
+
+	/// Parses the `..` or `..=` following `start` and the optional end bound.
+	fn parse_range(&mut self, start: Option<Expr>) -> Expr {
+		let op = self.next().unwrap();
+		let inclusive = op.kind == TokenType::DotDotEquals;
+
+		let has_end = !self.at_newline() && matches!(self.peek_one().map(|token| token.kind),
+			Some(TokenType::Num(_) | TokenType::Float(_) | TokenType::Identifier(_) | TokenType::CharLiteral(_)
+				| TokenType::StringLiteral(_) | TokenType::LParen | TokenType::LBracket | TokenType::BinOp(BinOp::Minus | BinOp::Add | BinOp::Not)));
+		let end = if has_end {
+			Some(Box::new(self.parse_operand()))
+		}
+		else {
+			if inclusive {
+				self.emitter.error()
+					.with_label("inclusive range must have an end")
+					.with_span(op.span.clone())
+					.emit();
+				self.has_error = true;
+			}
+			None
+		};
+
+		let start_offset = start.as_ref().and_then(Expr::span).map_or(op.span.start, |span| span.start);
+		let span = self.span_from(start_offset);
+		Expr::Range {start: start.map(Box::new), end, inclusive, span}
+	}
+
+	/// Parses a primary expression followed by any binary operators.
+	fn parse_operand(&mut self) -> Expr {
 		let primary = match self.parse_primary() {
 			Some(expr) => expr,
 			None => {
 				match self.peek_one() {
+					Some(token) if token.kind == TokenType::Assert => {
+						self.next();
+						self.emitter.error()
+							.with_label("`assert` is a statement and can't be used as a value")
+							.with_span(token.span.clone())
+							.emit();
+						self.has_error = true;
+						self.parse_assert(token);
+						return Expr::Error;
+					}
 					Some(token) => {
 						self.next();
 						self.emitter.error()
-							.with_label(format!("expected a primary expression but got {}", token.kind))
+							.with_label(format!("unexpected token `{}`, expected a primary expression",
+								self.emitter.source_slice(token.span.clone())))
 							.with_span(token.span)
 							.emit();
 						self.has_error = true;
@@ -691,36 +1942,86 @@ impl<'source, W: Write> Parser<'source, W> {
 			}
 		};
 
-		if let TokenType::BinOp(_) = token.kind {
+		let expr = if let TokenType::BinOp(_) = token.kind {
 			self.parse_binexp(primary, 0)
 		}
 		else {
 			primary
-		}
+		};
+		self.parse_misplaced_arrow(expr)
+	}
+
+	/// Reports an `->` following an expression and skips the operand after it.
+	fn parse_misplaced_arrow(&mut self, expr: Expr) -> Expr {
+		let arrow = match self.peek_one() {
+			Some(token) if token.kind == TokenType::Arrow => token,
+			_ => return expr
+		};
+		self.next();
+
+		self.emitter.error()
+			.with_label("`->` is only valid in a function return type")
+			.with_span(arrow.span)
+			.emit();
+		self.has_error = true;
+		self.parse_operand();
+		Expr::Error
 	}
 
 	fn parse_expression(&mut self) -> Expr {
+		let attributes = self.parse_attributes();
+		self.report_misplaced_attributes(&attributes, "attributes are only allowed on top-level declarations");
+
 		let primary = match self.parse_primary() {
 			Some(token) => token,
 			None => {
 				match self.peek_one() {
 					Some(token) => {
+						if token.kind == TokenType::Break {
+							self.next();
+							return self.parse_break(token);
+						}
+						if token.kind == TokenType::Const {
+							self.next();
+							return self.parse_const(token);
+						}
+						if token.kind == TokenType::Assert {
+							self.next();
+							let assert = self.parse_assert(token);
+							self.expect_terminator();
+							return assert;
+						}
 						if token.kind == TokenType::Ret {
 							self.next();
-							if let Some(token) = self.peek_one() {
-								if token.kind == TokenType::Semicolon {
+							if let Some(semicolon) = self.peek_one() {
+								if semicolon.kind == TokenType::Semicolon {
 									self.next();
-									return Expr::Ret {value: None};
+									return Expr::Ret {value: None, span: token.span};
 								}
 							}
+							if self.at_newline() {
+								return Expr::Ret {value: None, span: token.span};
+							}
 							let value = self.parse_atom();
-							self.expect(&[TokenType::Semicolon]);
-							return Expr::Ret {value: Some(Box::new(value))};
+							let span = self.span_from(token.span.start);
+							self.expect_terminator();
+							return Expr::Ret {value: Some(Box::new(value)), span};
 						}
 
 						self.next();
+						if let TokenType::BinOp(_) | TokenType::BinOpEquals(_) | TokenType::Equals = token.kind {
+							self.emitter.error()
+								.with_label(format!("expected an expression; `{}` cannot start a statement",
+									self.emitter.source_slice(token.span.clone())))
+								.with_span(token.span)
+								.emit();
+							self.has_error = true;
+							self.recover_to_statement();
+							return Expr::Error;
+						}
 						self.emitter.error()
-							.with_label(format!("expected a primary expression but got {}", token.kind))
+							.with_label(format!("unexpected token `{}`, expected a primary expression",
+								self.emitter.source_slice(token.span.clone())))
 							.with_span(token.span)
 							.emit();
 						self.has_error = true;
@@ -741,21 +2042,35 @@ impl<'source, W: Write> Parser<'source, W> {
 		let token = match self.peek_one() {
 			Some(token) => token,
 			None => {
-				self.emitter.error()
-					.with_label("expected an expression but found eof")
-					.with_eoi_span()
-					.emit();
-				self.has_error = true;
-				return Expr::Error;
+				if !matches!(primary, Expr::Block {..} | Expr::If {..} | Expr::For {..}) {
+					self.expect_terminator();
+				}
+				return primary;
 			}
 		};
 
 		match token.kind {
-			TokenType::BinOp(_) => self.parse_binexp(primary, 0),
-			TokenType::Equals => self.parse_assign(primary),
+			TokenType::BinOp(_) => {
+				let expr = self.parse_binexp(primary, 0);
+				self.finish_expression(expr)
+			},
+			TokenType::Equals => {
+				let expr = self.parse_assign(primary, true);
+				// A declaration ending in a body may be followed by a single `;`
+				let has_body = matches!(expr, Expr::Struct {..} | Expr::Function {body: Some(_), ..});
+				if has_body && matches!(self.peek_one(), Some(Token {kind: TokenType::Semicolon, ..})) {
+					self.next();
+				}
+				expr
+			}
+			TokenType::BinOpEquals(_) => {
+				let expr = self.parse_compound_assign(primary);
+				self.expect_terminator();
+				expr
+			}
 			TokenType::Colon => {
 				if let Expr::Var(var) = primary {
-					self.parse_vardecl(var)
+					self.parse_vardecl(vec![var])
 				}
 				else {
 					self.emitter.error()
@@ -766,22 +2081,265 @@ impl<'source, W: Write> Parser<'source, W> {
 					Expr::Error
 				}
 			},
-			t => todo!("{}", t)
+			TokenType::Arrow => {
+				let expr = self.parse_misplaced_arrow(primary);
+				self.expect_terminator();
+				expr
+			},
+			TokenType::Comma => match primary {
+				Expr::Var(var) => self.parse_multi_vardecl(var),
+				_ => {
+					self.expect_terminator();
+					primary
+				}
+			},
+			_ if matches!(primary, Expr::Block {..} | Expr::If {..} | Expr::For {..}) => primary,
+			_ => self.finish_expression(primary)
+		}
+	}
+
+	/// Ends an expression statement. An expression directly followed by the `}` closing
+	/// a block, without a `;`, is the block's value instead.
+	fn finish_expression(&mut self, expr: Expr) -> Expr {
+		let at_close = matches!(self.peek_one(), Some(Token {kind: TokenType::RBrace, ..}));
+		if self.block_depth > 0 && at_close && !matches!(expr, Expr::Error) {
+			return Expr::Tail {value: Box::new(expr)};
 		}
+		self.expect_terminator();
+		expr
 	}
 
 	fn has_eof(&mut self) -> bool {
-		self.lexer.peek(PeekCount::One).is_none()
+		self.peek_one().is_none()
+	}
+
+	/// Consumes a run of the same unmatched closing delimiter, reporting it once.
+	fn skip_unmatched(&mut self, delimiter: Token) {
+		let mut count = 0;
+		let mut span = delimiter.span.clone();
+		while let Some(token) = self.peek_one() {
+			if token.kind != delimiter.kind {
+				break;
+			}
+			self.next();
+			count += 1;
+			span.end = token.span.end;
+		}
+
+		let label = if count == 1 {
+			format!("unmatched {}", delimiter.kind)
+		}
+		else {
+			format!("{} unmatched {}", count, delimiter.kind)
+		};
+		self.emitter.error()
+			.with_label(label)
+			.with_span(span)
+			.emit();
+		self.has_error = true;
+	}
+
+	/// Parses any number of `#[name]` or `#[name(args)]` attributes, stopping at the first
+	/// malformed one.
+	fn parse_attributes(&mut self) -> Vec<Attribute> {
+		let mut attributes = Vec::new();
+		while let Some(Token {kind: TokenType::Hash, ..}) = self.peek_one() {
+			self.next();
+			if self.expect(&[TokenType::LBracket]).is_none() {
+				break;
+			}
+
+			let name = match self.parse_ident("an attribute name") {
+				Some(name) => name,
+				None => break
+			};
+
+			let mut args = Vec::new();
+			if let Some(Token {kind: TokenType::LParen, ..}) = self.peek_one() {
+				self.next();
+				while let Some(token) = self.peek_one() {
+					if token.kind == TokenType::RParen {
+						self.next();
+						break;
+					}
+
+					args.push(self.parse_atom());
+
+					match self.expect(&[TokenType::Comma, TokenType::RParen]) {
+						Some(Token {kind: TokenType::RParen, ..}) => break,
+						Some(_) => {}
+						None => break
+					}
+				}
+			}
+
+			if self.expect(&[TokenType::RBracket]).is_none() {
+				break;
+			}
+			attributes.push(Attribute {name, args});
+		}
+		attributes
+	}
+
+	fn report_misplaced_attributes(&mut self, attributes: &[Attribute], label: &str) {
+		for attribute in attributes {
+			self.emitter.error()
+				.with_label(label)
+				.with_span(attribute.name.span())
+				.emit();
+			self.has_error = true;
+		}
+	}
+
+	/// Skips a `{ .. }` block that isn't part of any declaration, reporting it once.
+	fn skip_toplevel_block(&mut self, open: Token) {
+		let mut depth = 0;
+		let mut span = open.span.clone();
+		while let Some(token) = self.next() {
+			span.end = token.span.end;
+			match token.kind {
+				TokenType::LBrace => depth += 1,
+				TokenType::RBrace => {
+					depth -= 1;
+					if depth == 0 {
+						break;
+					}
+				}
+				_ => {}
+			}
+		}
+
+		self.emitter.error()
+			.with_label("unexpected block at top level")
+			.with_span(span)
+			.with_help("blocks are only allowed in function bodies, e.g. `main = () { .. }`")
+			.emit();
+		self.has_error = true;
+	}
+
+	/// Parses a top-level declaration, skipping a token if nothing was consumed so that
+	/// parsing always moves forward.
+	fn parse_toplevel_decl_with_progress(&mut self) -> Expr {
+		let before = self.peek_one().map(|token| token.span.start);
+		let expr = self.parse_toplevel_decl();
+		if before.is_some() && self.peek_one().map(|token| token.span.start) == before {
+			self.next();
+		}
+		expr
 	}
 
 	fn parse_toplevel_decl(&mut self) -> Expr {
-		self.parse_expression()
+		let attributes = self.parse_attributes();
+		let extern_token = match self.peek_one() {
+			Some(token) if token.kind == TokenType::Extern => self.next(),
+			_ => None
+		};
+
+		let start = match self.peek_one() {
+			Some(token) => token,
+			None => {
+				if let Some(extern_token) = extern_token {
+					self.emitter.error()
+						.with_label("expected a function declaration after `extern`")
+						.with_span(extern_token.span)
+						.emit();
+					self.has_error = true;
+				}
+				else if let Some(attribute) = attributes.last() {
+					self.emitter.error()
+						.with_label("expected a declaration after the attribute")
+						.with_span(attribute.name.span())
+						.emit();
+					self.has_error = true;
+				}
+				return Expr::Error;
+			}
+		};
+		if start.kind == TokenType::RBrace || start.kind == TokenType::RParen {
+			self.skip_unmatched(start);
+			return Expr::Error;
+		}
+		if start.kind == TokenType::LBrace {
+			self.skip_toplevel_block(start);
+			return Expr::Error;
+		}
+
+		let had_error = self.has_error;
+		self.has_error = false;
+		let mut expr = if start.kind == TokenType::Import {
+			self.next();
+			self.parse_import(start.clone())
+		}
+		else {
+			self.parse_expression()
+		};
+		let failed = self.has_error;
+		self.has_error |= had_error;
+
+		match &mut expr {
+			Expr::Struct {attributes: attrs, ..} | Expr::Function {attributes: attrs, ..} => *attrs = attributes,
+			Expr::Error => {}
+			_ => self.report_misplaced_attributes(&attributes,
+				"attributes can only be applied to struct and function declarations")
+		}
+
+		if let Some(extern_token) = extern_token {
+			match &mut expr {
+				Expr::Function {name, body, is_extern, ..} => {
+					*is_extern = true;
+					if body.is_some() {
+						self.emitter.error()
+							.with_label(format!("extern function `{}` can't have a body", name.0))
+							.with_span(name.1.clone())
+							.with_secondary(extern_token.span, "declared extern here")
+							.emit();
+						self.has_error = true;
+					}
+				}
+				Expr::Error => {}
+				_ => {
+					self.emitter.error()
+						.with_label("`extern` can only be applied to function declarations")
+						.with_span(extern_token.span)
+						.emit();
+					self.has_error = true;
+				}
+			}
+		}
+
+		let is_declaration = matches!(expr, Expr::Struct {..} | Expr::Function {..}
+			| Expr::VarDecl {..} | Expr::MultiVarDecl {..} | Expr::Assign {..} | Expr::Import {..} | Expr::Const {..}
+			| Expr::Error);
+		if !self.script && !is_declaration && !failed {
+			self.emitter.error()
+				.with_label("expected a declaration, top-level statements are only allowed in script mode")
+				.with_span(start.span)
+				.emit();
+			self.has_error = true;
+		}
+
+		expr
+	}
+
+	pub fn has_error(&self) -> bool {
+		self.has_error
 	}
 
 	pub fn parse(&mut self) -> Vec<Expr> {
 		let mut ast = Vec::new();
+		let mut script = Vec::new();
 		while !self.has_eof() {
-			ast.push(self.parse_toplevel_decl());
+			let expr = self.parse_toplevel_decl_with_progress();
+			if self.script && !matches!(expr, Expr::Struct {..} | Expr::Function {..} | Expr::Import {..}) {
+				script.push(expr);
+			}
+			else {
+				ast.push(expr);
+			}
+		}
+
+		if !script.is_empty() {
+			ast.push(Expr::Script {body: script});
 		}
 
 		ast
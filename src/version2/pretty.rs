@@ -0,0 +1,485 @@
+use std::fmt::{Display, Formatter};
+use crate::ast::{ArrayLen, Attribute, Expr, Field, Type};
+use crate::lexer::BinOp;
+use crate::parser::binop_info;
+
+/// Binds looser than everything but a statement, so it is parenthesized in any operand.
+const ASSIGN_PREC: u32 = 1;
+const RANGE_PREC: u32 = 2;
+const CAST_PREC: u32 = 30;
+const UNARY_PREC: u32 = 40;
+/// Names, literals, calls and other expressions that never need parentheses.
+const ATOM_PREC: u32 = 50;
+
+impl Display for Type {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Type::Named((name, _)) => write!(f, "{}", name),
+			Type::Optional(inner) => write!(f, "{}?", inner),
+			Type::Tuple((types, _)) if types.len() == 1 => write!(f, "({},)", types[0]),
+			Type::Tuple((types, _)) => write!(f, "({})", join(types.iter().map(Type::to_string))),
+			Type::Array(element, ArrayLen::Num((num, _)), _) => write!(f, "[{}; {}]", element, num),
+			Type::Array(element, ArrayLen::Const((name, _)), _) => write!(f, "[{}; {}]", element, name)
+		}
+	}
+}
+
+/// Renders the expression as source-like text, like `pretty(0)`.
+impl Display for Expr {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.pretty(0))
+	}
+}
+
+impl Expr {
+	/// Renders the expression as source-like text. Nested blocks are indented with one
+	/// tab more than `indent`, and operators are parenthesized only where precedence
+	/// requires it, so the output parses back to the same tree.
+	pub fn pretty(&self, indent: usize) -> String {
+		let mut out = String::new();
+		write_expr(&mut out, self, indent);
+		out
+	}
+}
+
+/// Renders every top-level declaration of `ast` on its own lines.
+pub fn ast_to_source(ast: &[Expr]) -> String {
+	let mut out = String::new();
+	for expr in ast {
+		write_statement(&mut out, expr, 0);
+	}
+	out
+}
+
+fn join(items: impl Iterator<Item = String>) -> String {
+	items.collect::<Vec<_>>().join(", ")
+}
+
+fn push_indent(out: &mut String, depth: usize) {
+	for _ in 0..depth {
+		out.push('\t');
+	}
+}
+
+/// The operator and operands of a binary expression.
+fn binary_parts(expr: &Expr) -> Option<(BinOp, &Expr, &Expr)> {
+	let (op, lhs, rhs) = match expr {
+		Expr::Add(lhs, rhs) => (BinOp::Add, lhs, rhs),
+		Expr::Sub(lhs, rhs) => (BinOp::Minus, lhs, rhs),
+		Expr::Mul(lhs, rhs) => (BinOp::Multiply, lhs, rhs),
+		Expr::Div(lhs, rhs) => (BinOp::Divide, lhs, rhs),
+		Expr::Mod(lhs, rhs) => (BinOp::Modulo, lhs, rhs),
+		Expr::And(lhs, rhs) => (BinOp::And, lhs, rhs),
+		Expr::Or(lhs, rhs) => (BinOp::Or, lhs, rhs),
+		Expr::BitAnd(lhs, rhs) => (BinOp::BitAnd, lhs, rhs),
+		Expr::BitOr(lhs, rhs) => (BinOp::BitOr, lhs, rhs),
+		Expr::Shl(lhs, rhs) => (BinOp::ShiftLeft, lhs, rhs),
+		Expr::Shr(lhs, rhs) => (BinOp::ShiftRight, lhs, rhs),
+		Expr::Eq(lhs, rhs) => (BinOp::Equal, lhs, rhs),
+		Expr::Ne(lhs, rhs) => (BinOp::NotEqual, lhs, rhs),
+		Expr::Lt(lhs, rhs) => (BinOp::Less, lhs, rhs),
+		Expr::Gt(lhs, rhs) => (BinOp::Greater, lhs, rhs),
+		Expr::Le(lhs, rhs) => (BinOp::LessEqual, lhs, rhs),
+		Expr::Ge(lhs, rhs) => (BinOp::GreaterEqual, lhs, rhs),
+		_ => return None
+	};
+	Some((op, lhs, rhs))
+}
+
+fn binop_text(op: &BinOp) -> &'static str {
+	match op {
+		BinOp::Add => "+",
+		BinOp::Minus => "-",
+		BinOp::Multiply => "*",
+		BinOp::Divide => "/",
+		BinOp::Modulo => "%",
+		BinOp::And => "&&",
+		BinOp::Or => "||",
+		BinOp::BitAnd => "&",
+		BinOp::BitOr => "|",
+		BinOp::ShiftLeft => "<<",
+		BinOp::ShiftRight => ">>",
+		BinOp::Not => "!",
+		BinOp::Equal => "==",
+		BinOp::NotEqual => "!=",
+		BinOp::Less => "<",
+		BinOp::Greater => ">",
+		BinOp::LessEqual => "<=",
+		BinOp::GreaterEqual => ">="
+	}
+}
+
+/// How tightly the expression binds; an operand that binds looser than its context
+/// needs parentheses.
+fn precedence(expr: &Expr) -> u32 {
+	if let Some((op, _, _)) = binary_parts(expr) {
+		return binop_info(&op).map_or(ATOM_PREC, |(prec, _)| prec);
+	}
+	match expr {
+		Expr::Assign {..} => ASSIGN_PREC,
+		Expr::Range {..} | Expr::If {..} | Expr::Block {..} => RANGE_PREC,
+		Expr::Cast {..} => CAST_PREC,
+		Expr::Neg(..) | Expr::Pos(..) | Expr::Not(..) => UNARY_PREC,
+		Expr::Struct {..} | Expr::Function {..} | Expr::VarDecl {..} | Expr::MultiVarDecl {..} | Expr::Ret {..}
+		| Expr::Assert {..} | Expr::Break {..} | Expr::For {..} | Expr::Tail {..} | Expr::Script {..}
+		| Expr::Import {..} | Expr::Const {..} => 0,
+		_ => ATOM_PREC
+	}
+}
+
+fn write_operand(out: &mut String, expr: &Expr, depth: usize, min_prec: u32) {
+	if precedence(expr) < min_prec {
+		out.push('(');
+		write_expr(out, expr, depth);
+		out.push(')');
+	}
+	else {
+		write_expr(out, expr, depth);
+	}
+}
+
+/// Writes a value in a position that takes a full expression, like a call argument.
+fn write_value(out: &mut String, expr: &Expr, depth: usize) {
+	write_operand(out, expr, depth, RANGE_PREC);
+}
+
+fn write_values<'a>(out: &mut String, exprs: impl IntoIterator<Item = &'a Expr>, depth: usize) {
+	for (i, expr) in exprs.into_iter().enumerate() {
+		if i > 0 {
+			out.push_str(", ");
+		}
+		write_value(out, expr, depth);
+	}
+}
+
+fn write_quoted(out: &mut String, text: &str, quote: char) {
+	out.push(quote);
+	for char in text.chars() {
+		match char {
+			'\n' => out.push_str("\\n"),
+			'\t' => out.push_str("\\t"),
+			'\\' => out.push_str("\\\\"),
+			char if char.is_ascii_control() => out.push_str(&format!("\\x{:02X}", char as u32)),
+			char => out.push(char)
+		}
+	}
+	out.push(quote);
+}
+
+/// Writes `expr` as a line of a block, with the `;` that ends it unless it ends in
+/// a block of its own.
+fn write_statement(out: &mut String, expr: &Expr, depth: usize) {
+	if let Expr::Script {body} = expr {
+		body.iter().for_each(|expr| write_statement(out, expr, depth));
+		return;
+	}
+
+	let attributes = match expr {
+		Expr::Struct {attributes, ..} | Expr::Function {attributes, ..} => &attributes[..],
+		_ => &[]
+	};
+	write_attributes(out, attributes, depth);
+	push_indent(out, depth);
+	write_expr(out, expr, depth);
+	let has_block = match expr {
+		Expr::Struct {..} | Expr::Block {..} | Expr::If {..} | Expr::For {..} | Expr::Tail {..} => true,
+		Expr::Function {body, ..} => body.is_some(),
+		_ => false
+	};
+	if !has_block {
+		out.push(';');
+	}
+	out.push('\n');
+}
+
+fn write_attributes(out: &mut String, attributes: &[Attribute], depth: usize) {
+	for attribute in attributes {
+		push_indent(out, depth);
+		out.push_str("#[");
+		out.push_str(&attribute.name.0);
+		if !attribute.args.is_empty() {
+			out.push('(');
+			write_values(out, &attribute.args, depth);
+			out.push(')');
+		}
+		out.push_str("]\n");
+	}
+}
+
+/// Writes `{`, the statements of `body` one level deeper than `depth`, and `}`.
+fn write_block(out: &mut String, body: &[Expr], depth: usize) {
+	if body.is_empty() {
+		out.push_str("{}");
+		return;
+	}
+	out.push_str("{\n");
+	for expr in body {
+		write_statement(out, expr, depth + 1);
+	}
+	push_indent(out, depth);
+	out.push('}');
+}
+
+fn write_field(out: &mut String, field: &Field, depth: usize) {
+	if let Some(doc) = &field.doc {
+		for line in doc.lines() {
+			push_indent(out, depth);
+			out.push_str("/// ");
+			out.push_str(line);
+			out.push('\n');
+		}
+	}
+	push_indent(out, depth);
+	out.push_str(&format!("{}: {}", field.name.0, field.r#type));
+	if let Some((width, _)) = &field.width {
+		out.push_str(&format!(" : {}", width));
+	}
+	if let Some(default) = &field.default {
+		out.push_str(" = ");
+		write_value(out, default, depth);
+	}
+}
+
+fn write_if(out: &mut String, cond: &Expr, then_body: &[Expr], else_body: &Option<Vec<Expr>>, depth: usize) {
+	out.push_str("if ");
+	write_value(out, cond, depth);
+	out.push(' ');
+	write_block(out, then_body, depth);
+	match else_body.as_deref() {
+		Some([Expr::If {cond, then_body, else_body, ..}]) => {
+			out.push_str(" else ");
+			write_if(out, cond, then_body, else_body, depth);
+		}
+		Some(else_body) => {
+			out.push_str(" else ");
+			write_block(out, else_body, depth);
+		}
+		None => {}
+	}
+}
+
+fn write_expr(out: &mut String, expr: &Expr, depth: usize) {
+	if let Some((op, lhs, rhs)) = binary_parts(expr) {
+		// Every binary operator is left associative.
+		let prec = precedence(expr);
+		write_operand(out, lhs, depth, prec);
+		out.push_str(&format!(" {} ", binop_text(&op)));
+		write_operand(out, rhs, depth, prec + 1);
+		return;
+	}
+
+	match expr {
+		Expr::Error => out.push_str("<error>"),
+		Expr::Var((name, _)) => out.push_str(name),
+		Expr::Num((value, _)) => out.push_str(&value.to_string()),
+		Expr::FloatLiteral((value, _)) => out.push_str(&format!("{:?}", value.0)),
+		Expr::CharLiteral((value, _)) => write_quoted(out, value, '\''),
+		Expr::StringLiteral((value, _)) => write_quoted(out, value, '"'),
+		Expr::Neg(expr, _) => {
+			out.push('-');
+			write_operand(out, expr, depth, UNARY_PREC);
+		}
+		Expr::Pos(expr, _) => {
+			out.push('+');
+			write_operand(out, expr, depth, UNARY_PREC);
+		}
+		Expr::Not(expr, _) => {
+			out.push('!');
+			write_operand(out, expr, depth, UNARY_PREC);
+		}
+		Expr::Range {start, end, inclusive, ..} => {
+			if let Some(start) = start {
+				write_operand(out, start, depth, RANGE_PREC + 1);
+			}
+			out.push_str(if *inclusive { "..=" } else { ".." });
+			if let Some(end) = end {
+				write_operand(out, end, depth, RANGE_PREC + 1);
+			}
+		}
+		Expr::Cast {expr, ty} => {
+			write_operand(out, expr, depth, CAST_PREC);
+			out.push_str(&format!(" as {}", ty));
+		}
+		Expr::SizeOf(ty, _) => out.push_str(&format!("sizeof({})", ty)),
+		Expr::AlignOf(ty, _) => out.push_str(&format!("alignof({})", ty)),
+		Expr::Assign {target, value, ..} => {
+			write_operand(out, target, depth, ATOM_PREC);
+			out.push_str(" = ");
+			write_operand(out, value, depth, ASSIGN_PREC);
+		}
+		Expr::Struct {name, base, fields, ..} => {
+			out.push_str(&format!("{} = struct ", name.0));
+			if let Some(base) = base {
+				out.push_str(&format!(": {} ", base));
+			}
+			if fields.is_empty() {
+				out.push_str("{}");
+				return;
+			}
+			out.push_str("{\n");
+			for (i, field) in fields.iter().enumerate() {
+				write_field(out, field, depth + 1);
+				out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+			}
+			push_indent(out, depth);
+			out.push('}');
+		}
+		Expr::Function {name, type_params, constraints, args, ret_type, body, is_extern, ..} => {
+			if *is_extern {
+				out.push_str("extern ");
+			}
+			out.push_str(&name.0);
+			if !type_params.is_empty() {
+				out.push_str(&format!("[{}]", join(type_params.iter().map(|(param, _)| param.clone()))));
+			}
+			out.push_str(&format!(" = ({})", join(args.iter().map(|((name, _), ty)| format!("{}: {}", name, ty)))));
+			if let Some(ret_type) = ret_type {
+				out.push_str(&format!(" -> {}", ret_type));
+			}
+			if !constraints.is_empty() {
+				out.push_str(" where ");
+				out.push_str(&join(constraints.iter().map(|((param, _), (bound, _))| format!("{}: {}", param, bound))));
+			}
+			if let Some(body) = body {
+				out.push(' ');
+				write_block(out, body, depth);
+			}
+		}
+		Expr::VarDecl {name, r#type, value} => {
+			out.push_str(&format!("{}: {}", name.0, r#type));
+			if let Some(value) = value {
+				out.push_str(" = ");
+				write_value(out, value, depth);
+			}
+		}
+		Expr::MultiVarDecl {names, r#type, value} => {
+			out.push_str(&format!("{}: {}", join(names.iter().map(|(name, _)| name.clone())), r#type));
+			if let Some(value) = value {
+				out.push_str(" = ");
+				write_value(out, value, depth);
+			}
+		}
+		Expr::Construct {name, fields, ..} => {
+			out.push_str(&name.0);
+			out.push_str(" {");
+			for (i, ((field, _), value)) in fields.iter().enumerate() {
+				out.push_str(if i == 0 { "." } else { ", ." });
+				out.push_str(field);
+				out.push_str(" = ");
+				write_value(out, value, depth);
+			}
+			out.push('}');
+		}
+		Expr::PositionalConstruct {name, values, ..} => {
+			out.push_str(&name.0);
+			out.push_str(" {");
+			write_values(out, values, depth);
+			out.push('}');
+		}
+		Expr::Tuple(values, _) => {
+			out.push('(');
+			write_values(out, values, depth);
+			if values.len() == 1 {
+				out.push(',');
+			}
+			out.push(')');
+		}
+		Expr::Array(values, _) => {
+			out.push('[');
+			write_values(out, values, depth);
+			out.push(']');
+		}
+		Expr::FieldAccess {base, field} => {
+			write_operand(out, base, depth, ATOM_PREC);
+			out.push('.');
+			out.push_str(&field.0);
+		}
+		Expr::Index {base, index, ..} => {
+			write_operand(out, base, depth, ATOM_PREC);
+			out.push('[');
+			write_value(out, index, depth);
+			out.push(']');
+		}
+		Expr::Generic {name, type_args, ..} => {
+			out.push_str(&format!("{}[{}]", name.0, join(type_args.iter().map(Type::to_string))));
+		}
+		Expr::Call {callee, type_args, args, named_args, ..} => {
+			write_operand(out, callee, depth, ATOM_PREC);
+			if !type_args.is_empty() {
+				out.push_str(&format!("[{}]", join(type_args.iter().map(Type::to_string))));
+			}
+			out.push('(');
+			write_values(out, args, depth);
+			for (i, ((arg_name, _), value)) in named_args.iter().enumerate() {
+				if i > 0 || !args.is_empty() {
+					out.push_str(", ");
+				}
+				out.push('.');
+				out.push_str(arg_name);
+				out.push_str(" = ");
+				write_value(out, value, depth);
+			}
+			out.push(')');
+		}
+		Expr::Ret {value, ..} => {
+			out.push_str("ret");
+			if let Some(value) = value {
+				out.push(' ');
+				write_value(out, value, depth);
+			}
+		}
+		Expr::If {cond, then_body, else_body, ..} => write_if(out, cond, then_body, else_body, depth),
+		Expr::For {init, cond, step, body, ..} => {
+			out.push_str("for ");
+			if let Some(init) = init {
+				write_expr(out, init, depth);
+			}
+			out.push(';');
+			if let Some(cond) = cond {
+				out.push(' ');
+				write_value(out, cond, depth);
+			}
+			out.push(';');
+			if let Some(step) = step {
+				out.push(' ');
+				write_expr(out, step, depth);
+			}
+			out.push(' ');
+			write_block(out, body, depth);
+		}
+		Expr::Assert {cond, message, ..} => {
+			out.push_str("assert ");
+			write_value(out, cond, depth);
+			if let Some(message) = message {
+				out.push_str(", ");
+				write_value(out, message, depth);
+			}
+		}
+		Expr::Tail {value} => write_value(out, value, depth),
+		Expr::Block {label, body, ..} => {
+			out.push_str(&format!("'{}: ", label.0));
+			write_block(out, body, depth);
+		}
+		Expr::Break {label, value, ..} => {
+			out.push_str(&format!("break '{}", label.0));
+			if let Some(value) = value {
+				out.push(' ');
+				write_value(out, value, depth);
+			}
+		}
+		Expr::Script {body} => body.iter().for_each(|expr| write_statement(out, expr, depth)),
+		Expr::Import {path, alias, ..} => {
+			out.push_str("import ");
+			write_quoted(out, &path.0, '"');
+			if let Some((alias, _)) = alias {
+				out.push_str(" as ");
+				out.push_str(alias);
+			}
+		}
+		Expr::Const {name, value, ..} => {
+			out.push_str(&format!("const {} = ", name.0));
+			write_value(out, value, depth);
+		}
+		_ => unreachable!("binary expressions are written above")
+	}
+}
@@ -1,6 +1,10 @@
+use crate::ast::{self, spanned, Expr, Float, IgnoreSpans, SpannedExt, Type};
 use crate::diagnostics;
-use crate::lexer::{Lexer, SourceMap};
-use crate::parser::Parser;
+use crate::diagnostics::{DiagnosticEmitter, EmitFormat, EmitType};
+use crate::lexer::{BinOp, ConditionalSection, Lexer, PeekCount, SourceMap, TokenKind, TokenType, Utf8Reader};
+use crate::lint::Linter;
+use crate::parser::{self, binop_info, Associativity, Parser};
+use crate::pretty::ast_to_source;
 
 #[cfg(test)]
 macro_rules! test {
@@ -17,6 +21,48 @@ macro_rules! test {
     }};
 }
 #[cfg(test)]
+macro_rules! parse {
+    ($src:expr) => {{
+	    let mut output = String::new();
+
+		let map = SourceMap::new("test", $src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let lexer = Lexer::new($src, &emitter);
+		let mut parser = Parser::new(lexer, &emitter);
+		let ast = parser.parse();
+
+	    (ast, output)
+    }};
+}
+#[cfg(test)]
+macro_rules! lint {
+    (tokens $src:expr, |$linter:ident| $configure:expr) => {{
+	    let src = $src;
+	    let mut output = String::new();
+
+		let map = SourceMap::new("test", src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let tokens = Lexer::new(src, &emitter).tokenize_all();
+		let $linter = Linter::new(&map, &emitter);
+		$configure.check_tokens(&tokens);
+		drop(emitter);
+
+	    output
+    }};
+    ($src:expr, |$linter:ident| $configure:expr) => {{
+	    let src = $src;
+	    let (ast, mut output) = parse!(src);
+
+		let map = SourceMap::new("test", src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let $linter = Linter::new(&map, &emitter);
+		$configure.check_ast(&ast);
+		drop(emitter);
+
+	    output
+    }};
+}
+#[cfg(test)]
 macro_rules! word_count {
     ($haystack:expr, $word:expr, $count:expr) => {{
 	    if $haystack.matches($word).count() != $count {
@@ -68,6 +114,18 @@ fn test_struct_invalid_name() {
 	test_error!(r"a = struct {10: 10}", 1);
 }
 
+#[test]
+fn test_decl_name_error_points_at_equals() {
+	let diagnostics = parser::parse("test", "a.b = struct {}").diagnostics;
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].message, "expected an identifier before `=`");
+	assert_eq!(diagnostics[0].span, 4..5);
+
+	let diagnostics = parser::parse("test", "a =").diagnostics;
+	assert_eq!(diagnostics[0].message, "expected an expression");
+	assert_eq!(diagnostics[0].secondary, [(2..3, "after this `=`".to_string())]);
+}
+
 #[test]
 fn test_function_missing_lparen() {
 	test_error!(r"a = ) {}", 1);
@@ -76,4 +134,2992 @@ fn test_function_missing_lparen() {
 #[test]
 fn test_function_missing_rparen() {
 	test_error!(r"a = ( {}", 1);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn assign_value(expr: &Expr) -> &Expr {
+	match expr {
+		Expr::Assign {value, ..} => value,
+		e => panic!("expected an assignment but got {:?}", e)
+	}
+}
+
+#[cfg(test)]
+fn type_name(ty: &Type) -> &str {
+	match ty {
+		Type::Named((name, _)) => name,
+		ty => panic!("expected a named type but got {:?}", ty)
+	}
+}
+
+#[test]
+fn test_cast() {
+	let (ast, output) = parse!(r"a = x as int;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Cast {expr, ty} => {
+			assert!(matches!(**expr, Expr::Var(_)));
+			assert_eq!(type_name(ty), "int");
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_cast_chained() {
+	let (ast, output) = parse!(r"a = x as int as u8;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Cast {expr, ty} => {
+			assert_eq!(type_name(ty), "u8");
+			match &**expr {
+				Expr::Cast {expr, ty} => {
+					assert!(matches!(**expr, Expr::Var(_)));
+					assert_eq!(type_name(ty), "int");
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_cast_binds_tighter_than_binop() {
+	let (ast, output) = parse!(r"a = -1 as int + b;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Add(lhs, _) => match &**lhs {
+			Expr::Cast {expr, ..} => assert!(matches!(**expr, Expr::Neg(..))),
+			e => panic!("{:?}", e)
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_cast_missing_type() {
+	test_error!(r"a = x as;", 1);
+}
+
+#[test]
+fn test_json_diagnostic_position() {
+	let src = "é = 1 + ;";
+	let mut output = String::new();
+
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output).with_format(EmitFormat::Json);
+	let lexer = Lexer::new(src, &emitter);
+	let mut parser = Parser::new(lexer, &emitter);
+	let _ = parser.parse();
+
+	error_count!(output, 1);
+	assert!(output.starts_with("{\"severity\":\"error\""), "{}", output);
+	// 'é' takes two bytes but a single column
+	assert!(output.contains("\"line\":1,\"column\":8,\"span\":[8,9]"), "{}", output);
+}
+
+#[test]
+fn test_uncolored_diagnostics() {
+	let src = "a = 1 + ;";
+	let mut colored = String::new();
+	let mut plain = String::new();
+
+	let map = SourceMap::new("test", src);
+	for (output, use_color) in [(&mut colored, true), (&mut plain, false)] {
+		let emitter = diagnostics::with_string(&map, output).with_color(use_color);
+		emitter.error().with_label("unexpected `;`").with_span(8..9).with_help("remove it").emit();
+	}
+
+	assert!(colored.contains(diagnostics::color::RED), "{}", colored);
+	assert!(!plain.contains('\x1b'), "{}", plain);
+	assert!(plain.starts_with("error: unexpected `;`\n  --> test:1:9\n"), "{}", plain);
+	assert!(plain.contains("  = help: remove it"), "{}", plain);
+}
+
+#[cfg(test)]
+macro_rules! parse_relaxed {
+    ($src:expr) => {{
+	    let mut output = String::new();
+
+		let map = SourceMap::new("test", $src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let lexer = Lexer::new($src, &emitter);
+		let mut parser = Parser::new(lexer, &emitter).with_relaxed(true);
+		let ast = parser.parse();
+
+	    (ast, output)
+    }};
+}
+
+#[test]
+fn test_relaxed_newline_terminators() {
+	let (ast, output) = parse_relaxed!("a = 1\nb = a + 2\nc: int\nd: int = 3;\ne = 4");
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 5);
+	assert!(matches!(ast[1], Expr::Assign {..}));
+	assert!(matches!(ast[2], Expr::VarDecl {value: None, ..}));
+	assert!(matches!(ast[3], Expr::VarDecl {value: Some(_), ..}));
+}
+
+#[test]
+fn test_relaxed_function_body() {
+	let (ast, output) = parse_relaxed!("f = () -> i32 {\n\ta = 1\n\tret a\n}\n");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => assert_eq!(body.len(), 2),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_relaxed_missing_terminator() {
+	let (ast, output) = parse_relaxed!("a = 1 b = 2");
+	error_count!(output, 1);
+	assert_eq!(ast.len(), 2);
+}
+
+#[test]
+fn test_newline_is_not_a_terminator_by_default() {
+	test_error!("a = 1\nb = 2;", 1);
+}
+
+#[test]
+fn test_struct_field_missing_type() {
+	let output = test!(r"a = struct { x: }");
+	error_count!(output, 1);
+	word_count!(output, "missing type for field `x`", 1);
+}
+
+#[test]
+fn test_struct_field_missing_type_at_eof() {
+	let output = test!(r"a = struct { x:");
+	error_count!(output, 1);
+	word_count!(output, "missing type for field `x`", 1);
+}
+
+#[cfg(test)]
+fn assert_assigns_b(ast: &[Expr]) {
+	match ast.last() {
+		Some(Expr::Assign {target, ..}) => match &**target {
+			Expr::Var((name, _)) => assert_eq!(name, "b"),
+			e => panic!("{:?}", e)
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_recover_to_statement() {
+	let sources = [
+		r"a: ; b = 1;",
+		r"a: int 5 6; b = 1;",
+		r"a = 1 2 3; b = 1;",
+		r"a = ) { x = 1; } b = 1;",
+		r"a = struct {10: 10} b = 1;",
+		r"a = struct 5 b = 1;",
+		r"f = (x) { x = 1; } b = 1;",
+		r"f = () -> { x = 1; } b = 1;"
+	];
+	for src in sources {
+		let (ast, output) = parse!(src);
+		error_count!(output, 1);
+		assert_assigns_b(&ast);
+	}
+}
+
+#[test]
+fn test_recover_to_statement_in_body() {
+	let (ast, output) = parse!(r"f = () { a: ; b = ) 1; c = 2; } b = 1;");
+	error_count!(output, 2);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => assert_eq!(body.len(), 3),
+		e => panic!("{:?}", e)
+	}
+	assert_assigns_b(&ast);
+}
+
+#[test]
+fn test_recover_to_statement_relaxed() {
+	let (ast, output) = parse_relaxed!("a = 1 2 3\nb = 1");
+	error_count!(output, 1);
+	assert_assigns_b(&ast);
+}
+
+#[test]
+fn test_optional_type() {
+	let (ast, output) = parse!(r"x: int? = y;");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::VarDecl {r#type: Type::Optional(inner), ..} => assert_eq!(type_name(inner), "int"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_optional_type_in_struct_and_function() {
+	let (ast, output) = parse!(r"a = struct { x: int? } f = (x: a?) -> int? {}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {fields, ..} => assert!(matches!(fields[0].r#type, Type::Optional(_))),
+		e => panic!("{:?}", e)
+	}
+	match &ast[1] {
+		Expr::Function {args, ret_type, ..} => {
+			assert!(matches!(args[0].1, Type::Optional(_)));
+			assert!(matches!(ret_type, Some(Type::Optional(_))));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_nested_optional_type() {
+	let (ast, output) = parse!(r"x: int??;");
+	error_count!(output, 1);
+	word_count!(output, "nested optional", 1);
+	match &ast[0] {
+		Expr::VarDecl {r#type: Type::Optional(inner), ..} => assert_eq!(type_name(inner), "int"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_unmatched_closing_delimiters() {
+	let output = test!(r"}}}");
+	error_count!(output, 1);
+	word_count!(output, "3 unmatched '}'", 1);
+}
+
+#[test]
+fn test_unmatched_closing_delimiter_runs() {
+	let (ast, output) = parse!(r"a = 1; }) b = 1;");
+	error_count!(output, 2);
+	word_count!(output, "unmatched '}'", 1);
+	word_count!(output, "unmatched ')'", 1);
+	assert_assigns_b(&ast);
+}
+
+#[test]
+fn test_call() {
+	let (ast, output) = parse!(r"a = foo(1, b);");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Call {callee, type_args, args, named_args, ..} => {
+			assert!(matches!(&**callee, Expr::Var((name, _)) if name == "foo"), "{:?}", callee);
+			assert!(type_args.is_empty());
+			assert_eq!(args.len(), 2);
+			assert!(named_args.is_empty());
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_call_postfix_expression() {
+	let (ast, output) = parse!("f = () { a.b(1); f(1)(2); a[i](x); a[0](x); (g)(); }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	match &body[0] {
+		Expr::Call {callee, args, span, ..} => {
+			assert!(matches!(&**callee, Expr::FieldAccess {field, ..} if field.0 == "b"), "{:?}", callee);
+			assert_eq!(args.len(), 1);
+			assert_eq!(*span, 9..15);
+		}
+		e => panic!("{:?}", e)
+	}
+	match &body[1] {
+		Expr::Call {callee, args, ..} => {
+			assert!(matches!(&**callee, Expr::Call {args, ..} if matches!(args[..], [Expr::Num((1, _))])));
+			assert!(matches!(args[..], [Expr::Num((2, _))]), "{:?}", args);
+		}
+		e => panic!("{:?}", e)
+	}
+	// `i` isn't a builtin type, so `a[i](x)` calls `a[i]` rather than passing a type argument
+	match &body[2] {
+		Expr::Call {callee, type_args, args, ..} => {
+			assert!(type_args.is_empty());
+			assert!(matches!(&**callee, Expr::Index {..}), "{:?}", callee);
+			assert_eq!(args.len(), 1);
+		}
+		e => panic!("{:?}", e)
+	}
+	match &body[3] {
+		Expr::Call {callee, type_args, ..} => {
+			assert!(type_args.is_empty());
+			assert!(matches!(&**callee, Expr::Index {..}), "{:?}", callee);
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(&body[4], Expr::Call {callee, ..} if matches!(**callee, Expr::Var(_))), "{:?}", body[4]);
+	assert_eq!(ast_to_source(&ast), "f = () {\n\ta.b(1);\n\tf(1)(2);\n\ta[i](x);\n\ta[0](x);\n\tg();\n}\n");
+}
+
+#[test]
+fn test_call_indexed_by_name() {
+	let (ast, output) = parse!("f = () { handlers[i](event); g[int](x); }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	assert!(matches!(&body[0], Expr::Call {callee, type_args, ..}
+		if type_args.is_empty() && matches!(&**callee, Expr::Index {index, ..} if matches!(&**index, Expr::Var((name, _)) if name == "i"))),
+		"{:?}", body[0]);
+	assert!(matches!(&body[1], Expr::Call {callee, type_args, ..}
+		if matches!(**callee, Expr::Var(_)) && matches!(&type_args[..], [Type::Named((name, _))] if name == "int")),
+		"{:?}", body[1]);
+}
+
+#[test]
+fn test_call_trailing_comma() {
+	let (ast, output) = parse!(r"a = foo(1, b,); c = bar();");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Call {args, ..} if args.len() == 2));
+	assert!(matches!(assign_value(&ast[1]), Expr::Call {args, ..} if args.is_empty()));
+}
+
+#[test]
+fn test_call_unclosed() {
+	for src in ["a = foo(1,", "a = foo("] {
+		let output = test!(src);
+		word_count!(output, "unclosed `(` in call to `foo`", 1);
+		word_count!(output, "opened here", 1);
+	}
+	let output = test!("a = b.c[0](1,");
+	word_count!(output, "unclosed `(` in call to `b.c[0]`", 1);
+	test_error!("a = foo(1; b = 2;", 1);
+
+	for (src, message, span) in [("a = foo(1", "expected ',' or ')' but found eof", 9..9),
+		("a = foo(1; b = 2;", "expected ',' or ')' but got ';'", 9..10),
+		("f = () { foo(1 }", "expected ',' or ')' but got '}'", 15..16)] {
+		let diagnostics = parser::parse("test", src).diagnostics;
+		assert_eq!(diagnostics[0].message, message, "{}", src);
+		assert_eq!(diagnostics[0].span, span);
+		assert_eq!(diagnostics[0].secondary[0].1, "opened here");
+	}
+}
+
+#[test]
+fn test_unclosed_brace_points_at_opener() {
+	for (src, message, open) in [("Foo = struct { a: int", "expected ',' or '}' but found eof", 13..14),
+		("x = Foo {.a = 1", "expected ',' or '}' but found eof", 8..9),
+		("x = Foo {.a = 1, .b = 2,", "expected '}' but found eof", 8..9),
+		("a = foo(1", "expected ',' or ')' but found eof", 7..8)] {
+		let diagnostics = parser::parse("test", src).diagnostics;
+		assert_eq!(diagnostics.len(), 1, "{}: {:?}", src, diagnostics);
+		assert_eq!(diagnostics[0].message, message, "{}", src);
+		assert_eq!(diagnostics[0].secondary[0], (open, "opened here".to_string()), "{}", src);
+	}
+}
+
+#[test]
+fn test_tuple_literals() {
+	let (ast, output) = parse!(r"a = (1, 2); b = (1); c = (1,); d = (x + 1, y,);");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Tuple(values, _) if values.len() == 2));
+	assert!(matches!(assign_value(&ast[1]), Expr::Num((1, _))));
+	assert!(matches!(assign_value(&ast[2]), Expr::Tuple(values, _) if matches!(values[..], [Expr::Num((1, _))])));
+	match assign_value(&ast[3]) {
+		Expr::Tuple(values, _) => {
+			assert_eq!(values.len(), 2);
+			assert!(matches!(values[0], Expr::Add(..)));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_tuple_types() {
+	let (ast, output) = parse!(r"a: (int, u8?) = (1, 2); b: (int) = 1; c: (int,); f = (p: (int, int)) -> (int, (int,)) {}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::VarDecl {r#type: Type::Tuple((types, span)), ..} => {
+			assert_eq!(*span, 3..13);
+			assert_eq!(type_name(&types[0]), "int");
+			assert!(matches!(types[1], Type::Optional(_)));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(&ast[1], Expr::VarDecl {r#type: Type::Named(_), ..}));
+	assert!(matches!(&ast[2], Expr::VarDecl {r#type: Type::Tuple((types, _)), ..} if types.len() == 1));
+	match &ast[3] {
+		Expr::Function {args, ret_type: Some(Type::Tuple((types, _))), ..} => {
+			assert!(matches!(&args[0].1, Type::Tuple((types, _)) if types.len() == 2));
+			assert!(matches!(&types[1], Type::Tuple((inner, _)) if inner.len() == 1));
+		}
+		e => panic!("{:?}", e)
+	}
+	test_error!(r"a: (int int) = 1;", 1);
+}
+
+#[test]
+fn test_call_type_args() {
+	let (ast, output) = parse!(r"a = foo[int](1);");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Call {type_args, args, ..} => {
+			assert_eq!(type_args.len(), 1);
+			assert_eq!(type_name(&type_args[0]), "int");
+			assert_eq!(args.len(), 1);
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_call_multiple_type_args() {
+	let (ast, output) = parse!(r"a = foo[int, Bar](1);");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Call {type_args, ..} => {
+			assert_eq!(type_args.len(), 2);
+			assert_eq!(type_name(&type_args[1]), "Bar");
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_call_named_args() {
+	let (ast, output) = parse!(r"a = foo(1, .x = 2, .y = b);");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Call {args, named_args, ..} => {
+			assert_eq!(args.len(), 1);
+			let names: Vec<_> = named_args.iter().map(|(name, _)| name.0.as_str()).collect();
+			assert_eq!(names, ["x", "y"]);
+			assert!(matches!(named_args[0].1, Expr::Num((2, _))));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_call_positional_after_named() {
+	let output = test!(r"a = foo(.x = 1, 2); b = 3;");
+	error_count!(output, 1);
+	word_count!(output, "positional arguments must come before named arguments", 1);
+}
+
+#[test]
+fn test_call_empty_type_args() {
+	test_error!(r"a = foo[](1);", 1);
+}
+
+#[cfg(test)]
+fn binop_lexeme(op: &BinOp) -> &'static str {
+	match op {
+		BinOp::Add => "+",
+		BinOp::Minus => "-",
+		BinOp::Multiply => "*",
+		BinOp::Divide => "/",
+		BinOp::Modulo => "%",
+		BinOp::And => "&&",
+		BinOp::Or => "||",
+		BinOp::BitAnd => "&",
+		BinOp::BitOr => "|",
+		BinOp::ShiftLeft => "<<",
+		BinOp::ShiftRight => ">>",
+		BinOp::Not => "!",
+		BinOp::Equal => "==",
+		BinOp::NotEqual => "!=",
+		BinOp::Less => "<",
+		BinOp::Greater => ">",
+		BinOp::LessEqual => "<=",
+		BinOp::GreaterEqual => ">="
+	}
+}
+
+/// Checks that slicing the source with a token's span gives back its lexeme.
+#[cfg(test)]
+fn assert_lexeme(kind: &TokenType, slice: &str) {
+	let expected = match kind {
+		TokenType::Struct => "struct".to_string(),
+		TokenType::Ret => "ret".to_string(),
+		TokenType::As => "as".to_string(),
+		TokenType::Break => "break".to_string(),
+		TokenType::Extern => "extern".to_string(),
+		TokenType::Assert => "assert".to_string(),
+		TokenType::If => "if".to_string(),
+		TokenType::Else => "else".to_string(),
+		TokenType::For => "for".to_string(),
+		TokenType::Import => "import".to_string(),
+		TokenType::Const => "const".to_string(),
+		TokenType::LBrace => "{".to_string(),
+		TokenType::RBrace => "}".to_string(),
+		TokenType::LParen => "(".to_string(),
+		TokenType::RParen => ")".to_string(),
+		TokenType::LBracket => "[".to_string(),
+		TokenType::RBracket => "]".to_string(),
+		TokenType::Colon => ":".to_string(),
+		TokenType::Semicolon => ";".to_string(),
+		TokenType::Dot => ".".to_string(),
+		TokenType::DotDot => "..".to_string(),
+		TokenType::DotDotEquals => "..=".to_string(),
+		TokenType::Comma => ",".to_string(),
+		TokenType::Arrow => "->".to_string(),
+		TokenType::Question => "?".to_string(),
+		TokenType::Hash => "#".to_string(),
+		TokenType::Newline => "\n".to_string(),
+		TokenType::DocComment(_) => {
+			assert!(slice.starts_with("///") && !slice.contains('\n'), "{:?} has the span of {:?}", kind, slice);
+			return;
+		}
+		TokenType::BinOp(op) => binop_lexeme(op).to_string(),
+		TokenType::Equals => "=".to_string(),
+		TokenType::BinOpEquals(op) => binop_lexeme(op).to_string() + "=",
+		TokenType::Identifier(ident) => ident.clone(),
+		TokenType::Label(label) => format!("'{}", label),
+		TokenType::Custom(_) => {
+			assert!(!slice.is_empty(), "{:?} has an empty span", kind);
+			return;
+		}
+		TokenType::Num(num) => num.to_string(),
+		TokenType::Eof => String::new(),
+		TokenType::Float(value) => {
+			assert_eq!(slice.replace('_', "").parse::<f64>().ok(), Some(*value), "{:?} has the span of {:?}", kind, slice);
+			return;
+		}
+		TokenType::CharLiteral(_) | TokenType::StringLiteral(_) => {
+			let quote = if let TokenType::CharLiteral(_) = kind { '\'' } else { '"' };
+			assert!(slice.len() >= 2 && slice.starts_with(quote) && slice.ends_with(quote),
+			        "{:?} has the span of {:?}", kind, slice);
+			return;
+		}
+	};
+	assert_eq!(slice, expected, "{:?} has the span of {:?}", kind, slice);
+}
+
+#[test]
+fn test_token_spans_round_trip() {
+	let sources = [
+		include_str!("../../tests/test.lang"),
+		include_str!("../../tests/test2.lang"),
+		include_str!("../../tests/test3.lang"),
+		"a = \"str\" + 'c'; b = \"esc\\n\\t\\\\\" c",
+		"é = \"ü\"; f = (x: int?) -> int { ret x as int; }",
+		"a -= 1; b += c; d = foo[int](1, 2);",
+		"a = 0..10; b = ..=c.d; e = f..;",
+		"#[inline] #[align(8, x)] f = () {}",
+		"/// doc\na = struct {\n\t/// é\n\tx: int\n}"
+	];
+	for src in sources {
+		let mut output = String::new();
+		let map = SourceMap::new("test", src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let mut lexer = Lexer::new(src, &emitter);
+		while let Some(token) = lexer.next() {
+			assert_lexeme(&token.kind, &src[token.span.clone()]);
+		}
+	}
+}
+
+#[test]
+fn test_struct_field_doc_comment() {
+	let (ast, output) = parse!("a = struct {\n\t/// The x coordinate\n\t/// in pixels\n\tx: int,\n\ty: int\n}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {fields, ..} => {
+			assert_eq!(fields[0].doc.as_deref(), Some("The x coordinate\nin pixels"));
+			assert_eq!(fields[1].doc, None);
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_doc_comment_does_not_leak() {
+	let (ast, output) = parse!("/// Not a field doc\na = struct {\n\tx: int\n}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {fields, ..} => assert_eq!(fields[0].doc, None),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_doc_comment_on_first_argument() {
+	let (ast, output) = parse!("f = (/// the x\n x: i32, y: i32) { }");
+	error_count!(output, 0);
+	assert!(matches!(&ast[0], Expr::Function {args, ..} if args.len() == 2), "{:?}", ast[0]);
+
+	let (ast, output) = parse_relaxed!("f = (\n\tx: i32\n) {\n}\n");
+	error_count!(output, 0);
+	assert!(matches!(&ast[0], Expr::Function {args, ..} if args.len() == 1), "{:?}", ast[0]);
+}
+
+#[test]
+fn test_hex_escape() {
+	let (ast, output) = parse!(r#"a = "\x41"; b = '\x7a';"#);
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::StringLiteral((text, _)) => assert_eq!(text, "A"),
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[1]) {
+		Expr::CharLiteral((text, _)) => assert_eq!(text, "z"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_hex_escape_one_digit() {
+	test_error!(r#"a = "\x1";"#, 1);
+}
+
+#[test]
+fn test_hex_escape_not_hex() {
+	test_error!(r#"a = "\xG0";"#, 1);
+}
+
+#[test]
+fn test_hex_escape_out_of_range() {
+	test_error!(r#"a = "\x80";"#, 1);
+}
+
+#[test]
+fn test_octal_escape() {
+	let (ast, output) = parse!(r#"a = "\0"; b = '\101'; c = "\0121\18";"#);
+	error_count!(output, 0);
+	let texts: Vec<_> = ast.iter().map(|expr| match assign_value(expr) {
+		Expr::StringLiteral((text, _)) | Expr::CharLiteral((text, _)) => text.as_str(),
+		e => panic!("{:?}", e)
+	}).collect();
+	assert_eq!(texts, ["\0", "A", "\n1\u{1}8"]);
+}
+
+#[test]
+fn test_octal_escape_out_of_range() {
+	let output = test!(r#"a = "\777";"#);
+	error_count!(output, 1);
+	word_count!(output, r"octal escape \777 is out of range", 1);
+}
+
+#[test]
+fn test_script_mode() {
+	let src = "a = 1;\nprint(a);\nf = () {}\nb: int = f();\na + b;";
+	let mut output = String::new();
+
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let lexer = Lexer::new(src, &emitter);
+	let mut parser = Parser::new(lexer, &emitter).with_script(true);
+	let ast = parser.parse();
+
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 2);
+	assert!(matches!(ast[0], Expr::Function {..}));
+	match &ast[1] {
+		Expr::Script {body} => {
+			assert_eq!(body.len(), 4);
+			assert!(matches!(body[0], Expr::Assign {..}));
+			assert!(matches!(body[1], Expr::Call {..}));
+			assert!(matches!(body[2], Expr::VarDecl {..}));
+			assert!(matches!(body[3], Expr::Add(..)));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_module_mode_rejects_statements() {
+	let (ast, output) = parse!(r"a = 1; print(a); b = 2;");
+	error_count!(output, 1);
+	word_count!(output, "expected a declaration", 1);
+	assert_eq!(ast.len(), 3);
+}
+
+#[test]
+fn test_expression_statements_in_body() {
+	test_error!(r"f = () { print(1); a + b; }", 0);
+}
+
+#[test]
+fn test_source_map_from_file() {
+	let path = std::env::temp_dir().join(format!("lang-source-map-{}.lang", std::process::id()));
+	std::fs::write(&path, "a = 1;\nb = é;\n").unwrap();
+	let path = path.to_str().unwrap();
+
+	let map = SourceMap::from_file(path).unwrap();
+	std::fs::remove_file(path).unwrap();
+
+	assert_eq!(map.file(), path);
+	assert_eq!(map.source(), "a = 1;\nb = é;\n");
+	let loc = map.span_to_loc(11..13);
+	assert_eq!((loc.line, loc.column), (2, 5));
+
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let lexer = Lexer::new(map.source(), &emitter);
+	let mut parser = Parser::new(lexer, &emitter);
+	assert_eq!(parser.parse().len(), 2);
+	error_count!(output, 0);
+}
+
+#[test]
+fn test_source_map_from_missing_file() {
+	assert!(SourceMap::from_file("does/not/exist.lang").is_err());
+}
+
+#[test]
+fn test_bitfield() {
+	let (ast, output) = parse!(r"a = struct { flags: u8 : 3, b: u32 }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {fields, ..} => {
+			assert_eq!(fields[0].width.as_ref().map(|(width, _)| *width), Some(3));
+			assert!(fields[1].width.is_none());
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_bitfield_zero_width() {
+	test_error!(r"a = struct { flags: u8 : 0 }", 1);
+}
+
+#[test]
+fn test_bitfield_too_wide() {
+	test_error!(r"a = struct { flags: u8 : 9 }", 1);
+}
+
+#[test]
+fn test_bitfield_too_wide_echoes_radix() {
+	let output = test!(r"a = struct { flags: u8 : 0x10, mode: u8 : 9 }");
+	error_count!(output, 2);
+	word_count!(output, "bit width 0x10 exceeds the 8 bits of the field type", 1);
+	word_count!(output, "bit width 9 exceeds the 8 bits of the field type", 1);
+}
+
+#[test]
+fn test_bitfield_missing_width() {
+	test_error!(r"a = struct { flags: u8 : }", 1);
+}
+
+#[test]
+fn test_lex_from_bytes() {
+	let src = "é = \"ü\"; f = (x: int?) -> int { ret x as int; }\n/// doc\na = struct { x: int }";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+
+	let mut from_str = Lexer::new(src, &emitter);
+	let mut from_bytes = Lexer::from_input(Utf8Reader::new(src.as_bytes()), &emitter);
+	loop {
+		let expected = from_str.next().map(|token| (token.kind, token.span));
+		let token = from_bytes.next().map(|token| (token.kind, token.span));
+		assert_eq!(token, expected);
+		if token.is_none() {
+			break;
+		}
+	}
+	error_count!(output, 0);
+}
+
+#[test]
+fn test_lex_invalid_utf8() {
+	let src = b"a = 1; b = \xFF;";
+	let mut output = String::new();
+	let map = SourceMap::new("test", "a = 1; b = ?;");
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let lexer = Lexer::from_input(Utf8Reader::new(&src[..]), &emitter);
+	let mut parser = Parser::new(lexer, &emitter);
+	parser.parse();
+	word_count!(output, "invalid UTF-8 at byte 11", 1);
+}
+
+#[test]
+fn test_function_missing_arrow() {
+	let (ast, output) = parse!(r"f = (x: int) int {}");
+	error_count!(output, 1);
+	word_count!(output, "help:", 1);
+	word_count!(output, "add '->' to declare the return type", 1);
+	match &ast[0] {
+		Expr::Function {ret_type: Some(ty), body: Some(_), ..} => assert_eq!(type_name(ty), "int"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_struct_base() {
+	let (ast, output) = parse!(r"Foo = struct : Base { x: int }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {base: Some(base), fields, ..} => {
+			assert_eq!(type_name(base), "Base");
+			assert_eq!(fields.len(), 1);
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_struct_without_base() {
+	let (ast, output) = parse!(r"Foo = struct { x: int }");
+	error_count!(output, 0);
+	assert!(matches!(ast[0], Expr::Struct {base: None, ..}));
+}
+
+#[test]
+fn test_struct_base_missing_type() {
+	let (ast, output) = parse!(r"Foo = struct : { x: int }");
+	error_count!(output, 1);
+	word_count!(output, "expected a base type", 1);
+	assert!(matches!(ast[0], Expr::Struct {base: None, ..}));
+}
+
+#[test]
+fn test_arrow_without_return_type() {
+	let output = test!(r"f = () -> {}");
+	error_count!(output, 1);
+	word_count!(output, "expected a type after '->'", 1);
+	word_count!(output, "test:1:8", 1);
+	word_count!(output, "return type arrow here", 1);
+}
+
+#[test]
+fn test_lint_operator_spacing() {
+	let output = lint!(tokens "a = b+c;", |linter| linter.with_operator_spacing(true));
+	word_count!(output, "missing spaces around `+`", 1);
+	assert!(lint!(tokens "a = b + c; d = -e * -1;", |linter| linter.with_operator_spacing(true)).is_empty());
+}
+
+#[test]
+fn test_lint_operator_spacing_off_by_default() {
+	assert!(lint!(tokens "a=b+c;", |linter| linter).is_empty());
+}
+
+#[test]
+fn test_multi_var_decl() {
+	let (ast, output) = parse!(r"a, b: int; c, d, e: u8 = 0;");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::MultiVarDecl {names, r#type, value: None} => {
+			assert_eq!(names.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+			assert_eq!(type_name(r#type), "int");
+		}
+		e => panic!("{:?}", e)
+	}
+	match &ast[1] {
+		Expr::MultiVarDecl {names, value: Some(value), ..} => {
+			assert_eq!(names.len(), 3);
+			assert!(matches!(**value, Expr::Num((0, _))));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_multi_var_decl_missing_type() {
+	test_error!(r"a, b = 1; c: int;", 1);
+}
+
+#[test]
+fn test_named_construct() {
+	let (ast, output) = parse!(r"f = () { a = Foo {.x = 1, .y = 2}; }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => match assign_value(&body[0]) {
+			Expr::Construct {fields, ..} => {
+				assert_eq!(fields.iter().map(|(name, _)| name.0.as_str()).collect::<Vec<_>>(), ["x", "y"]);
+			}
+			e => panic!("{:?}", e)
+		},
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_positional_construct() {
+	let (ast, output) = parse!(r"f = () { a = Foo {1, 2 + 3}; }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => match assign_value(&body[0]) {
+			Expr::PositionalConstruct {name, values, ..} => {
+				assert_eq!(name.0, "Foo");
+				assert_eq!(values.len(), 2);
+				assert!(matches!(values[1], Expr::Add(..)));
+			}
+			e => panic!("{:?}", e)
+		},
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_mixed_construct() {
+	let output = test!(r"f = () { a = Foo {1, .y = 2}; b = Foo {.x = 1, 2}; }");
+	error_count!(output, 2);
+	word_count!(output, "cannot mix named and positional", 2);
+}
+
+#[test]
+fn test_unexpected_token_lexeme() {
+	let output = test!(r"a = ];");
+	error_count!(output, 1);
+	word_count!(output, "unexpected token `]`", 1);
+
+	let output = test!(r"a = += b;");
+	word_count!(output, "unexpected token `+=`", 1);
+
+	let output = test!(r"a = 1 * ;");
+	word_count!(output, "expected a primary expression after `*`", 1);
+}
+
+#[cfg(test)]
+fn range_bounds(expr: &Expr) -> (Option<&Expr>, Option<&Expr>, bool) {
+	match expr {
+		Expr::Range {start, end, inclusive, ..} => (start.as_deref(), end.as_deref(), *inclusive),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_ranges() {
+	let (ast, output) = parse!(r"f = () { a = 0..10; b = 0..=10; c = 1..; d = ..n; e = ..=n; g = ..; }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	let forms: Vec<_> = body.iter().map(|expr| {
+		let (start, end, inclusive) = range_bounds(assign_value(expr));
+		(start.is_some(), end.is_some(), inclusive)
+	}).collect();
+	assert_eq!(forms, [(true, true, false), (true, true, true), (true, false, false),
+		(false, true, false), (false, true, true), (false, false, false)]);
+}
+
+#[test]
+fn test_range_with_field_access() {
+	let (ast, output) = parse!(r"f = () { r = a.b..c.d; }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => {
+			let (start, end, inclusive) = range_bounds(assign_value(&body[0]));
+			assert!(matches!(start, Some(Expr::FieldAccess {..})));
+			assert!(matches!(end, Some(Expr::FieldAccess {..})));
+			assert!(!inclusive);
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_inclusive_range_without_end() {
+	test_error!(r"f = () { r = 1..=; }", 1);
+}
+
+#[test]
+fn test_struct_missing_lbrace_near_eof() {
+	test_error!(r"a = struct ;", 1);
+	test_error!(r"a = struct 1", 1);
+	test_error!(r"a = struct ; b = 1;", 1);
+}
+
+#[test]
+fn test_recovery_identifier_at_eof() {
+	let (ast, output) = parse!(r"a = ) b");
+	error_count!(output, 1);
+	assert_eq!(ast.len(), 1);
+}
+
+#[test]
+fn test_parse_result() {
+	let result = parser::parse("test", "a = 1;\nb = ];\nc = 2;");
+	assert!(result.had_error);
+	assert_eq!(result.ast.len(), 3);
+	assert_eq!(result.diagnostics.len(), 1);
+
+	let diagnostic = &result.diagnostics[0];
+	assert_eq!(diagnostic.severity, EmitType::Error);
+	assert_eq!(diagnostic.message, "unexpected token `]`, expected a primary expression");
+	assert_eq!(diagnostic.span, 11..12);
+
+	let result = parser::parse("test", "a = 1;");
+	assert!(!result.had_error);
+	assert!(result.diagnostics.is_empty());
+}
+
+#[test]
+fn test_function_attributes() {
+	let (ast, output) = parse!(r"#[inline] #[align(8)] f = () {}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {attributes, ..} => {
+			assert_eq!(attributes.len(), 2);
+			assert_eq!(attributes[0].name.0, "inline");
+			assert!(attributes[0].args.is_empty());
+			assert_eq!(attributes[1].name.0, "align");
+			assert!(matches!(attributes[1].args[..], [Expr::Num((8, _))]));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_attribute_at_eof() {
+	test_error!(r"f = () {} #[", 1);
+}
+
+#[test]
+fn test_misplaced_attributes() {
+	let output = test!(r"#[inline] a = 1; f = () { #[inline] b = 2; }");
+	error_count!(output, 2);
+	word_count!(output, "attributes can only be applied", 1);
+	word_count!(output, "attributes are only allowed on top-level declarations", 1);
+}
+
+#[test]
+fn test_literal_hook() {
+	let src = "a = #FF00FF; b = #zz;";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let mut lexer = Lexer::new(src, &emitter).with_literal_hook('#', |text| {
+		u32::from_str_radix(text, 16).ok().map(|color| TokenType::Custom(format!("color {:06x}", color)))
+	});
+
+	let tokens: Vec<_> = std::iter::from_fn(|| lexer.next()).collect();
+	assert_eq!(tokens[2].kind, TokenType::Custom("color ff00ff".to_string()));
+	assert_eq!(tokens[2].span, 4..11);
+	assert_eq!(tokens[6].kind, TokenType::Hash);
+	assert_eq!(tokens[7].kind, TokenType::Identifier("zz".to_string()));
+}
+
+#[test]
+fn test_lint_cast_truncation() {
+	let output = lint!(r"f = (x: u64) -> u8 { y: u32 = x as u16; ret y as u8; }", |linter| linter.with_cast_truncation(true));
+	error_count!(output, 0);
+	word_count!(output, "casting `u64` to `u16` may truncate", 1);
+	word_count!(output, "casting `u32` to `u8` may truncate", 1);
+}
+
+#[test]
+fn test_lint_cast_widening() {
+	let output = lint!(r"f = (x: u8) -> u64 { y: i32 = x as i32; ret (y as u64) as i64; }", |linter| linter.with_cast_truncation(true));
+	assert!(output.is_empty(), "{}", output);
+}
+
+/// A parsed JSON value, to check the structure of `--ast-json` output.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+enum JsonValue {
+	Null,
+	Bool(bool),
+	Num(f64),
+	Str(String),
+	Array(Vec<JsonValue>),
+	Object(Vec<(String, JsonValue)>)
+}
+
+#[cfg(test)]
+impl std::ops::Index<&str> for JsonValue {
+	type Output = JsonValue;
+
+	fn index(&self, key: &str) -> &JsonValue {
+		match self {
+			JsonValue::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+				.unwrap_or_else(|| panic!("no key `{}` in {:?}", key, self)),
+			_ => panic!("expected an object but got {:?}", self)
+		}
+	}
+}
+
+#[cfg(test)]
+impl std::ops::Index<usize> for JsonValue {
+	type Output = JsonValue;
+
+	fn index(&self, index: usize) -> &JsonValue {
+		match self {
+			JsonValue::Array(values) => &values[index],
+			_ => panic!("expected an array but got {:?}", self)
+		}
+	}
+}
+
+/// Parses a complete JSON document, panicking on malformed input.
+#[cfg(test)]
+fn parse_json(src: &str) -> JsonValue {
+	fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+		while chars.next_if(|char| char.is_whitespace()).is_some() {}
+	}
+
+	fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+		assert_eq!(chars.next(), Some('"'));
+		let mut text = String::new();
+		loop {
+			match chars.next().expect("unterminated string") {
+				'"' => return text,
+				'\\' => match chars.next().expect("unterminated escape") {
+					'n' => text.push('\n'),
+					't' => text.push('\t'),
+					'r' => text.push('\r'),
+					'u' => {
+						let hex: String = chars.by_ref().take(4).collect();
+						text.push(char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+					}
+					char => text.push(char)
+				},
+				char => text.push(char)
+			}
+		}
+	}
+
+	fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> JsonValue {
+		skip_whitespace(chars);
+		let value = match *chars.peek().expect("expected a value") {
+			'"' => JsonValue::Str(parse_string(chars)),
+			'[' => {
+				chars.next();
+				let mut values = Vec::new();
+				skip_whitespace(chars);
+				if chars.next_if_eq(&']').is_none() {
+					loop {
+						values.push(parse_value(chars));
+						match chars.next() {
+							Some(',') => {}
+							Some(']') => break,
+							char => panic!("expected ',' or ']' but got {:?}", char)
+						}
+					}
+				}
+				JsonValue::Array(values)
+			}
+			'{' => {
+				chars.next();
+				let mut entries = Vec::new();
+				skip_whitespace(chars);
+				if chars.next_if_eq(&'}').is_none() {
+					loop {
+						skip_whitespace(chars);
+						let key = parse_string(chars);
+						skip_whitespace(chars);
+						assert_eq!(chars.next(), Some(':'));
+						entries.push((key, parse_value(chars)));
+						match chars.next() {
+							Some(',') => {}
+							Some('}') => break,
+							char => panic!("expected ',' or '}}' but got {:?}", char)
+						}
+					}
+				}
+				JsonValue::Object(entries)
+			}
+			_ => {
+				let mut word = String::new();
+				while let Some(char) = chars.next_if(|char| char.is_alphanumeric() || "+-.".contains(*char)) {
+					word.push(char);
+				}
+				match word.as_str() {
+					"null" => JsonValue::Null,
+					"true" => JsonValue::Bool(true),
+					"false" => JsonValue::Bool(false),
+					number => JsonValue::Num(number.parse().unwrap_or_else(|_| panic!("invalid JSON value `{}`", number)))
+				}
+			}
+		};
+		skip_whitespace(chars);
+		value
+	}
+
+	let mut chars = src.chars().peekable();
+	let value = parse_value(&mut chars);
+	assert_eq!(chars.next(), None, "trailing input after the JSON value");
+	value
+}
+
+#[test]
+fn test_tokens_flag() {
+	let path = std::env::temp_dir().join(format!("lang-tokens-{}.lang", std::process::id()));
+	std::fs::write(&path, "a = \"s\";").unwrap();
+	let args = ["--tokens".to_string(), path.to_str().unwrap().to_string()];
+
+	let mut out = Vec::new();
+	let code = crate::run(&args, &mut out);
+	std::fs::remove_file(&path).unwrap();
+
+	assert_eq!(code, 0);
+	assert_eq!(String::from_utf8(out).unwrap(),
+		"Identifier 0..1\nEquals 2..3\nStringLiteral 4..7\nSemicolon 7..8\n");
+}
+
+#[test]
+fn test_ast_json_flag() {
+	let path = std::env::temp_dir().join(format!("lang-ast-json-{}.lang", std::process::id()));
+	std::fs::write(&path, "Point = struct { x: int }\nf = (p: Point) -> int { ret p.x; }\n").unwrap();
+	let args = ["--ast-json".to_string(), path.to_str().unwrap().to_string()];
+
+	let mut out = Vec::new();
+	let code = crate::run(&args, &mut out);
+	std::fs::remove_file(&path).unwrap();
+
+	assert_eq!(code, 0);
+	let json = parse_json(&String::from_utf8(out).unwrap());
+	let name = |value: &str| JsonValue::Str(value.to_string());
+	let span = |start: f64, end: f64| JsonValue::Array(vec![JsonValue::Num(start), JsonValue::Num(end)]);
+	assert!(matches!(&json, JsonValue::Array(decls) if decls.len() == 2), "{:?}", json);
+
+	let point = &json[0];
+	assert_eq!(point["node"], name("Struct"));
+	assert_eq!(point["name"]["value"], name("Point"));
+	assert_eq!(point["name"]["span"], span(0.0, 5.0));
+	assert_eq!(point["base"], JsonValue::Null);
+	assert_eq!(point["fields"][0]["name"]["value"], name("x"));
+	assert_eq!(point["fields"][0]["type"]["node"], name("Named"));
+	assert_eq!(point["fields"][0]["type"]["name"]["value"], name("int"));
+
+	let function = &json[1];
+	assert_eq!(function["node"], name("Function"));
+	assert_eq!(function["args"][0]["name"]["value"], name("p"));
+	assert_eq!(function["args"][0]["type"]["name"]["value"], name("Point"));
+	assert_eq!(function["ret_type"]["name"]["value"], name("int"));
+	assert_eq!(function["is_extern"], JsonValue::Bool(false));
+	let ret = &function["body"][0];
+	assert_eq!(ret["node"], name("Ret"));
+	assert_eq!(ret["span"], span(50.0, 57.0));
+	assert_eq!(ret["value"]["node"], name("FieldAccess"));
+	assert_eq!(ret["value"]["base"]["name"]["value"], name("p"));
+	assert_eq!(ret["value"]["field"]["value"], name("x"));
+}
+
+#[test]
+fn test_unknown_flag() {
+	assert_eq!(crate::run(&["--bogus".to_string()], &mut Vec::new()), 2);
+}
+
+#[test]
+fn test_block_comment() {
+	let (ast, output) = parse!("a = /* one\n two */ 1; /**/ b = 2;");
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 2);
+	match assign_value(&ast[1]) {
+		Expr::Num((2, span)) => assert_eq!(*span, 31..32),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_comment_open_in_string() {
+	let (ast, output) = parse!(r#"a = "/*"; b = 1;"#);
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 2);
+	match assign_value(&ast[0]) {
+		Expr::StringLiteral((text, _)) => assert_eq!(text, "/*"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_quotes_in_block_comment() {
+	// Comments don't contain string literals, so the first `*/` closes them
+	let (ast, output) = parse!(r#"a = 1; /* "*/ b = 2; /* don't " */ c = 3;"#);
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 3);
+
+	// The exact input from the request: the comment ends at the first `*/`, so the
+	// `" */` after it is an unterminated string
+	let diagnostics = parser::parse("test", r#"/* "*/" */"#).diagnostics;
+	let messages: Vec<_> = diagnostics.iter().map(|diagnostic| (diagnostic.message.as_str(), diagnostic.span.clone())).collect();
+	assert_eq!(messages, [("unterminated string literal ' */'", 6..10), ("expected ';' but found eof", 10..10)]);
+
+	let (ast, output) = parse!(r#"/* 5" screen */ s = "abc";"#);
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::StringLiteral((text, _)) => assert_eq!(text, "abc"),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_unterminated_block_comment() {
+	test_error!("a = 1; /* b = 2;", 1);
+	test_error!("a = 1; /* /* */ b = 2;", 1);
+}
+
+#[test]
+fn test_nested_block_comment() {
+	let (ast, output) = parse!("a = 1; /* x /* y */ z */ b = 2;");
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 2);
+	match assign_value(&ast[1]) {
+		Expr::Num((2, span)) => assert_eq!(*span, 29..30),
+		e => panic!("{:?}", e)
+	}
+
+	let (ast, output) = parse!("a = 1; /* x /* 5\" */ z */ b = 2; // \"\nc = 3;");
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 3);
+}
+
+#[test]
+fn test_line_comment() {
+	let (ast, output) = parse!("a = 1 // one / two
+// b = 3;
+;c = 2; //");
+	error_count!(output, 0);
+	assert_eq!(ast.len(), 2);
+	match assign_value(&ast[1]) {
+		Expr::Num((2, span)) => assert_eq!(*span, 34..35),
+		e => panic!("{:?}", e)
+	}
+}
+
+/// The number of carets in the first underline of `output`.
+#[cfg(test)]
+fn caret_count(output: &str) -> usize {
+	let line = output.lines().find(|line| line.contains('^')).expect("no underline");
+	line.matches('^').count()
+}
+
+#[test]
+fn test_carets_single_char() {
+	let output = test!(r"a = ];");
+	assert_eq!(caret_count(&output), 1);
+}
+
+#[test]
+fn test_carets_identifier_after_tab_and_multibyte() {
+	let output = test!("f = (é: int)\tinteger {}");
+	assert_eq!(caret_count(&output), "integer".len());
+	let column = |line: &str, pattern: &str| {
+		let line = line.replace(diagnostics::color::RED, "").replace(diagnostics::color::CYAN, "")
+			.replace(diagnostics::color::RESET, "");
+		line[..line.find(pattern).unwrap()].chars().count()
+	};
+	let underline = output.lines().find(|line| line.contains('^')).unwrap();
+	let source = output.lines().find(|line| line.contains("integer {}")).unwrap();
+	assert_eq!(column(underline, "^"), column(source, "integer {}"));
+}
+
+#[test]
+fn test_carets_eoi() {
+	let output = test!("a = struct");
+	assert_eq!(caret_count(&output), 1);
+}
+
+#[test]
+fn test_assignment_value() {
+	let (ast, output) = parse!(r"f = () { b = (a = 1) + 2; c.x = (d.y = b); }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	match assign_value(&body[0]) {
+		Expr::Add(lhs, rhs) => {
+			match &**lhs {
+				Expr::Assign {target, value, ..} => {
+					assert!(matches!(**target, Expr::Var((ref name, _)) if name == "a"));
+					assert!(matches!(**value, Expr::Num((1, _))));
+				}
+				e => panic!("{:?}", e)
+			}
+			assert!(matches!(**rhs, Expr::Num((2, _))));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(assign_value(&body[1]), Expr::Assign {..}));
+}
+
+#[test]
+fn test_assignment_value_invalid_target() {
+	test_error!(r"f = () { b = (1 = 2); }", 1);
+}
+
+#[test]
+fn test_parenthesized_value_is_not_a_function() {
+	let (ast, output) = parse!(r"f = () { a = (1 + 2) * 3; }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => assert!(matches!(assign_value(&body[0]), Expr::Mul(..))),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_assign_classifies_struct() {
+	let (ast, output) = parse!(r"a = struct {}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Struct {name, fields, ..} => {
+			assert_eq!(name.value(), "a");
+			assert!(fields.is_empty());
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_assign_classifies_function() {
+	let (ast, output) = parse!(r"a = (){}");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Function {name, args, body: Some(body), ..} => {
+			assert_eq!(name.value(), "a");
+			assert!(args.is_empty());
+			assert!(body.is_empty());
+		}
+		e => panic!("{:?}", e)
+	}
+
+	let (ast, output) = parse!(r"a = (x: i32, y: i32) {}");
+	error_count!(output, 0);
+	assert!(matches!(&ast[0], Expr::Function {args, ..} if args.len() == 2));
+}
+
+#[test]
+fn test_assign_classifies_value() {
+	let (ast, output) = parse!(r"a = b;");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Var(name) if name.value() == "b"));
+
+	let (ast, output) = parse!(r"a = (b);");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Var(name) if name.value() == "b"));
+}
+
+#[test]
+fn test_peek_three() {
+	let src = "a = (b";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let mut lexer = Lexer::new(src, &emitter);
+	assert!(matches!(lexer.peek(PeekCount::Three).map(|token| token.kind), Some(TokenType::LParen)));
+	assert!(matches!(lexer.peek(PeekCount::One).map(|token| token.kind), Some(TokenType::Identifier(_))));
+	assert!(matches!(lexer.next().map(|token| token.kind), Some(TokenType::Identifier(_))));
+	assert!(matches!(lexer.peek(PeekCount::Three).map(|token| token.kind), Some(TokenType::Identifier(_))));
+	assert!(matches!(lexer.next().map(|token| token.kind), Some(TokenType::Equals)));
+	assert!(matches!(lexer.next().map(|token| token.kind), Some(TokenType::LParen)));
+	assert!(matches!(lexer.next().map(|token| token.kind), Some(TokenType::Identifier(_))));
+	assert!(lexer.next().is_none());
+}
+
+#[test]
+fn test_tokenize_all() {
+	let src = "a = 1; /* trailing */\n";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let tokens = Lexer::new(src, &emitter).tokenize_all();
+
+	assert_eq!(tokens.len(), 5);
+	let eof = tokens.last().unwrap();
+	assert_eq!(eof.kind, TokenType::Eof);
+	assert_eq!(eof.span, src.len()..src.len());
+	assert_eq!(eof.span, map.eoi_span());
+	for token in &tokens {
+		assert_lexeme(&token.kind, &src[token.span.clone()]);
+	}
+}
+
+#[test]
+fn test_misplaced_arrow() {
+	let output = test!(r"f = () { x = a -> b; a -> b; y = 1; }");
+	error_count!(output, 2);
+	word_count!(output, "`->` is only valid in a function return type", 2);
+}
+
+#[test]
+fn test_toplevel_block() {
+	let (ast, output) = parse!(r"{ a = 1; { b = 2; } } c = 3;");
+	error_count!(output, 1);
+	word_count!(output, "unexpected block at top level", 1);
+	word_count!(output, "help:", 1);
+	assert_eq!(ast.len(), 2);
+	assert!(matches!(ast[1], Expr::Assign {..}));
+}
+
+#[test]
+fn test_float_literals() {
+	let cases: &[(&str, Option<f64>)] = &[
+		("1_000.000_5e-1_0", Some(1000.0005e-10)),
+		("1.5e3", Some(1.5e3)),
+		("2e9", Some(2e9)),
+		("1E+2", Some(1e2)),
+		("0.25", Some(0.25)),
+		("1_.5", None),
+		("1.5_e3", None),
+		("1._5", None),
+		("1e_5", None),
+		("1e5_", None),
+		("1__0.5", None),
+		("1e999", None),
+		("1_7.9e30_8", None)
+	];
+	for (src, expected) in cases {
+		let mut output = String::new();
+		let map = SourceMap::new("test", src);
+		let emitter = diagnostics::with_string(&map, &mut output);
+		let mut lexer = Lexer::new(src, &emitter);
+		let tokens = lexer.tokenize_all();
+		let has_error = lexer.has_error();
+		drop(lexer);
+		drop(emitter);
+
+		assert_eq!(tokens.len(), 2, "{} lexed as {:?}", src, tokens);
+		match expected {
+			Some(value) => {
+				assert_eq!(tokens[0].kind, TokenType::Float(*value), "{}", src);
+				assert_lexeme(&tokens[0].kind, &src[tokens[0].span.clone()]);
+				assert!(output.is_empty(), "{}: {}", src, output);
+			}
+			None => {
+				assert!(has_error, "{} should not lex", src);
+				error_count!(output, 1);
+			}
+		}
+	}
+
+	let output = test!("a = 1e999; b = 1.7e308;");
+	error_count!(output, 1);
+	word_count!(output, "float literal `1e999` is too large for an f64", 1);
+}
+
+#[test]
+fn test_float_before_range() {
+	let src = "1..2";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let kinds: Vec<_> = Lexer::new(src, &emitter).tokenize_all().into_iter().map(|token| token.kind).collect();
+	assert_eq!(kinds, [TokenType::Num(1), TokenType::DotDot, TokenType::Num(2), TokenType::Eof]);
+}
+
+#[test]
+fn test_empty_input() {
+	for src in ["", "   \n\t\n"] {
+		let result = parser::parse("test", src);
+		assert!(result.ast.is_empty(), "{:?}", result.ast);
+		assert!(result.diagnostics.is_empty());
+		assert!(!result.had_error);
+
+		let map = SourceMap::new("test", src);
+		assert_eq!(map.eoi_span(), src.len()..src.len());
+		let loc = map.span_to_loc(map.eoi_span());
+		assert!(loc.line >= 1 && loc.column >= 1);
+	}
+}
+
+#[test]
+fn test_conditional_section() {
+	let src = "#if debug\na = 1;\n#endif\nb = 2;";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let mut lexer = Lexer::new(src, &emitter);
+	let tokens = lexer.tokenize_all();
+
+	assert_eq!(tokens.len(), 9);
+	assert_eq!(lexer.conditional_sections(), [ConditionalSection {
+		condition: ("debug".to_string(), 4..9),
+		span: 0..23
+	}]);
+	assert!(!lexer.has_error());
+	drop(lexer);
+	drop(emitter);
+	assert!(output.is_empty());
+}
+
+#[test]
+fn test_unterminated_conditional_section() {
+	let (ast, output) = parse!("#if debug\na = 1;");
+	error_count!(output, 1);
+	word_count!(output, "unterminated `#if`", 1);
+	assert_eq!(ast.len(), 1);
+}
+
+#[test]
+fn test_binop_info() {
+	let (add, add_associativity) = binop_info(&BinOp::Add).unwrap();
+	let (mul, _) = binop_info(&BinOp::Multiply).unwrap();
+	assert!(mul > add);
+	assert_eq!(add_associativity, Associativity::Left);
+	assert_eq!(binop_info(&BinOp::Not), None);
+}
+
+#[test]
+fn test_sizeof() {
+	let (ast, output) = parse!("a = sizeof(int); b = alignof(u8?);");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::SizeOf(Type::Named((name, _)), _) if name == "int"));
+	assert!(matches!(assign_value(&ast[1]), Expr::AlignOf(Type::Optional(_), _)));
+}
+
+#[test]
+fn test_sizeof_without_type() {
+	let (ast, output) = parse!("a = sizeof(); b = 1;");
+	error_count!(output, 1);
+	word_count!(output, "`sizeof` expects a type", 1);
+	assert_eq!(ast.len(), 2);
+}
+
+#[test]
+fn test_lint_builtin_shadowing() {
+	let src = "print: int = 0; f = (sizeof: int) { a: int = 1; } alignof = () {}";
+	assert!(lint!(src, |linter| linter).is_empty());
+
+	let output = lint!(src, |linter| linter.with_builtin_shadowing(true));
+	error_count!(output, 0);
+	word_count!(output, "`print` shadows the builtin", 1);
+	word_count!(output, "`sizeof` shadows the builtin", 1);
+	word_count!(output, "`alignof` shadows the builtin", 1);
+	word_count!(output, "shadows", 3);
+
+	let output = lint!("print = () {}", |linter| linter.with_builtin_shadowing(true));
+	word_count!(output, "`print` shadows the builtin", 1);
+}
+
+#[test]
+fn test_ignore_spans() {
+	use std::collections::hash_map::DefaultHasher;
+	use std::hash::{Hash, Hasher};
+
+	let (ast, output) = parse!("a = b * (c + 1);\nd =   b*(c+1);\ne = b * (c + 2);");
+	error_count!(output, 0);
+	let hash = |expr: &IgnoreSpans| {
+		let mut hasher = DefaultHasher::new();
+		expr.hash(&mut hasher);
+		hasher.finish()
+	};
+	let first = IgnoreSpans::new(assign_value(&ast[0]));
+	let second = IgnoreSpans::new(assign_value(&ast[1]));
+	let third = IgnoreSpans::new(assign_value(&ast[2]));
+
+	assert_ne!(assign_value(&ast[0]), assign_value(&ast[1]));
+	assert_eq!(first, second);
+	assert_eq!(hash(&first), hash(&second));
+	assert_ne!(first, third);
+}
+
+#[test]
+fn test_operator_starting_statement() {
+	let (ast, output) = parse!(r"f = () { * a; b = 1; } * c; d = 2;");
+	error_count!(output, 2);
+	word_count!(output, "expected an expression; `*` cannot start a statement", 2);
+	assert_eq!(ast.len(), 3);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => {
+			assert!(matches!(body[..], [Expr::Error, Expr::Assign {..}]));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(ast[2], Expr::Assign {..}));
+}
+
+#[test]
+fn test_nested_index() {
+	let (ast, output) = parse!(r"x = a[i][j + 1];");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Index {base, index, ..} => {
+			assert!(matches!(**index, Expr::Add(..)));
+			match &**base {
+				Expr::Index {base, index, ..} => {
+					assert!(matches!(&**base, Expr::Var((name, _)) if name == "a"));
+					assert!(matches!(&**index, Expr::Var((name, _)) if name == "i"));
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_index_and_field_access() {
+	let (ast, output) = parse!(r"x = a.b[i].c; a.b[i].c[j] = 1;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::FieldAccess {base, field} => {
+			assert_eq!(field.0, "c");
+			match &**base {
+				Expr::Index {base, ..} => {
+					assert!(matches!(&**base, Expr::FieldAccess {field: (name, _), ..} if name == "b"));
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	}
+	match &ast[1] {
+		Expr::Assign {target, ..} => assert!(matches!(**target, Expr::Index {..})),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_chained_field_access() {
+	let (ast, output) = parse!(r"x = a.b.c.d; y = f(1).b.c; z = (a + b).c;");
+	error_count!(output, 0);
+	let fields = |mut expr: &Expr| {
+		let mut fields = Vec::new();
+		while let Expr::FieldAccess {base, field} = expr {
+			fields.insert(0, field.value().clone());
+			expr = base;
+		}
+		(fields, expr.clone())
+	};
+	let (names, base) = fields(assign_value(&ast[0]));
+	assert_eq!(names, ["b", "c", "d"]);
+	assert!(matches!(base, Expr::Var((name, _)) if name == "a"));
+	let (names, base) = fields(assign_value(&ast[1]));
+	assert_eq!(names, ["b", "c"]);
+	assert!(matches!(base, Expr::Call {..}));
+	let (names, base) = fields(assign_value(&ast[2]));
+	assert_eq!(names, ["c"]);
+	assert!(matches!(base, Expr::Add(..)));
+}
+
+#[test]
+fn test_nested_field_assign_target() {
+	let (ast, output) = parse!(r"a.b.c = 1; f = () { a.b.c = 2; }");
+	error_count!(output, 0);
+	let check = |expr: &Expr| match expr {
+		Expr::Assign {target, value, ..} => {
+			assert!(matches!(**value, Expr::Num(_)));
+			match &**target {
+				Expr::FieldAccess {base, field} => {
+					assert_eq!(field.value(), "c");
+					match &**base {
+						Expr::FieldAccess {base, field} => {
+							assert_eq!(field.value(), "b");
+							assert!(matches!(&**base, Expr::Var((name, _)) if name == "a"));
+						}
+						e => panic!("{:?}", e)
+					}
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	};
+	check(&ast[0]);
+	match &ast[1] {
+		Expr::Function {body: Some(body), ..} => check(&body[0]),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_index_errors() {
+	test_error!(r"x = a[];", 1);
+	test_error!(r"x = a[i, j];", 1);
+	test_error!(r"x = foo[int, 1](2);", 1);
+}
+
+#[cfg(test)]
+const FUZZ_SOURCE: &str = r#"#[packed] Hello = struct : Base {
+	/// doc
+	hello: i32 : 3,
+	world: u8?
+}
+#if debug
+f = (a: u64, b: Hello) -> i32 {
+	x, y: i32 = (a = 1) + -b.c[0][i] * 3 as u8;
+	z = Hello {.hello = 1, .world = 'c'};
+	w = Hello {1, 2};
+	v = foo[int, Bar?](1, .x = "s", ..=3, 1..);
+	/* block */ ret sizeof(int) % alignof(u8);
+}
+#endif
+{ 1.5e3; 2_000; }
+"#;
+
+/// Parses `src` with `parse_for_ide` on another thread, failing if it doesn't finish.
+#[cfg(test)]
+fn fuzz_ide_parse(src: String) {
+	let (sender, receiver) = std::sync::mpsc::channel();
+	let thread_src = src.clone();
+	std::thread::spawn(move || {
+		sender.send(parser::parse_for_ide("fuzz", &thread_src)).unwrap();
+	});
+	let result = match receiver.recv_timeout(std::time::Duration::from_secs(5)) {
+		Ok(result) => result,
+		Err(e) => panic!("parsing {:?} did not finish: {:?}", src, e)
+	};
+	// Every top-level declaration consumes at least one token.
+	assert!(result.ast.len() <= src.len(), "{:?}", src);
+	assert_eq!(result.had_error, result.diagnostics.iter().any(|d| d.severity == EmitType::Error), "{:?}", src);
+}
+
+#[test]
+fn test_parse_for_ide_terminates() {
+	for end in 0..=FUZZ_SOURCE.len() {
+		fuzz_ide_parse(FUZZ_SOURCE[..end].to_string());
+		fuzz_ide_parse(FUZZ_SOURCE[end..].to_string());
+	}
+
+	let fragments = ["a", "1", "=", ":", ";", ",", "(", ")", "{", "}", "[", "]", ".", "..", "..=", "->", "+",
+		"-", "*", "?", "#", "#[", "struct", "ret", "as", "'c'", "\"s\"", "/*", "*/", "///", "\n", "1.5", "#if x",
+		"#endif", "sizeof", "&", "!", "+=", "i32", "Foo"];
+	let mut state = 0x2545F4914F6CDD1Du64;
+	let mut next_random = || {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		state as usize
+	};
+	for _ in 0..2000 {
+		let len = next_random() % 32;
+		let src: Vec<_> = (0..len).map(|_| fragments[next_random() % fragments.len()]).collect();
+		fuzz_ide_parse(src.join(" "));
+	}
+}
+
+#[test]
+fn test_parse_for_ide_reports_every_unexpected_character() {
+	let src = "\u{1}".repeat(30) + "a = 1;";
+	let unexpected = |result: &parser::ParseResult| result.diagnostics.iter()
+		.filter(|d| d.message.starts_with("unexpected character")).count();
+	assert_eq!(unexpected(&parser::parse("test", &src)), 20);
+	let result = parser::parse_for_ide("test", &src);
+	assert_eq!(unexpected(&result), 30);
+	assert_eq!(result.ast.len(), 1);
+}
+
+#[test]
+fn test_tail_expression() {
+	let (ast, output) = parse!(r"f = () { b = 2; a + b } g = () { a; } h = () { a }");
+	error_count!(output, 0);
+	let bodies: Vec<_> = ast.iter().map(|expr| match expr {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	}).collect();
+
+	assert!(matches!(&bodies[0][1], Expr::Tail {value} if matches!(**value, Expr::Add(..))));
+	assert!(matches!(bodies[1][..], [Expr::Var(_)]));
+	assert!(matches!(&bodies[2][..], [Expr::Tail {value}] if matches!(**value, Expr::Var(_))));
+}
+
+#[test]
+fn test_short_diagnostic_format() {
+	let src = "a = 1;\nb = 1 + ;";
+	let mut output = String::new();
+	let map = SourceMap::new("test", src);
+	let emitter = diagnostics::with_string(&map, &mut output).with_format(EmitFormat::Short);
+	let lexer = Lexer::new(src, &emitter);
+	let _ = Parser::new(lexer, &emitter).parse();
+	drop(emitter);
+
+	assert_eq!(output, "test:2:8: error: expected a primary expression after `+`\n");
+}
+
+#[test]
+fn test_construct_field_value_span() {
+	let src = r"a = Foo { .x = 1 + 2, .y = b.c };";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Construct {fields, ..} => {
+			assert_eq!(&src[fields[0].1.span().unwrap()], "1 + 2");
+			assert_eq!(&src[fields[1].1.span().unwrap()], "b.c");
+		}
+		e => panic!("{:?}", e)
+	}
+	assert_eq!(Expr::Error.span(), None);
+}
+
+#[test]
+fn test_expr_full_span() {
+	let src = r"a = -(b + c) * f(x, .y = 1); b = Foo { 1 }; c = (1, 2)[0]; d = ..=n; e = sizeof(int);";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	let value_src = |expr: &Expr| &src[assign_value(expr).span().unwrap()];
+	assert_eq!(value_src(&ast[0]), "-(b + c) * f(x, .y = 1)");
+	assert_eq!(value_src(&ast[1]), "Foo { 1 }");
+	assert_eq!(value_src(&ast[2]), "(1, 2)[0]");
+	assert_eq!(value_src(&ast[3]), "..=n");
+	assert_eq!(value_src(&ast[4]), "sizeof(int)");
+	match assign_value(&ast[0]) {
+		Expr::Mul(lhs, _) => assert_eq!(&src[lhs.span().unwrap()], "-(b + c)"),
+		e => panic!("{:?}", e)
+	}
+
+	let src = r"f = () {
+		ret x;
+		assert y, z;
+		if a { 1; } else { 2; }
+		'outer: { break 'outer 1; }
+		for ;; {}
+	}";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	let spans: Vec<_> = body.iter().map(|expr| &src[expr.span().unwrap()]).collect();
+	assert_eq!(spans, ["ret x", "assert y, z", "if a { 1; } else { 2; }", "'outer: { break 'outer 1; }", "for ;; {}"]);
+
+	let src = r"f = () {} Foo = struct { a: int } g = (x: int); a = 1;";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	let spans: Vec<_> = ast.iter().map(|expr| &src[expr.span().unwrap()]).collect();
+	assert_eq!(spans, ["f = () {}", "Foo = struct { a: int }", "g = (x: int)", "a = 1"]);
+}
+
+#[test]
+fn test_where_clause() {
+	let (ast, output) = parse!(r"f[T] = (x: T) where T: Num { } g[T, U] = (x: T, y: U) -> T where T: Num, U: Eq { ret x; }");
+	error_count!(output, 0);
+	let constraints: Vec<Vec<_>> = ast.iter().map(|expr| match expr {
+		Expr::Function {constraints, ..} => constraints.iter()
+			.map(|(param, bound)| (param.0.as_str(), bound.0.as_str())).collect(),
+		e => panic!("{:?}", e)
+	}).collect();
+	assert_eq!(constraints, [vec![("T", "Num")], vec![("T", "Num"), ("U", "Eq")]]);
+
+	match &ast[1] {
+		Expr::Function {type_params, ret_type: Some(ret_type), ..} => {
+			let params: Vec<_> = type_params.iter().map(|(param, _)| param.as_str()).collect();
+			assert_eq!(params, ["T", "U"]);
+			assert_eq!(type_name(ret_type), "T");
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_where_clause_missing_colon() {
+	let (ast, output) = parse!(r"f[T] = (x: T) where T { } a = 1;");
+	error_count!(output, 1);
+	word_count!(output, "expected ':'", 1);
+	assert_eq!(ast.len(), 2);
+	assert!(matches!(&ast[0], Expr::Function {body: Some(_), ..}));
+}
+
+#[test]
+fn test_generic_assign_target() {
+	test_error!(r"a[T, U] = 1;", 1);
+}
+
+#[test]
+fn test_spanned_helpers() {
+	let name = spanned("count".to_string(), 4..9);
+	assert_eq!(name.value(), "count");
+	assert_eq!(name.span(), 4..9);
+
+	let len = name.map(|name| name.len());
+	assert_eq!(len, (5, 4..9));
+}
+
+#[test]
+fn test_declaration_iterators() {
+	let (ast, output) = parse!(r"A = struct { x: i32 } f = () {} b = 1; B = struct {} g = () -> i32 { ret 1; } c: i32 = 2;");
+	error_count!(output, 0);
+	let names = |decls: Vec<&Expr>| decls.into_iter().map(|expr| match expr {
+		Expr::Function {name, ..} | Expr::Struct {name, ..} => name.0.clone(),
+		e => panic!("{:?}", e)
+	}).collect::<Vec<_>>();
+	assert_eq!(names(ast::functions(&ast).collect()), ["f", "g"]);
+	assert_eq!(names(ast::structs(&ast).collect()), ["A", "B"]);
+}
+
+#[test]
+fn test_lint_missing_fields() {
+	let decl = "Foo = struct { x: i32, y: i32, z: i32 = 0 }";
+	let output = lint!(&format!("{} a = Foo {{.x = 1, .y = 2}};", decl), |linter| linter.with_missing_fields(true));
+	error_count!(output, 0);
+
+	let output = lint!(&format!("{} a = Foo {{}}; b = Foo {{.y = 1}};", decl), |linter| linter.with_missing_fields(true));
+	error_count!(output, 2);
+	word_count!(output, "missing fields `x`, `y` in construct of `Foo`", 1);
+	word_count!(output, "missing field `x` in construct of `Foo`", 1);
+
+	let output = lint!("Bar = struct { x: i32 = 1, y: u8 = 'a' } a = Bar {};", |linter| linter.with_missing_fields(true));
+	error_count!(output, 0);
+
+	let output = lint!(&format!("{} a = Foo {{1, 2}}; b = Foo {{1}}; c = Foo {{1, 2, 3, 4}};", decl),
+		|linter| linter.with_missing_fields(true));
+	error_count!(output, 2);
+	word_count!(output, "missing field `y` in construct of `Foo`", 1);
+	word_count!(output, "too many values in construct of `Foo`, expected at most 3", 1);
+}
+
+#[test]
+fn test_lint_type_assignments() {
+	let output = lint!("x = int; y = i32;", |linter| linter.with_type_assignments(true));
+	word_count!(output, "warning", 2);
+	word_count!(output, "`int` is a type, not a value", 1);
+	word_count!(output, "did you mean `x: int`?", 1);
+	word_count!(output, "did you mean `y: i32`?", 1);
+
+	let output = lint!("Foo = struct {} f = (a: u8) { a = u8; b = Foo; c: int; c = Foo; }", |linter| linter.with_type_assignments(true));
+	word_count!(output, "warning", 1);
+	word_count!(output, "did you mean `b: Foo`?", 1);
+
+	let output = lint!("x = y; f = () { z = x; }", |linter| linter.with_type_assignments(true));
+	assert!(output.is_empty(), "{}", output);
+
+	// A declaration only covers the rest of its own block
+	let output = lint!("f = (c: bool) { if c { x: int; } x = int; for ;; { y = u8; } y: u8; 'a: { 'b: { z = i8; } z: i8; } }",
+		|linter| linter.with_type_assignments(true));
+	word_count!(output, "warning", 3);
+	word_count!(output, "did you mean `x: int`?", 1);
+	word_count!(output, "did you mean `y: u8`?", 1);
+	word_count!(output, "did you mean `z: i8`?", 1);
+}
+
+#[test]
+fn test_equality() {
+	let (ast, output) = parse!(r"a = b != c + 1; d = e == f;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Ne(lhs, rhs) => {
+			assert!(matches!(**lhs, Expr::Var(_)));
+			assert!(matches!(**rhs, Expr::Add(..)));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(assign_value(&ast[1]), Expr::Eq(..)));
+}
+
+#[test]
+fn test_comparisons() {
+	let (ast, output) = parse!(r"a = b < c; a = b > c; a = b <= c; a = b >= c * 2; a = x + 1 < y == z;");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Lt(..)));
+	assert!(matches!(assign_value(&ast[1]), Expr::Gt(..)));
+	assert!(matches!(assign_value(&ast[2]), Expr::Le(..)));
+	match assign_value(&ast[3]) {
+		Expr::Ge(_, rhs) => assert!(matches!(**rhs, Expr::Mul(..))),
+		e => panic!("{:?}", e)
+	}
+	// Comparisons share a precedence and group to the left.
+	match assign_value(&ast[4]) {
+		Expr::Eq(lhs, _) => match &**lhs {
+			Expr::Lt(lhs, _) => assert!(matches!(**lhs, Expr::Add(..))),
+			e => panic!("{:?}", e)
+		},
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_logical_and_bitwise_operators() {
+	let (ast, output) = parse!(r"a = b && c | d; a = b || c & d;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::And(_, rhs) => assert!(matches!(**rhs, Expr::BitOr(..))),
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[1]) {
+		Expr::Or(_, rhs) => assert!(matches!(**rhs, Expr::BitAnd(..))),
+		e => panic!("{:?}", e)
+	}
+
+	let src = "&& || & | &= |=";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let kinds: Vec<_> = Lexer::new(src, &emitter).tokenize_all().into_iter().map(|token| token.kind).collect();
+	assert_eq!(kinds, [TokenType::BinOp(BinOp::And), TokenType::BinOp(BinOp::Or), TokenType::BinOp(BinOp::BitAnd),
+		TokenType::BinOp(BinOp::BitOr), TokenType::BinOpEquals(BinOp::BitAnd), TokenType::BinOpEquals(BinOp::BitOr),
+		TokenType::Eof]);
+}
+
+#[test]
+fn test_logical_not() {
+	let (ast, output) = parse!(r"a = !!x; b = !-x; c = -y; d = !x && y;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Not(inner, _) => assert!(matches!(&**inner, Expr::Not(inner, _) if matches!(**inner, Expr::Var(_)))),
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[1]) {
+		Expr::Not(inner, _) => assert!(matches!(&**inner, Expr::Neg(inner, _) if matches!(**inner, Expr::Var(_)))),
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(assign_value(&ast[2]), Expr::Neg(inner, _) if matches!(**inner, Expr::Var(_))));
+	match assign_value(&ast[3]) {
+		Expr::And(lhs, _) => assert!(matches!(**lhs, Expr::Not(..))),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_logical_not_missing_operand() {
+	let output = test!("a = !");
+	error_count!(output, 2);
+	word_count!(output, "expected a primary expression after operator `!`", 1);
+	word_count!(output, "expected ';' but found eof", 1);
+
+	let output = test!("a = -!;");
+	error_count!(output, 1);
+	word_count!(output, "expected a primary expression after operator `!`", 1);
+}
+
+#[test]
+fn test_and_binds_tighter_than_or() {
+	let (ast, output) = parse!(r"x = a || b && c; y = a && b || c;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Or(lhs, rhs) => {
+			assert!(matches!(**lhs, Expr::Var(_)));
+			assert!(matches!(**rhs, Expr::And(..)));
+		}
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[1]) {
+		Expr::Or(lhs, rhs) => {
+			assert!(matches!(**lhs, Expr::And(..)));
+			assert!(matches!(**rhs, Expr::Var(_)));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_lex_comparisons() {
+	let src = "< > <= >= == != -> >>";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let kinds: Vec<_> = Lexer::new(src, &emitter).tokenize_all().into_iter().map(|token| token.kind).collect();
+	assert_eq!(kinds, [TokenType::BinOp(BinOp::Less), TokenType::BinOp(BinOp::Greater),
+		TokenType::BinOp(BinOp::LessEqual), TokenType::BinOp(BinOp::GreaterEqual), TokenType::BinOp(BinOp::Equal),
+		TokenType::BinOp(BinOp::NotEqual), TokenType::Arrow, TokenType::BinOp(BinOp::ShiftRight), TokenType::Eof]);
+}
+
+#[test]
+fn test_lint_float_equality() {
+	let output = lint!("b = a == 1.0; c = 2.5 != a; d = a == -1.0;", |linter| linter.with_float_equality(true));
+	word_count!(output, "warning", 3);
+	word_count!(output, "comparing floats with `==` is unreliable", 2);
+	word_count!(output, "comparing floats with `!=` is unreliable", 1);
+
+	let output = lint!("b = a == 1;", |linter| linter.with_float_equality(true));
+	assert!(output.is_empty(), "{}", output);
+}
+
+#[test]
+fn test_if_else() {
+	let (ast, output) = parse!(r"f = () { if a < b { x = 1; } if c { } else if d { y = 2; } else { z = 3; } v = if e { 1 } else { 2 }; }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	assert_eq!(body.len(), 3);
+	match &body[0] {
+		Expr::If {cond, then_body, else_body: None, ..} => {
+			assert!(matches!(**cond, Expr::Lt(..)));
+			assert_eq!(then_body.len(), 1);
+		}
+		e => panic!("{:?}", e)
+	}
+	match &body[1] {
+		Expr::If {then_body, else_body: Some(else_body), ..} => {
+			assert!(then_body.is_empty());
+			match &else_body[..] {
+				[Expr::If {cond, else_body: Some(last), ..}] => {
+					assert!(matches!(&**cond, Expr::Var((name, _)) if name == "d"));
+					assert!(matches!(assign_value(&last[0]), Expr::Num((3, _))));
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&body[2]) {
+		Expr::If {then_body, ..} => assert!(matches!(then_body[..], [Expr::Tail {..}])),
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_construct_nested_in_condition() {
+	for src in ["f = () { if g(Foo {.x = 1}) { } }", "f = () { if (Foo {.x = 1}).x { } }",
+		"f = () { if a[Foo {.x = 1}] { } for ; h(Foo {}); { } }"] {
+		let (ast, output) = parse!(src);
+		error_count!(output, 0);
+		let body = match &ast[0] {
+			Expr::Function {body: Some(body), ..} => body,
+			e => panic!("{:?}", e)
+		};
+		assert!(matches!(&body[0], Expr::If {then_body, ..} if then_body.is_empty()), "{:?}", body[0]);
+	}
+	// Directly in the condition the `{` still starts the body
+	let (ast, output) = parse!("f = () { if a { } }");
+	error_count!(output, 0);
+	assert!(matches!(&ast[0], Expr::Function {body: Some(body), ..} if matches!(&body[0], Expr::If {cond, ..} if matches!(**cond, Expr::Var(_)))));
+}
+
+#[test]
+fn test_if_missing_brace() {
+	let (ast, output) = parse!(r"f = () { if a x = 1; y = 2; }");
+	error_count!(output, 1);
+	word_count!(output, "expected '{' after the `if` condition", 1);
+	word_count!(output, "`if` here", 1);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => assert!(matches!(body.last(), Some(Expr::Assign {..}))),
+		e => panic!("{:?}", e)
+	}
+
+	let output = test!(r"f = () { if a {} else b; }");
+	error_count!(output, 1);
+	word_count!(output, "expected '{' after `else`", 1);
+}
+
+#[test]
+fn test_assert() {
+	let (ast, output) = parse!(r#"f = () { assert a < b; assert a, "oops"; }"#);
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	match &body[0] {
+		Expr::Assert {cond, message: None, ..} => assert!(matches!(**cond, Expr::Lt(..))),
+		e => panic!("{:?}", e)
+	}
+	match &body[1] {
+		Expr::Assert {cond, message: Some(message), ..} => {
+			assert!(matches!(**cond, Expr::Var(_)));
+			assert!(matches!(&**message, Expr::StringLiteral((text, _)) if text == "oops"));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_assert_as_value() {
+	let (ast, output) = parse!(r"f = () { x = assert a; y = 1; }");
+	error_count!(output, 1);
+	word_count!(output, "`assert` is a statement and can't be used as a value", 1);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => {
+			assert_eq!(body.len(), 2);
+			assert!(matches!(assign_value(&body[0]), Expr::Error));
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_extern_function() {
+	let (ast, output) = parse!(r"extern f = (x: int) -> int; g = () -> int;");
+	error_count!(output, 0);
+	assert!(matches!(&ast[0], Expr::Function {body: None, is_extern: true, ..}));
+	assert!(matches!(&ast[1], Expr::Function {body: None, is_extern: false, ..}));
+
+	let (ast, output) = parse!(r"extern f = (x: int) -> int { ret x; }");
+	error_count!(output, 1);
+	word_count!(output, "extern function `f` can't have a body", 1);
+	assert!(matches!(&ast[0], Expr::Function {is_extern: true, ..}));
+
+	let output = test!(r"extern S = struct {} extern");
+	error_count!(output, 2);
+	word_count!(output, "`extern` can only be applied to function declarations", 1);
+	word_count!(output, "expected a function declaration after `extern`", 1);
+}
+
+#[test]
+fn test_labeled_block() {
+	let (ast, output) = parse!(r"f = () { x = 'outer: { 'inner: { break 'outer 1; } break 'outer; }; 'done: { a; } }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	match assign_value(&body[0]) {
+		Expr::Block {label, body, ..} => {
+			assert_eq!(label.0, "outer");
+			assert!(matches!(&body[0], Expr::Block {label, body, ..}
+				if label.0 == "inner" && matches!(&body[0], Expr::Break {value: Some(_), ..})));
+			assert!(matches!(&body[1], Expr::Break {label, value: None, ..} if label.0 == "outer"));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(&body[1], Expr::Block {..}));
+}
+
+#[test]
+fn test_break_undefined_label() {
+	let output = test!(r"f = () { 'a: { break 'b 1; } break 'a; }");
+	error_count!(output, 2);
+	word_count!(output, "undefined label `'b`", 1);
+	word_count!(output, "undefined label `'a`", 1);
+}
+
+#[test]
+fn test_radix_literals() {
+	let src = "0x1F 0o17 0b1010 0xFF_FF 0";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let tokens = Lexer::new(src, &emitter).tokenize_all();
+	let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+	assert_eq!(kinds, [TokenType::Num(0x1F), TokenType::Num(0o17), TokenType::Num(0b1010), TokenType::Num(0xFFFF),
+		TokenType::Num(0), TokenType::Eof]);
+	assert_eq!(tokens[0].span, 0..4);
+	assert_eq!(tokens[3].span, 17..24);
+	drop(emitter);
+	assert!(output.is_empty(), "{}", output);
+}
+
+#[test]
+fn test_radix_literal_errors() {
+	let output = test!("a = 0x1G; b = 0b102; c = 0o; d = 0x1_;");
+	error_count!(output, 4);
+	word_count!(output, "invalid digit `G` in hexadecimal literal", 1);
+	word_count!(output, "invalid digit `2` in binary literal", 1);
+	word_count!(output, "expected octal digits after `0o`", 1);
+	word_count!(output, "underscores in a number must be between two digits", 1);
+	test_error!("a = 0x1_0000_0000_0000_0000;", 1);
+
+	let output = test!("a = 0xFFFFFFFFFFFFFFFFF; b = 0b11;");
+	error_count!(output, 1);
+	word_count!(output, "hexadecimal literal `0xFFFFFFFFFFFFFFFFF` is too large for a u64", 1);
+}
+
+#[test]
+fn test_integer_literal_overflow() {
+	let (ast, output) = parse!("a = 99999999999999999999999; b = 18446744073709551615;");
+	error_count!(output, 1);
+	word_count!(output, "integer literal `99999999999999999999999` is too large for a u64", 1);
+	assert!(matches!(assign_value(&ast[0]), Expr::Num((0, _))));
+	assert!(matches!(assign_value(&ast[1]), Expr::Num((u64::MAX, _))));
+}
+
+#[test]
+fn test_digit_separators() {
+	let src = "1_000_000 0xFF_FF 1_0.2_5 _x1";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let tokens = Lexer::new(src, &emitter).tokenize_all();
+	let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+	assert_eq!(kinds, [TokenType::Num(1_000_000), TokenType::Num(0xFFFF), TokenType::Float(10.25),
+		TokenType::Identifier("_x1".to_string()), TokenType::Eof]);
+	drop(emitter);
+	assert!(output.is_empty(), "{}", output);
+
+	let diagnostics = parser::parse("test", "a = _100;").diagnostics;
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].message, "underscores in a number must be between two digits");
+	assert_eq!(diagnostics[0].span, 4..5);
+}
+
+#[test]
+fn test_misplaced_digit_separators() {
+	for (src, underscore) in [("100_", 3..4), ("1__0", 1..2), ("1_.5", 1..2), ("1._5", 2..3)] {
+		let src = format!("a = {};", src);
+		let diagnostics = parser::parse("test", &src).diagnostics;
+		assert_eq!(diagnostics.len(), 1, "{}", src);
+		assert_eq!(diagnostics[0].message, "underscores in a number must be between two digits");
+		assert_eq!(diagnostics[0].span, underscore.start + 4..underscore.end + 4, "{}", src);
+	}
+}
+
+#[test]
+fn test_unexpected_characters() {
+	let (ast, output) = parse!("a\u{1} = 1;");
+	error_count!(output, 1);
+	word_count!(output, "unexpected character `\\u{1}`", 1);
+	assert!(matches!(&ast[0], Expr::Assign {target, ..} if matches!(&**target, Expr::Var((name, _)) if name == "a")));
+}
+
+#[test]
+fn test_lexer_error_limit() {
+	let src = format!("{}a = 1;", "\u{0} ".repeat(100));
+	let output = test!(&src);
+	error_count!(output, 20);
+
+	let mut output = String::new();
+	let map = SourceMap::new("test", &src);
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let mut lexer = Lexer::new(&src, &emitter).with_error_limit(3);
+	let tokens = lexer.tokenize_all();
+	assert!(lexer.has_error());
+	drop(lexer);
+	drop(emitter);
+	error_count!(output, 3);
+	assert_eq!(tokens.len(), 5);
+}
+
+#[test]
+fn test_byte_order_mark() {
+	let src = "\u{FEFF}a = 1;";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Assign {target, ..} => match &**target {
+			Expr::Var((name, span)) => {
+				assert_eq!(name, "a");
+				assert_eq!(&src[span.clone()], "a");
+			}
+			e => panic!("{:?}", e)
+		},
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_byte_order_mark_location() {
+	let src = "\u{FEFF}a = ;";
+	let output = test!(src);
+	let output = output.replace(diagnostics::color::RED, "").replace(diagnostics::color::CYAN, "")
+		.replace(diagnostics::color::BLUE, "").replace(diagnostics::color::RESET, "");
+	assert!(output.contains("test:1:5"), "{}", output);
+	assert!(output.contains("1 | a = ;\n"), "{}", output);
+	assert!(output.contains("  |     ^\n"), "{}", output);
+
+	let map = SourceMap::new("test", src);
+	assert_eq!(map.span_to_loc(0..0).column, 1);
+	assert_eq!(map.loc_to_offset(1, 1), Some(3));
+	assert_eq!(map.loc_to_offset(1, 5), Some(7));
+	assert_eq!(SourceMap::new("test", "\u{FEFF}").loc_to_offset(1, 1), Some(3));
+}
+
+#[test]
+fn test_float_literal() {
+	let (ast, output) = parse!(r"a = 3.25; b = -1e9; c = x.y + 2.5; d = 1..2;");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::FloatLiteral((Float(value), span)) if *value == 3.25 && *span == (4..8)));
+	assert!(matches!(assign_value(&ast[1]), Expr::Neg(inner, _) if matches!(**inner, Expr::FloatLiteral((Float(value), _)) if value == 1e9)));
+	match assign_value(&ast[2]) {
+		Expr::Add(lhs, rhs) => {
+			assert!(matches!(**lhs, Expr::FieldAccess {..}));
+			assert!(matches!(**rhs, Expr::FloatLiteral(_)));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(range_bounds(assign_value(&ast[3])), (Some(Expr::Num(_)), Some(Expr::Num(_)), false)));
+}
+
+#[test]
+fn test_shift_assignment() {
+	let (ast, output) = parse!(r"x <<= 2; x >>= 1; a = b << 1 + c;");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Assign {target, value, ..} => {
+			assert!(matches!(&**target, Expr::Var((name, _)) if name == "x"));
+			match &**value {
+				Expr::Shl(lhs, rhs) => {
+					assert!(matches!(&**lhs, Expr::Var((name, _)) if name == "x"));
+					assert!(matches!(**rhs, Expr::Num((2, _))));
+				}
+				e => panic!("{:?}", e)
+			}
+		}
+		e => panic!("{:?}", e)
+	}
+	match &ast[1] {
+		Expr::Assign {value, ..} => assert!(matches!(&**value, Expr::Shr(_, rhs) if matches!(**rhs, Expr::Num((1, _))))),
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[2]) {
+		Expr::Shl(_, rhs) => assert!(matches!(**rhs, Expr::Add(..))),
+		e => panic!("{:?}", e)
+	}
+
+	let src = "<< >> <<= >>= < <";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let tokens = Lexer::new(src, &emitter).tokenize_all();
+	let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+	assert_eq!(kinds, [TokenType::BinOp(BinOp::ShiftLeft), TokenType::BinOp(BinOp::ShiftRight),
+		TokenType::BinOpEquals(BinOp::ShiftLeft), TokenType::BinOpEquals(BinOp::ShiftRight),
+		TokenType::BinOp(BinOp::Less), TokenType::BinOp(BinOp::Less), TokenType::Eof]);
+	assert_eq!(tokens[2].span, 6..9);
+}
+
+#[test]
+fn test_for_loop() {
+	let (ast, output) = parse!(r"f = () {
+		for i: int = 0; i < 10; i += 1 { g(i); }
+		for ;; {}
+		for i = 0; ; i = i + 1 { g(i); }
+	}");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	match &body[0] {
+		Expr::For {init: Some(init), cond: Some(cond), step: Some(step), body, ..} => {
+			assert!(matches!(**init, Expr::VarDecl {..}));
+			assert!(matches!(**cond, Expr::Lt(..)));
+			assert!(matches!(&**step, Expr::Assign {value, ..} if matches!(**value, Expr::Add(..))));
+			assert_eq!(body.len(), 1);
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(&body[1], Expr::For {init: None, cond: None, step: None, body, ..} if body.is_empty()));
+	assert!(matches!(&body[2], Expr::For {init: Some(_), cond: None, step: Some(_), ..}));
+}
+
+#[test]
+fn test_for_clause_errors() {
+	// A missing second `;` is reported at the `{` and the body is still parsed
+	let (ast, output) = parse!("f = () { for i = 0; i < 10 { g(i); } h(); }");
+	error_count!(output, 1);
+	word_count!(output, "expected ';' but got '{'", 1);
+	match &ast[0] {
+		Expr::Function {body: Some(body), ..} => {
+			assert!(matches!(&body[0], Expr::For {cond: Some(_), step: None, body, ..} if body.len() == 1), "{:?}", body[0]);
+			assert!(matches!(&body[1], Expr::Call {..}), "{:?}", body[1]);
+		}
+		e => panic!("{:?}", e)
+	}
+}
+
+#[test]
+fn test_span_past_end() {
+	for (src, eof) in [("a = 1;\nb = é", (2, 6)), ("a\n", (1, 3)), ("", (1, 1))] {
+		let map = SourceMap::new("test", src);
+		let loc = map.span_to_loc(map.eoi_span());
+		assert_eq!((loc.line, loc.column), eof, "{:?}", src);
+		let loc = map.span_to_loc(src.len() + 10..src.len() + 20);
+		assert_eq!((loc.line, loc.column), eof, "{:?}", src);
+	}
+}
+
+#[test]
+fn test_loc_to_offset() {
+	let src = "a = 1;\nb = é + c;\n\td = 2;";
+	let map = SourceMap::new("test", src);
+	for offset in [0, 4, 6, 7, 11, 14, 18, 19, 20, src.len()] {
+		let loc = map.span_to_loc(offset..offset);
+		assert_eq!(map.loc_to_offset(loc.line, loc.column), Some(offset), "{}", offset);
+	}
+	assert_eq!(map.loc_to_offset(2, 6), Some(13));
+	assert_eq!(map.loc_to_offset(1, 0), None);
+	assert_eq!(map.loc_to_offset(0, 1), None);
+	assert_eq!(map.loc_to_offset(1, 9), None);
+	assert_eq!(map.loc_to_offset(4, 1), None);
+
+	let map = SourceMap::new("test", "");
+	assert_eq!(map.loc_to_offset(1, 1), Some(0));
+	assert_eq!(map.loc_to_offset(1, 2), None);
+}
+
+#[test]
+fn test_signed_number_literals() {
+	let (ast, output) = parse!(r"a = -5; b = +5; c = -+5; d = 1 - -5; e = 2 + +5;");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Neg(inner, span) if matches!(**inner, Expr::Num((5, _))) && *span == (4..6)));
+	assert!(matches!(assign_value(&ast[1]), Expr::Pos(inner, span) if matches!(**inner, Expr::Num((5, _))) && *span == (12..14)));
+	assert!(matches!(assign_value(&ast[2]), Expr::Neg(inner, span)
+		if matches!(&**inner, Expr::Pos(inner, _) if matches!(**inner, Expr::Num((5, _)))) && *span == (20..23)));
+	assert!(matches!(assign_value(&ast[3]), Expr::Sub(_, rhs) if matches!(**rhs, Expr::Neg(..))));
+	assert!(matches!(assign_value(&ast[4]), Expr::Add(_, rhs) if matches!(**rhs, Expr::Pos(..))));
+
+	let (ast, _) = parse!(r"a = +x;");
+	assert_eq!(assign_value(&ast[0]).span(), Some(4..6));
+	assert_eq!(ast_to_source(&ast), "a = +x;\n");
+
+	// Type suffixes aren't supported yet, but a sign never changes how the suffix is lexed.
+	let (ast, output) = parse!(r"a = -5i32; b = +5i32;");
+	error_count!(output, 2);
+	word_count!(output, "invalid suffix `i32` on number literal", 2);
+	assert!(matches!(assign_value(&ast[0]), Expr::Neg(inner, _) if matches!(**inner, Expr::Num((5, _)))));
+	assert!(matches!(assign_value(&ast[1]), Expr::Pos(inner, _) if matches!(**inner, Expr::Num((5, _)))));
+
+	let output = test!(r"a = +;");
+	word_count!(output, "expected a primary expression after operator `+`", 1);
+}
+
+#[test]
+fn test_pretty_print() {
+	let src = "Hello = struct : Base {
+	/// The greeting.
+	hello: i32 = 1,
+	flags: u8 : 3
+}
+#[inline]
+max[T] = (a: T, b: T) -> T where T: Ord {
+	if a > b { ret a; } else if a == b { ret b; }
+	c: (int, bool?) = (1, -(2 + 3) as bool);
+	'found: { break 'found Hello {.hello = f[int](1, .x = \"a\\n\"), .flags = !x}; }
+	for i: int = 0; i < 10; i = i + 1 {}
+	a = (1 + 2) * 3 - (4 - 5) << 1..=n;
+	b[0].c
+}
+extern g = () -> int;
+";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	let expected = "Hello = struct : Base {
+	/// The greeting.
+	hello: i32 = 1,
+	flags: u8 : 3
+}
+#[inline]
+max[T] = (a: T, b: T) -> T where T: Ord {
+	if a > b {
+		ret a;
+	} else if a == b {
+		ret b;
+	}
+	c: (int, bool?) = (1, -(2 + 3) as bool);
+	'found: {
+		break 'found Hello {.hello = f[int](1, .x = \"a\\n\"), .flags = !x};
+	}
+	for i: int = 0; i < 10; i = i + 1 {}
+	a = (1 + 2) * 3 - (4 - 5) << 1..=n;
+	b[0].c
+}
+extern g = () -> int;
+";
+	let pretty = ast_to_source(&ast);
+	assert_eq!(pretty, expected);
+
+	let (reparsed, output) = parse!(&pretty);
+	error_count!(output, 0);
+	let erase = |ast: &[Expr]| ast.iter().map(IgnoreSpans::new).collect::<Vec<_>>();
+	assert_eq!(erase(&reparsed), erase(&ast));
+
+	let (ast, _) = parse!(r"a = -(1 - 2) - (3 - 4) * x.y;");
+	assert_eq!(assign_value(&ast[0]).to_string(), "-(1 - 2) - (3 - 4) * x.y");
+}
+
+#[test]
+fn test_import_alias() {
+	let (ast, output) = parse!(r#"import "m" as m; import "other";"#);
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Import {path, alias: Some(alias), span} => {
+			assert_eq!(path.0, "m");
+			assert_eq!(alias.0, "m");
+			assert_eq!(*span, 0..15);
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(&ast[1], Expr::Import {path, alias: None, ..} if path.0 == "other"));
+
+	let (ast, output) = parse!(r#"import "m" as; a = 1;"#);
+	error_count!(output, 1);
+	word_count!(output, "expected an alias name after `as` but got ';'", 1);
+	assert!(matches!(&ast[..], [Expr::Import {alias: None, ..}, Expr::Assign {..}]));
+
+	test_error!(r"import m;", 1);
+}
+
+#[test]
+fn test_compound_assignment() {
+	let (ast, output) = parse!(r"f = () { a += 1; b -= 2; c *= 3; d /= 4; e %= 5; x.y |= 6; z[0] &= 7; }");
+	error_count!(output, 0);
+	let body = match &ast[0] {
+		Expr::Function {body: Some(body), ..} => body,
+		e => panic!("{:?}", e)
+	};
+	let values: Vec<_> = body.iter().map(|expr| match expr {
+		Expr::Assign {target, value, ..} => {
+			match &**value {
+				Expr::Add(lhs, _) | Expr::Sub(lhs, _) | Expr::Mul(lhs, _) | Expr::Div(lhs, _) | Expr::Mod(lhs, _)
+				| Expr::BitOr(lhs, _) | Expr::BitAnd(lhs, _) => assert_eq!(lhs, target),
+				e => panic!("{:?}", e)
+			}
+			value.to_string()
+		}
+		e => panic!("{:?}", e)
+	}).collect();
+	assert_eq!(values, ["a + 1", "b - 2", "c * 3", "d / 4", "e % 5", "x.y | 6", "z[0] & 7"]);
+
+	let src = r"f = () { 1 += 2; g() -= 1; }";
+	let diagnostics = parser::parse("test", src).diagnostics;
+	assert_eq!(diagnostics.len(), 2);
+	for (diagnostic, op) in diagnostics.iter().zip(["+=", "-="]) {
+		assert_eq!(diagnostic.message, "invalid assignment target, expected a variable or field");
+		assert_eq!(&src[diagnostic.span.clone()], op);
+	}
+}
+
+#[test]
+fn test_backslash_in_identifier() {
+	let src = r"a = fo\no; b = \x;";
+	let diagnostics = parser::parse("test", src).diagnostics;
+	assert_eq!(diagnostics.len(), 2);
+	for (diagnostic, span) in diagnostics.iter().zip([6..7, 15..16]) {
+		assert_eq!(diagnostic.message,
+			"backslash in identifier, escape sequences are only allowed in string and character literals");
+		assert_eq!(diagnostic.span, span);
+	}
+
+	let (ast, _) = parse!(r"a = fo\no;");
+	assert!(matches!(assign_value(&ast[0]), Expr::Var((name, _)) if name == r"fo\no"));
+	let (_, output) = parse!(r#"a = "fo\no"; b = '\t';"#);
+	error_count!(output, 0);
+}
+
+#[test]
+fn test_array_literal() {
+	let src = r"a = [1, b + 2, [3],]; c = [][0]; d = [x, y][i][j]; e = [];";
+	let (ast, output) = parse!(src);
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Array(values, span) => {
+			assert_eq!(values.len(), 3);
+			assert!(matches!(&values[2], Expr::Array(inner, _) if inner.len() == 1));
+			assert_eq!(&src[span.clone()], "[1, b + 2, [3],]");
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(assign_value(&ast[1]), Expr::Index {base, ..} if matches!(&**base, Expr::Array(values, _) if values.is_empty())));
+	match assign_value(&ast[2]) {
+		Expr::Index {base, index, span} => {
+			assert!(matches!(&**base, Expr::Index {base, ..} if matches!(**base, Expr::Array(..))));
+			assert!(matches!(**index, Expr::Var(_)));
+			assert_eq!(&src[span.clone()], "[x, y][i][j]");
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(matches!(assign_value(&ast[3]), Expr::Array(values, _) if values.is_empty()));
+
+	let src = "a = [1, 2";
+	let diagnostics = parser::parse("test", src).diagnostics;
+	assert_eq!(diagnostics.len(), 2);
+	assert_eq!(diagnostics[0].message, "unclosed `[` in array literal");
+	assert_eq!(diagnostics[0].secondary[0].0, 4..5);
+	test_error!(r"a = [1 2];", 1);
+
+	let diagnostics = parser::parse("test", "a = [1, 2; b = 1;").diagnostics;
+	assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+	assert_eq!(diagnostics[0].message, "expected ',' or ']' but got ';'");
+	assert_eq!(diagnostics[0].secondary, [(4..5, "opened here".to_string())]);
+
+	// Recovery skips nested brackets up to the literal's own `]`
+	let (ast, output) = parse!("a = [1 2, [3]]; b = 1;");
+	error_count!(output, 1);
+	word_count!(output, "expected ',' or ']' but got a number", 1);
+	assert_eq!(ast.len(), 2);
+}
+
+#[test]
+fn test_lex_ranges() {
+	let src = "/// Doc\nFoo = struct { a: int } // comment\nf = () { x += \"s\"; ret 'l: { 1.5 }; }\n";
+	let map = SourceMap::new("test", src);
+	let mut output = String::new();
+	let emitter = diagnostics::with_string(&map, &mut output);
+	let ranges = Lexer::new(src, &emitter).lex_ranges();
+	assert_eq!(ranges[..4].iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+		[TokenKind::DocComment, TokenKind::Identifier, TokenKind::Equals, TokenKind::Struct]);
+	assert!(ranges.iter().any(|(kind, span)| *kind == TokenKind::StringLiteral && &src[span.clone()] == "\"s\""));
+	assert!(ranges.iter().any(|(kind, _)| *kind == TokenKind::BinOpEquals));
+
+	// Everything between the tokens is whitespace or a comment.
+	let mut end = 0;
+	for (_, span) in &ranges {
+		assert!(span.start >= end && span.end > span.start);
+		let gap = src[end..span.start].trim();
+		assert!(gap.is_empty() || gap.starts_with("//"), "{:?}", gap);
+		end = span.end;
+	}
+	assert!(src[end..].trim().is_empty());
+}
+
+#[test]
+fn test_logical_operator_precedence() {
+	let (ast, output) = parse!(r"x = a || b && c; y = a < b && c > d || e;");
+	error_count!(output, 0);
+	match assign_value(&ast[0]) {
+		Expr::Or(lhs, rhs) => {
+			assert!(matches!(**lhs, Expr::Var(_)));
+			assert!(matches!(**rhs, Expr::And(..)));
+		}
+		e => panic!("{:?}", e)
+	}
+	match assign_value(&ast[1]) {
+		Expr::Or(lhs, rhs) => {
+			assert!(matches!(&**lhs, Expr::And(lhs, rhs) if matches!(**lhs, Expr::Lt(..)) && matches!(**rhs, Expr::Gt(..))));
+			assert!(matches!(**rhs, Expr::Var(_)));
+		}
+		e => panic!("{:?}", e)
+	}
+	assert!(binop_info(&BinOp::And).unwrap().0 > binop_info(&BinOp::Or).unwrap().0);
+	assert!(binop_info(&BinOp::Less).unwrap().0 > binop_info(&BinOp::And).unwrap().0);
+}
+
+#[test]
+fn test_const_array_length() {
+	let (ast, output) = parse!("const N = 4; f = () { a: [int; N]; b: [[u8; 2]; N]?; }");
+	error_count!(output, 0);
+	match &ast[0] {
+		Expr::Const {name, value, span} => {
+			assert_eq!(name.0, "N");
+			assert!(matches!(**value, Expr::Num((4, _))), "{:?}", value);
+			assert_eq!(*span, 0..11);
+		}
+		e => panic!("{:?}", e)
+	}
+	match &ast[1] {
+		Expr::Function {body: Some(body), ..} => match &body[0] {
+			Expr::VarDecl {r#type: Type::Array(element, ast::ArrayLen::Const((len, _)), span), ..} => {
+				assert!(matches!(&**element, Type::Named((name, _)) if name == "int"), "{:?}", element);
+				assert_eq!(len, "N");
+				assert_eq!(*span, 25..33);
+			}
+			e => panic!("{:?}", e)
+		},
+		e => panic!("{:?}", e)
+	}
+	assert_eq!(ast_to_source(&ast), "const N = 4;\nf = () {\n\ta: [int; N];\n\tb: [[u8; 2]; N]?;\n}\n");
+
+	let output = lint!("const N = 4; const M = N; f = (a: [int; M]) { b: [int; N]; c: [int; 3]; }", |linter| linter.with_array_lengths(true));
+	error_count!(output, 0);
+	// Top-level constants can be used before their declaration
+	let output = lint!("f = () { a: [int; N]; } const N = 4;", |linter| linter.with_array_lengths(true));
+	error_count!(output, 0);
+}
+
+#[test]
+fn test_non_const_array_length() {
+	let output = lint!("f = () { n: int = 4; a: [int; n]; }", |linter| linter.with_array_lengths(true));
+	error_count!(output, 1);
+	word_count!(output, "array length `n` is a variable, not a constant", 1);
+	word_count!(output, "`n` declared here", 1);
+
+	let output = lint!("f = (n: int) { a: [int; n]; }", |linter| linter.with_array_lengths(true));
+	word_count!(output, "array length `n` is a variable, not a constant", 1);
+
+	let output = lint!("const S = \"four\"; a: [int; S]; b: [int; M];", |linter| linter.with_array_lengths(true));
+	error_count!(output, 2);
+	word_count!(output, "array length `S` is not an integer constant", 1);
+	word_count!(output, "cannot find constant `M` for the array length", 1);
+
+	let (_, output) = parse!("a: [int; 1 + 2];");
+	error_count!(output, 1);
+
+	let output = lint!("const N = 2 * 4; const M = -1; const K = (N + 1) << 2; a: [int; N]; b: [int; M]; c: [int; K];",
+		|linter| linter.with_array_lengths(true));
+	error_count!(output, 0);
+
+	let path = std::env::temp_dir().join(format!("lang-array-length-{}.lang", std::process::id()));
+	std::fs::write(&path, "f = () { n: int = 4; a: [int; n]; }\n").unwrap();
+	let code = crate::run(&[path.to_str().unwrap().to_string()], &mut Vec::new());
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(code, 1);
+}
+
+#[test]
+fn test_array_type_argument() {
+	let (ast, output) = parse!("a = f[[int; 3]](y); b = g[[[u8; 2]; N], int](y);");
+	error_count!(output, 0);
+	assert!(matches!(assign_value(&ast[0]), Expr::Call {type_args, ..} if matches!(type_args[..], [Type::Array(..)])),
+		"{:?}", ast[0]);
+	assert!(matches!(assign_value(&ast[1]), Expr::Call {type_args, ..} if matches!(type_args[..], [Type::Array(..), Type::Named(_)])),
+		"{:?}", ast[1]);
+}
+
+#[test]
+fn test_unclosed_delimiter_points_at_opener() {
+	let diagnostics = parser::parse("test", "f = () {\n\ta = 1;\n").diagnostics;
+	assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+	assert_eq!(diagnostics[0].message, "expected '}' but found eof");
+	assert_eq!(diagnostics[0].secondary, [(7..8, "opened here".to_string())]);
+
+	let diagnostics = parser::parse("test", "f = () { if a { b = 1; }").diagnostics;
+	assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+	assert_eq!(diagnostics[0].secondary, [(7..8, "opened here".to_string())]);
+
+	let diagnostics = parser::parse("test", "a = (1 + 2;").diagnostics;
+	assert_eq!(diagnostics[0].message, "expected ')' but got ';'");
+	assert_eq!(diagnostics[0].secondary, [(4..5, "opened here".to_string())]);
+
+	let (_, output) = parse!("f = () {");
+	word_count!(output, "opened here", 1);
+}
+
+#[test]
+fn test_error_count() {
+	let mut output = String::new();
+	let map = SourceMap::new("test", "a = ;");
+	let emitter = diagnostics::with_string(&map, &mut output).with_color(false);
+	emitter.finish();
+	emitter.error().with_label("first").with_span(0..1).emit();
+	emitter.warning().with_label("not counted").with_span(0..1).emit();
+	emitter.take_diagnostics();
+	emitter.error().with_label("second").with_span(4..5).emit();
+	assert_eq!(emitter.error_count(), 2);
+	emitter.finish();
+	assert!(output.ends_with("\nerror: aborting due to 2 previous errors\n"), "{}", output);
+	word_count!(output, "aborting", 1);
+
+	let path = std::env::temp_dir().join(format!("lang-exit-code-{}.lang", std::process::id()));
+	std::fs::write(&path, "f = () { a = ; }\n").unwrap();
+	let code = crate::run(&[path.to_str().unwrap().to_string()], &mut Vec::new());
+	std::fs::remove_file(&path).unwrap();
+	assert_eq!(code, 1);
+}
+
+#[test]
+fn test_missing_operand_between_operators() {
+	let diagnostics = parser::parse("test", "f = () { c = a * / b; }").diagnostics;
+	assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+	assert_eq!(diagnostics[0].message, "missing operand between `*` and `/`");
+	assert_eq!(diagnostics[0].span, 16..17);
+
+	let (ast, output) = parse!("c = a && || b;");
+	error_count!(output, 1);
+	word_count!(output, "missing operand between `&&` and `||`", 1);
+	match assign_value(&ast[0]) {
+		Expr::Or(lhs, rhs) => {
+			assert!(matches!(&**lhs, Expr::And(_, rhs) if matches!(**rhs, Expr::Error)), "{:?}", lhs);
+			assert!(matches!(&**rhs, Expr::Var((name, _)) if name == "b"), "{:?}", rhs);
+		}
+		e => panic!("{:?}", e)
+	}
+
+	// A sign after an operator is a unary operator, not a missing operand
+	let (_, output) = parse!("c = a * -b;");
+	error_count!(output, 0);
+}
+
+#[test]
+fn test_json_emitter() {
+	let src = "a = 1;\nb = (1 + 2;";
+	let map = SourceMap::new("test.lang", src);
+	let mut output = Vec::new();
+	let emitter = DiagnosticEmitter::json(&map, &mut output);
+	let lexer = Lexer::new(src, &emitter);
+	let _ = Parser::new(lexer, &emitter).parse();
+	emitter.finish();
+	drop(emitter);
+
+	let output = String::from_utf8(output).unwrap();
+	assert_eq!(output, "{\"severity\":\"error\",\"message\":\"expected ')' but got ';'\",\"file\":\"test.lang\",\
+		\"line\":2,\"column\":11,\"span\":[17,18],\"secondary\":[{\"message\":\"opened here\",\"line\":2,\
+		\"column\":5,\"span\":[11,12]}]}\n");
+}
+
+#[test]
+fn test_semicolon_after_declaration() {
+	for src in ["f = () {}", "f = () {};", "S = struct { x: int };", "f = () { g = () {}; S = struct {}; }"] {
+		let (ast, output) = parse!(src);
+		error_count!(output, 0);
+		assert_eq!(ast.len(), 1, "{:?}", ast);
+	}
+
+	let diagnostics = parser::parse("test", "f = () {};;\nS = struct {};;").diagnostics;
+	assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+	assert_eq!(diagnostics[0].span, 10..11);
+	assert_eq!(diagnostics[1].span, 26..27);
+	assert_eq!(diagnostics[0].message, "unexpected token `;`, expected a primary expression");
+}
+
+#[test]
+fn test_span_to_loc_large_file() {
+	let src: String = (0..10_000).map(|i| format!("a{} = {};\n", i, i)).collect();
+	let map = SourceMap::new("test", &src);
+	let last_line = "a9999 = 9999;\n";
+	let loc = map.span_to_loc(src.len() - 2..src.len() - 1);
+	assert_eq!((loc.line, loc.column), (10_000, last_line.len() - 1));
+	let loc = map.span_to_loc(map.eoi_span());
+	assert_eq!((loc.line, loc.column), (10_000, last_line.len() + 1));
+
+	for line in [1, 2, 5_000, 9_999] {
+		let offset = map.loc_to_offset(line, 3).unwrap();
+		let loc = map.span_to_loc(offset..offset + 1);
+		assert_eq!((loc.line, loc.column), (line, 3));
+	}
+}